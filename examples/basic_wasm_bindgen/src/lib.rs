@@ -5,7 +5,12 @@ use xtra::spawn::WasmBindgen;
 
 struct Echoer;
 
-impl Actor for Echoer {}
+#[async_trait::async_trait]
+impl Actor for Echoer {
+    type Stop = ();
+
+    async fn stopped(self) -> Self::Stop {}
+}
 
 struct Echo(String);
 impl Message for Echo {
@@ -1,13 +1,54 @@
-use std::time::{Duration, Instant};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
 
 use xtra::prelude::*;
 use xtra::spawn::Tokio;
+use xtra::NativeHandler;
+
+/// Counts heap allocations, so that each benchmark can report how many it makes per message.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Prints the average time and number of allocations per message of a benchmark which started at
+/// `start`, when `allocations` had been made.
+fn report(name: &str, count: usize, start: Instant, allocations: usize) {
+    let duration = Instant::now() - start;
+    let average_ns = duration.as_nanos() / count as u128; // <120-170ns on my machine
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+    println!(
+        "{} avg time of processing: {}ns, allocations per message: {:.2}",
+        name,
+        average_ns,
+        allocations as f64 / count as f64
+    );
+}
 
 struct Counter {
     count: usize,
 }
 
-impl Actor for Counter {}
+#[async_trait::async_trait]
+impl Actor for Counter {
+    type Stop = ();
+
+    async fn stopped(self) -> Self::Stop {}
+}
 
 struct Increment;
 
@@ -36,8 +77,8 @@ impl Handler<Increment> for Counter {
 
 #[async_trait::async_trait]
 impl Handler<IncrementWithData> for Counter {
-    async fn handle(&mut self, _: IncrementWithData, _ctx: &mut Context<Self>) {
-        self.count += 1;
+    async fn handle(&mut self, msg: IncrementWithData, _ctx: &mut Context<Self>) {
+        self.count += msg.0;
     }
 }
 
@@ -50,47 +91,52 @@ impl Handler<GetCount> for Counter {
     }
 }
 
-struct SendTimer {
-    time: Duration,
+/// The same as `Counter`, but its handlers are native `async fn`s, which do not box their futures.
+/// Only the handlers differ, as the lifecycle methods of `Actor` are called a few times per actor
+/// rather than once per message.
+struct NativeCounter {
+    count: usize,
 }
 
-impl Actor for SendTimer {}
-
-struct GetTime;
+#[async_trait::async_trait]
+impl Actor for NativeCounter {
+    type Stop = ();
 
-impl Message for GetTime {
-    type Result = Duration;
+    async fn stopped(self) -> Self::Stop {}
 }
 
-#[async_trait::async_trait]
-impl Handler<GetTime> for SendTimer {
-    async fn handle(&mut self, _time: GetTime, _ctx: &mut Context<Self>) -> Duration {
-        self.time
+impl NativeHandler<Increment> for NativeCounter {
+    async fn handle(&mut self, _: Increment, _ctx: &mut Context<Self>) {
+        self.count += 1;
     }
 }
 
-struct ReturnTimer;
-
-impl Actor for ReturnTimer {}
-
-struct TimeReturn;
-
-impl Message for TimeReturn {
-    type Result = Instant;
+impl NativeHandler<IncrementWithData> for NativeCounter {
+    async fn handle(&mut self, msg: IncrementWithData, _ctx: &mut Context<Self>) {
+        self.count += msg.0;
+    }
 }
 
-#[async_trait::async_trait]
-impl Handler<TimeReturn> for ReturnTimer {
-    async fn handle(&mut self, _time: TimeReturn, _ctx: &mut Context<Self>) -> Instant {
-        Instant::now()
+impl NativeHandler<GetCount> for NativeCounter {
+    async fn handle(&mut self, _: GetCount, _ctx: &mut Context<Self>) -> usize {
+        let count = self.count;
+        self.count = 0;
+        count
     }
 }
 
 const COUNT: usize = 50_000_000; // May take a while on some machines
 
-async fn do_address_benchmark(name: &str, f: fn(&Address<Counter>) -> ()) {
-    let addr = Counter { count: 0 }.create(None).spawn(&mut Tokio::Global);
+/// Messages sent with `send` wait for their response one at a time, so fewer are sent.
+const SEND_COUNT: usize = 5_000_000;
 
+async fn do_address_benchmark<A>(name: &str, actor: A, f: fn(&Address<A>))
+where
+    A: NativeHandler<GetCount> + Actor<Stop = ()>,
+{
+    let addr = actor.create(None).spawn(&mut Tokio::Global);
+
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
     let start = Instant::now();
 
     // rounding overflow
@@ -102,14 +148,35 @@ async fn do_address_benchmark(name: &str, f: fn(&Address<Counter>) -> ()) {
     // future tokio reschedule time because of the .await
     let total_count = addr.send(GetCount).await.unwrap();
 
-    let duration = Instant::now() - start;
-    let average_ns = duration.as_nanos() / COUNT as u128; // <120-170ns on my machine
-    println!("{} avg time of processing: {}ns", name, average_ns);
+    report(name, COUNT, start, allocations);
     assert_eq!(total_count, COUNT, "total_count should equal COUNT!");
 }
 
-async fn do_parallel_address_benchmark(name: &str, workers: usize, f: fn(&Address<Counter>) -> ()) {
+async fn do_address_send_benchmark<A, M>(name: &str, actor: A, message: fn() -> M)
+where
+    A: NativeHandler<M> + NativeHandler<GetCount> + Actor<Stop = ()>,
+    M: Message<Result = ()>,
+{
+    let addr = actor.create(None).spawn(&mut Tokio::Global);
+
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for _ in 0..SEND_COUNT {
+        addr.send(message()).await.unwrap();
+    }
+
+    let total_count = addr.send(GetCount).await.unwrap();
+
+    report(name, SEND_COUNT, start, allocations);
+    assert_eq!(
+        total_count, SEND_COUNT,
+        "total_count should equal SEND_COUNT!"
+    );
+}
+
+async fn do_parallel_address_benchmark(name: &str, workers: usize, f: fn(&Address<Counter>)) {
     let (addr, mut ctx) = Context::new(None);
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
     let start = Instant::now();
     for _ in 0..workers {
         tokio::spawn(ctx.attach(Counter { count: 0 }));
@@ -121,11 +188,9 @@ async fn do_parallel_address_benchmark(name: &str, workers: usize, f: fn(&Addres
 
     // awaiting on GetCount will make sure all previous messages are processed first BUT introduces
     // future tokio reschedule time because of the .await
-    let _ = addr.send(GetCount).await.unwrap();
+    addr.send(GetCount).await.unwrap();
 
-    let duration = Instant::now() - start;
-    let average_ns = duration.as_nanos() / COUNT as u128; // <120-170ns on my machine
-    println!("{} avg time of processing: {}ns", name, average_ns);
+    report(name, COUNT, start, allocations);
 }
 
 async fn do_channel_benchmark<M: Message, F: Fn(&dyn MessageChannel<M>)>(name: &str, f: F)
@@ -135,6 +200,7 @@ where
     let addr = Counter { count: 0 }.create(None).spawn(&mut Tokio::Global);
     let chan = &addr as &dyn MessageChannel<M>;
 
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
     let start = Instant::now();
     for _ in 0..COUNT {
         f(chan);
@@ -144,31 +210,104 @@ where
     // future tokio reschedule time because of the .await
     let total_count = addr.send::<GetCount>(GetCount).await.unwrap();
 
-    let duration = Instant::now() - start;
-    let average_ns = duration.as_nanos() / total_count as u128; // <120-170ns on my machine
-    println!("{} avg time of processing: {}ns", name, average_ns);
+    report(name, total_count, start, allocations);
     assert_eq!(total_count, COUNT, "total_count should equal COUNT!");
 }
 
+async fn do_channel_send_benchmark<M>(name: &str, message: fn() -> M)
+where
+    M: Message<Result = ()>,
+    Counter: Handler<M>,
+{
+    let addr = Counter { count: 0 }.create(None).spawn(&mut Tokio::Global);
+    let chan = &addr as &dyn MessageChannel<M>;
+
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for _ in 0..SEND_COUNT {
+        chan.send(message()).await.unwrap();
+    }
+
+    let total_count = addr.send::<GetCount>(GetCount).await.unwrap();
+
+    report(name, SEND_COUNT, start, allocations);
+    assert_eq!(
+        total_count, SEND_COUNT,
+        "total_count should equal SEND_COUNT!"
+    );
+}
+
 #[tokio::main]
 async fn main() {
-    do_address_benchmark("address do_send (ZST message)", |addr| {
-        let _ = addr.do_send(Increment);
-    })
+    do_address_benchmark(
+        "address do_send (ZST message)",
+        Counter { count: 0 },
+        |addr| {
+            let _ = addr.do_send(Increment);
+        },
+    )
+    .await;
+
+    do_address_benchmark(
+        "address do_send (8-byte message)",
+        Counter { count: 0 },
+        |addr| {
+            let _ = addr.do_send(IncrementWithData(1));
+        },
+    )
     .await;
 
-    do_address_benchmark("address do_send (8-byte message)", |addr| {
-        let _ = addr.do_send(IncrementWithData(0));
+    do_address_benchmark(
+        "native address do_send (ZST message)",
+        NativeCounter { count: 0 },
+        |addr| {
+            let _ = addr.do_send(Increment);
+        },
+    )
+    .await;
+
+    do_address_benchmark(
+        "native address do_send (8-byte message)",
+        NativeCounter { count: 0 },
+        |addr| {
+            let _ = addr.do_send(IncrementWithData(1));
+        },
+    )
+    .await;
+
+    do_address_send_benchmark("address send (ZST message)", Counter { count: 0 }, || {
+        Increment
     })
     .await;
 
+    do_address_send_benchmark(
+        "address send (8-byte message)",
+        Counter { count: 0 },
+        || IncrementWithData(1),
+    )
+    .await;
+
+    do_address_send_benchmark(
+        "native address send (ZST message)",
+        NativeCounter { count: 0 },
+        || Increment,
+    )
+    .await;
+
+    do_address_send_benchmark(
+        "native address send (8-byte message)",
+        NativeCounter { count: 0 },
+        || IncrementWithData(1),
+    )
+    .await;
+
     do_parallel_address_benchmark("address do_send 2 workers (ZST message)", 2, |addr| {
         let _ = addr.do_send(Increment);
     })
     .await;
 
     do_parallel_address_benchmark("address do_send 2 workers (8-byte message)", 2, |addr| {
-        let _ = addr.do_send(IncrementWithData(0));
+        let _ = addr.do_send(IncrementWithData(1));
     })
     .await;
 
@@ -178,17 +317,11 @@ async fn main() {
     .await;
 
     do_channel_benchmark("channel do_send (8-byte message)", |chan| {
-        let _ = chan.do_send(IncrementWithData(0));
+        let _ = chan.do_send(IncrementWithData(1));
     })
     .await;
 
-    do_channel_benchmark("channel send (ZST message)", |chan| {
-        let _ = chan.send(Increment);
-    })
-    .await;
+    do_channel_send_benchmark("channel send (ZST message)", || Increment).await;
 
-    do_channel_benchmark("channel send (8-byte message)", |chan| {
-        let _ = chan.send(IncrementWithData(0));
-    })
-    .await;
+    do_channel_send_benchmark("channel send (8-byte message)", || IncrementWithData(1)).await;
 }
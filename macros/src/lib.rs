@@ -0,0 +1,613 @@
+//! Procedural macros for [xtra](https://docs.rs/xtra). These are re-exported by xtra when its
+//! `macros` feature is enabled, and should be used through it rather than by depending on this
+//! crate directly.
+
+#![deny(unsafe_code, missing_docs)]
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::parse::{Parse, ParseStream};
+use syn::visit_mut::{self, VisitMut};
+use syn::{
+    parse_macro_input, parse_quote, DeriveInput, Error, Expr, ExprLit, FnArg, Ident, ImplItem,
+    ImplItemFn, ItemImpl, Lit, Meta, MetaNameValue, Pat, ReturnType, Token, Type, TypePath,
+    Visibility,
+};
+
+/// Implements `xtra::Message` for a struct or enum. The result type of the message is given by
+/// the `#[result = "Type"]` attribute, and defaults to `()` if it is omitted.
+///
+/// # Example
+///
+/// ```
+/// use xtra::Message;
+///
+/// #[derive(Message)]
+/// #[result = "u32"]
+/// struct GetCount;
+///
+/// #[derive(Message)]
+/// struct Increment; // Result = ()
+/// ```
+#[proc_macro_derive(Message, attributes(result))]
+pub fn derive_message(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_message(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Turns the methods of an inherent `impl` block of an actor into messages. For each method which
+/// takes `&mut self`, this generates:
+///
+/// - a message struct named after the method in `CamelCase`, with one field per argument,
+/// - a `Message` implementation whose result is the method's return type,
+/// - a `Handler` implementation which calls the method,
+/// - a method of the same name on the `{Actor}AddressExt` extension trait, implemented for any
+///   `Address` to the actor, which sends the message and returns its `SendFuture`.
+///
+/// The methods may be `async` or not. An argument of type `&mut Context<Self>` is passed the
+/// actor's context rather than becoming a field of the message. Methods which do not take
+/// `&mut self` are left untouched, so constructors and the like can live in the same block.
+/// Generated items take the visibility of the method they were generated from, and the extension
+/// trait is public only if all of the methods are.
+///
+/// # Clients
+///
+/// Passing `client = Name` additionally generates a client struct of that name, which wraps an
+/// `Address` to the actor and has an `async` method for each handler method. These return
+/// `Result<T, xtra::Disconnected>`, or `Result<T, E>` if `error = E` is also passed, in which case
+/// `E` must implement `From<xtra::Disconnected>`. This allows a library to hand out a typed
+/// client to its actor without exposing the message types to its callers.
+///
+/// ```
+/// # use xtra::prelude::*;
+/// # use xtra::spawn::Smol;
+/// # struct Counter(usize);
+/// # #[async_trait::async_trait] impl Actor for Counter {type Stop = (); async fn stopped(self) -> Self::Stop {} }
+/// #[derive(Debug, PartialEq)]
+/// enum CounterError {
+///     Gone,
+/// }
+///
+/// impl From<xtra::Disconnected> for CounterError {
+///     fn from(_: xtra::Disconnected) -> Self {
+///         CounterError::Gone
+///     }
+/// }
+///
+/// #[xtra::handlers(client = CounterClient, error = CounterError)]
+/// impl Counter {
+///     pub async fn increment(&mut self) -> usize {
+///         self.0 += 1;
+///         self.0
+///     }
+/// }
+///
+/// smol::block_on(async {
+///     let client = CounterClient::new(Counter(0).create(None).spawn(&mut Smol::Global));
+///     assert_eq!(client.increment().await, Ok(1));
+/// })
+/// ```
+///
+/// # Example
+///
+/// ```
+/// use xtra::prelude::*;
+/// use xtra::spawn::Smol;
+///
+/// struct Counter(usize);
+///
+/// #[async_trait::async_trait]
+/// impl Actor for Counter {
+///     type Stop = ();
+///     async fn stopped(self) -> Self::Stop {}
+/// }
+///
+/// #[xtra::handlers]
+/// impl Counter {
+///     fn new() -> Self {
+///         Counter(0)
+///     }
+///
+///     async fn increment(&mut self, by: usize) {
+///         self.0 += by;
+///     }
+///
+///     async fn get_count(&mut self) -> usize {
+///         self.0
+///     }
+/// }
+///
+/// smol::block_on(async {
+///     let addr = Counter::new().create(None).spawn(&mut Smol::Global);
+///     addr.send(Increment { by: 2 }).await.unwrap(); // Using the generated message...
+///     addr.increment(3).await.unwrap(); // ...or the generated method
+///     assert_eq!(addr.get_count().await, Ok(5));
+/// })
+/// ```
+#[proc_macro_attribute]
+pub fn handlers(args: TokenStream, input: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(args as HandlersArgs);
+    let item = parse_macro_input!(input as ItemImpl);
+
+    expand_handlers(args, item)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn expand_message(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let mut result = None;
+
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("result"))
+    {
+        if result.is_some() {
+            return Err(Error::new_spanned(attr, "duplicate `result` attribute"));
+        }
+
+        match &attr.meta {
+            Meta::NameValue(MetaNameValue {
+                value:
+                    Expr::Lit(ExprLit {
+                        lit: Lit::Str(ty), ..
+                    }),
+                ..
+            }) => result = Some(ty.parse::<Type>()?),
+            _ => return Err(Error::new_spanned(attr, "expected `#[result = \"Type\"]`")),
+        }
+    }
+
+    let result = result.unwrap_or_else(|| parse_quote!(()));
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::xtra::Message for #name #ty_generics #where_clause {
+            type Result = #result;
+        }
+    })
+}
+
+/// A method of a `#[handlers]` impl block which is turned into a message.
+struct HandlerMethod {
+    vis: Visibility,
+    method: Ident,
+    message: Ident,
+    fields: Vec<(Ident, Type)>,
+    /// The arguments the method is called with, in order. `None` stands for the context.
+    call_args: Vec<Option<Ident>>,
+    result: Type,
+    is_async: bool,
+    docs: Vec<syn::Attribute>,
+}
+
+/// The arguments of the `#[handlers]` attribute.
+#[derive(Default)]
+struct HandlersArgs {
+    client: Option<Ident>,
+    error: Option<Type>,
+}
+
+impl Parse for HandlersArgs {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut args = HandlersArgs::default();
+
+        while !input.is_empty() {
+            let key: Ident = input.parse()?;
+            input.parse::<Token![=]>()?;
+
+            if key == "client" && args.client.is_none() {
+                args.client = Some(input.parse()?);
+            } else if key == "error" && args.error.is_none() {
+                args.error = Some(input.parse()?);
+            } else if key == "client" || key == "error" {
+                return Err(Error::new_spanned(key, "duplicate argument"));
+            } else {
+                return Err(Error::new_spanned(
+                    key,
+                    "unknown argument, expected `client` or `error`",
+                ));
+            }
+
+            if !input.is_empty() {
+                input.parse::<Token![,]>()?;
+            }
+        }
+
+        if let (None, Some(error)) = (&args.client, &args.error) {
+            return Err(Error::new_spanned(
+                error,
+                "`error` can only be given together with `client`",
+            ));
+        }
+
+        Ok(args)
+    }
+}
+
+fn expand_handlers(args: HandlersArgs, item: ItemImpl) -> syn::Result<TokenStream2> {
+    if let Some((_, path, _)) = &item.trait_ {
+        return Err(Error::new_spanned(
+            path,
+            "`#[handlers]` must be used on an inherent impl block",
+        ));
+    }
+
+    if !item.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &item.generics,
+            "`#[handlers]` does not support generic actors",
+        ));
+    }
+
+    let actor = &*item.self_ty;
+    let actor_name = match actor {
+        Type::Path(TypePath { path, .. }) => &path.segments.last().unwrap().ident,
+        _ => {
+            return Err(Error::new_spanned(
+                actor,
+                "`#[handlers]` must be used on an impl block of a named type",
+            ))
+        }
+    };
+
+    let mut methods = Vec::new();
+    for impl_item in &item.items {
+        if let ImplItem::Fn(method) = impl_item {
+            if let Some(method) = HandlerMethod::parse(method, actor)? {
+                methods.push(method);
+            }
+        }
+    }
+
+    let messages = methods.iter().map(|m| m.expand_message(actor));
+    let ext = expand_ext_trait(&methods, actor, actor_name);
+    let HandlersArgs { client, error } = args;
+    let client = client.map(|client| expand_client(&methods, actor, &client, error));
+
+    Ok(quote! {
+        #item
+        #(#messages)*
+        #ext
+        #client
+    })
+}
+
+impl HandlerMethod {
+    /// Returns `None` if the method does not take `&mut self` and should not become a message.
+    fn parse(method: &ImplItemFn, actor: &Type) -> syn::Result<Option<Self>> {
+        let sig = &method.sig;
+
+        match sig.receiver() {
+            Some(receiver) if receiver.reference.is_some() && receiver.mutability.is_some() => {}
+            _ => return Ok(None),
+        }
+
+        if !sig.generics.params.is_empty() {
+            return Err(Error::new_spanned(
+                &sig.generics,
+                "handler methods cannot be generic",
+            ));
+        }
+
+        let mut fields = Vec::new();
+        let mut call_args = Vec::new();
+
+        for arg in sig.inputs.iter().skip(1) {
+            let arg = match arg {
+                FnArg::Typed(arg) => arg,
+                FnArg::Receiver(_) => unreachable!("only the first argument can be a receiver"),
+            };
+
+            if is_context(&arg.ty) {
+                call_args.push(None);
+                continue;
+            }
+
+            let ident = match &*arg.pat {
+                Pat::Ident(pat) => pat.ident.clone(),
+                pat => {
+                    return Err(Error::new_spanned(
+                        pat,
+                        "handler method arguments must be plain identifiers",
+                    ))
+                }
+            };
+
+            let mut ty = (*arg.ty).clone();
+            ReplaceSelf(actor).visit_type_mut(&mut ty);
+
+            call_args.push(Some(ident.clone()));
+            fields.push((ident, ty));
+        }
+
+        let mut result = match &sig.output {
+            ReturnType::Default => parse_quote!(()),
+            ReturnType::Type(_, ty) => (**ty).clone(),
+        };
+        ReplaceSelf(actor).visit_type_mut(&mut result);
+
+        Ok(Some(HandlerMethod {
+            vis: method.vis.clone(),
+            method: sig.ident.clone(),
+            message: format_ident!("{}", to_camel_case(&sig.ident.to_string())),
+            fields,
+            call_args,
+            result,
+            is_async: sig.asyncness.is_some(),
+            docs: method
+                .attrs
+                .iter()
+                .filter(|attr| attr.path().is_ident("doc"))
+                .cloned()
+                .collect(),
+        }))
+    }
+
+    fn expand_message(&self, actor: &Type) -> TokenStream2 {
+        let HandlerMethod {
+            vis,
+            method,
+            message,
+            result,
+            docs,
+            ..
+        } = self;
+
+        let field_names: Vec<_> = self.fields.iter().map(|(name, _)| name).collect();
+        let field_types = self.fields.iter().map(|(_, ty)| ty);
+        let field_docs = field_names
+            .iter()
+            .map(|name| format!("The `{}` argument of the handler.", name));
+
+        let definition = if self.fields.is_empty() {
+            quote!(;)
+        } else {
+            quote! {
+                {
+                    #(
+                        #[doc = #field_docs]
+                        #vis #field_names: #field_types,
+                    )*
+                }
+            }
+        };
+
+        let uses_ctx = self.call_args.iter().any(Option::is_none);
+        let ctx = if uses_ctx {
+            format_ident!("ctx")
+        } else {
+            format_ident!("_ctx")
+        };
+        let call_args = self.call_args.iter().map(|arg| match arg {
+            Some(name) => quote!(#name),
+            None => quote!(#ctx),
+        });
+        let await_call = if self.is_async {
+            quote!(.await)
+        } else {
+            quote!()
+        };
+
+        let struct_doc = format!("The message for `{}::{}`.", quote!(#actor), method);
+        let docs = if docs.is_empty() {
+            quote!(#[doc = #struct_doc])
+        } else {
+            quote!(#(#docs)*)
+        };
+
+        quote! {
+            #docs
+            #vis struct #message #definition
+
+            impl ::xtra::Message for #message {
+                type Result = #result;
+            }
+
+            #[::xtra::async_trait]
+            impl ::xtra::Handler<#message> for #actor {
+                #[allow(unused_variables)]
+                async fn handle(
+                    &mut self,
+                    message: #message,
+                    #ctx: &mut ::xtra::Context<Self>,
+                ) -> #result {
+                    let #message { #(#field_names),* } = message;
+                    self.#method(#(#call_args),*) #await_call
+                }
+            }
+        }
+    }
+}
+
+fn expand_ext_trait(methods: &[HandlerMethod], actor: &Type, actor_name: &Ident) -> TokenStream2 {
+    let ext = format_ident!("{}AddressExt", actor_name);
+    let ext_doc = format!(
+        "Methods to send the messages generated by `#[handlers]` to an address of `{}`.",
+        actor_name
+    );
+    let vis = shared_visibility(methods);
+
+    let signatures: Vec<_> = methods
+        .iter()
+        .map(|m| {
+            let method = &m.method;
+            let message = &m.message;
+            let args = m.fields.iter().map(|(name, ty)| quote!(#name: #ty));
+            quote! {
+                fn #method(&self, #(#args),*) -> ::xtra::address::SendFuture<#actor, #message>
+            }
+        })
+        .collect();
+
+    let docs = methods.iter().map(|m| {
+        format!(
+            "Sends [`{}`] to the actor, returning a future which resolves to its result.",
+            m.message
+        )
+    });
+
+    let bodies = methods.iter().map(|m| {
+        let message = &m.message;
+        let fields = m.fields.iter().map(|(name, _)| name);
+        quote!({ self.send(#message { #(#fields),* }) })
+    });
+
+    quote! {
+        #[doc = #ext_doc]
+        #vis trait #ext {
+            #(
+                #[doc = #docs]
+                #signatures;
+            )*
+        }
+
+        impl<Rc: ::xtra::refcount::RefCounter> #ext for ::xtra::Address<#actor, Rc> {
+            #(#signatures #bodies)*
+        }
+    }
+}
+
+fn expand_client(
+    methods: &[HandlerMethod],
+    actor: &Type,
+    client: &Ident,
+    error: Option<Type>,
+) -> TokenStream2 {
+    let vis = shared_visibility(methods);
+    let error = error.unwrap_or_else(|| parse_quote!(::xtra::Disconnected));
+    let client_doc = format!(
+        "A client to an actor of type `{}`, which sends it messages through an `Address`.",
+        quote!(#actor)
+    );
+
+    let methods = methods.iter().map(|m| {
+        let HandlerMethod {
+            vis,
+            method,
+            message,
+            result,
+            ..
+        } = m;
+        let args = m.fields.iter().map(|(name, ty)| quote!(#name: #ty));
+        let fields = m.fields.iter().map(|(name, _)| name);
+        let doc = format!(
+            "Calls `{}::{}` on the actor, returning an error if it is disconnected.",
+            quote!(#actor),
+            method
+        );
+
+        quote! {
+            #[doc = #doc]
+            #vis async fn #method(&self, #(#args),*) -> ::std::result::Result<#result, #error> {
+                self.address
+                    .send(#message { #(#fields),* })
+                    .await
+                    .map_err(::std::convert::From::from)
+            }
+        }
+    });
+
+    quote! {
+        #[doc = #client_doc]
+        #vis struct #client<Rc: ::xtra::refcount::RefCounter = ::xtra::refcount::Strong> {
+            address: ::xtra::Address<#actor, Rc>,
+        }
+
+        impl<Rc: ::xtra::refcount::RefCounter> #client<Rc> {
+            /// Creates a client which sends messages through the given address.
+            #vis fn new(address: ::xtra::Address<#actor, Rc>) -> Self {
+                #client { address }
+            }
+
+            /// Returns the address which this client sends messages through.
+            #vis fn address(&self) -> &::xtra::Address<#actor, Rc> {
+                &self.address
+            }
+
+            /// Converts this client back into the address which it sends messages through.
+            #vis fn into_address(self) -> ::xtra::Address<#actor, Rc> {
+                self.address
+            }
+
+            #(#methods)*
+        }
+
+        impl<Rc: ::xtra::refcount::RefCounter> ::std::clone::Clone for #client<Rc> {
+            fn clone(&self) -> Self {
+                #client {
+                    address: self.address.clone(),
+                }
+            }
+        }
+
+        impl<Rc: ::xtra::refcount::RefCounter> ::std::convert::From<::xtra::Address<#actor, Rc>>
+            for #client<Rc>
+        {
+            fn from(address: ::xtra::Address<#actor, Rc>) -> Self {
+                #client { address }
+            }
+        }
+    }
+}
+
+/// The visibility of items which cover all of the methods: public if all of them are public, and
+/// private otherwise.
+fn shared_visibility(methods: &[HandlerMethod]) -> TokenStream2 {
+    if methods
+        .iter()
+        .all(|m| matches!(m.vis, Visibility::Public(_)))
+    {
+        quote!(pub)
+    } else {
+        quote!()
+    }
+}
+
+/// Whether the type is `&mut Context<..>`, in which case the argument is the actor's context.
+fn is_context(ty: &Type) -> bool {
+    match ty {
+        Type::Reference(reference) if reference.mutability.is_some() => match &*reference.elem {
+            Type::Path(TypePath { path, .. }) => path
+                .segments
+                .last()
+                .is_some_and(|segment| segment.ident == "Context"),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+fn to_camel_case(snake: &str) -> String {
+    snake
+        .split('_')
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let mut chars = part.chars();
+            chars
+                .next()
+                .map(|first| first.to_uppercase().chain(chars).collect::<String>())
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+/// Replaces `Self` with the actor's type, as the generated items are not inside of the impl block.
+struct ReplaceSelf<'a>(&'a Type);
+
+impl VisitMut for ReplaceSelf<'_> {
+    fn visit_type_mut(&mut self, ty: &mut Type) {
+        if let Type::Path(TypePath { qself: None, path }) = ty {
+            if path.is_ident("Self") {
+                *ty = self.0.clone();
+                return;
+            }
+        }
+
+        visit_mut::visit_type_mut(self, ty);
+    }
+}
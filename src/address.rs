@@ -5,20 +5,33 @@ use std::fmt::{self, Display, Formatter};
 use std::future::Future;
 use std::mem;
 use std::pin::Pin;
+use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 use std::{cmp::Ordering, error::Error, hash::Hash};
 
 use catty::Receiver;
 use flume::r#async::SendFut as ChannelSendFuture;
-use flume::Sender;
+use flume::{SendTimeoutError as ChannelSendTimeoutError, Sender, TrySendError};
+use futures_core::future::BoxFuture;
 use futures_core::Stream;
 use futures_util::{future, FutureExt, StreamExt};
 
-use crate::envelope::{NonReturningEnvelope, ReturningEnvelope};
+use crate::batch::BatchHandler;
+use crate::blocking::assert_not_in_actor;
+use crate::coalesce::{Coalesce, CoalesceTable};
+use crate::envelope::{
+    BatchEnvelope, CoalescingEnvelope, LimitedEnvelope, MessageEnvelope, NonReturningEnvelope,
+    ReturningEnvelope, StreamingEnvelope,
+};
+use crate::intercept::{Interceptor, Interceptors};
+use crate::limit::{Admission, Limiter, Permit};
 use crate::manager::AddressMessage;
+use crate::pool::EnvelopePool;
 use crate::refcount::{Either, RefCounter, Strong, Weak};
 use crate::sink::AddressSink;
-use crate::{Actor, Handler, KeepRunning, Message};
+use crate::streaming::{ResponseStream, ResponseStreamInner, StreamingHandler, StreamingMessage};
+use crate::{Actor, KeepRunning, Message, NativeHandler};
 
 /// The future returned [`Address::send`](struct.Address.html#method.send).
 /// It resolves to `Result<M::Result, Disconnected>`.
@@ -27,18 +40,25 @@ use crate::{Actor, Handler, KeepRunning, Message};
 #[must_use]
 pub struct SendFuture<A: Actor, M: Message>(SendFutureInner<A, M>);
 
+#[derive(Default)]
 enum SendFutureInner<A: Actor, M: Message> {
+    #[default]
     Disconnected,
     Sending(
         ChannelSendFuture<'static, AddressMessage<A>>,
         Receiver<M::Result>,
     ),
+    Waiting(
+        BoxFuture<'static, Result<(), Disconnected>>,
+        Receiver<M::Result>,
+    ),
     Receiving(Receiver<M::Result>),
 }
 
-impl<A: Actor, M: Message> Default for SendFutureInner<A, M> {
-    fn default() -> Self {
-        SendFutureInner::Disconnected
+impl<A: Actor, M: Message> SendFuture<A, M> {
+    /// A future which resolves to `Err(Disconnected)` straight away.
+    pub(crate) fn disconnected() -> Self {
+        SendFuture(SendFutureInner::Disconnected)
     }
 }
 
@@ -60,6 +80,13 @@ impl<A: Actor, M: Message> Future for SendFuture<A, M> {
                     (Poll::Pending, SendFutureInner::Sending(tx, rx))
                 }
             }
+            SendFutureInner::Waiting(mut tx, mut rx) => {
+                if tx.poll_unpin(ctx).is_ready() {
+                    (poll_rx(&mut rx, ctx), SendFutureInner::Receiving(rx))
+                } else {
+                    (Poll::Pending, SendFutureInner::Waiting(tx, rx))
+                }
+            }
             SendFutureInner::Receiving(mut rx) => {
                 (poll_rx(&mut rx, ctx), SendFutureInner::Receiving(rx))
             }
@@ -78,6 +105,7 @@ pub struct DoSendFuture<A: Actor>(DoSendFutureInner<A>);
 enum DoSendFutureInner<A: Actor> {
     Disconnected,
     Send(ChannelSendFuture<'static, AddressMessage<A>>),
+    Waiting(BoxFuture<'static, Result<(), Disconnected>>),
 }
 
 impl<A: Actor> Future for DoSendFuture<A> {
@@ -89,6 +117,23 @@ impl<A: Actor> Future for DoSendFuture<A> {
             DoSendFutureInner::Send(tx) => {
                 tx.poll_unpin(ctx).map(|res| res.map_err(|_| Disconnected))
             }
+            DoSendFutureInner::Waiting(tx) => tx.poll_unpin(ctx),
+        }
+    }
+}
+
+/// The future returned from [`Address::try_send`](struct.Address.html#method.try_send).
+/// It resolves to `Result<M::Result, SendError>`.
+#[must_use]
+pub struct TrySendFuture<A: Actor, M: Message>(Result<SendFuture<A, M>, Option<SendError>>);
+
+impl<A: Actor, M: Message> Future for TrySendFuture<A, M> {
+    type Output = Result<M::Result, SendError>;
+
+    fn poll(self: Pin<&mut Self>, ctx: &mut Context) -> Poll<Self::Output> {
+        match &mut self.get_mut().0 {
+            Ok(fut) => fut.poll_unpin(ctx).map(|res| res.map_err(Into::into)),
+            Err(err) => Poll::Ready(Err(err.take().expect("polled after completion"))),
         }
     }
 }
@@ -107,6 +152,79 @@ impl Display for Disconnected {
 
 impl Error for Disconnected {}
 
+/// The error returned by the blocking send methods which take a timeout, such as
+/// [`Address::send_blocking_timeout`](struct.Address.html#method.send_blocking_timeout).
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum SendTimeoutError {
+    /// The actor is no longer running and disconnected from the sending address.
+    Disconnected,
+    /// The timeout elapsed before the message could be delivered or, if a response was being
+    /// waited for, before the actor responded. In the latter case, the message may still be handled.
+    Timeout,
+}
+
+impl Display for SendTimeoutError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SendTimeoutError::Disconnected => f.write_str("Actor address disconnected"),
+            SendTimeoutError::Timeout => f.write_str("Timed out sending to actor"),
+        }
+    }
+}
+
+impl Error for SendTimeoutError {}
+
+impl From<Disconnected> for SendTimeoutError {
+    fn from(_: Disconnected) -> Self {
+        SendTimeoutError::Disconnected
+    }
+}
+
+impl<T> From<ChannelSendTimeoutError<T>> for SendTimeoutError {
+    fn from(err: ChannelSendTimeoutError<T>) -> Self {
+        match err {
+            ChannelSendTimeoutError::Timeout(_) => SendTimeoutError::Timeout,
+            ChannelSendTimeoutError::Disconnected(_) => SendTimeoutError::Disconnected,
+        }
+    }
+}
+
+/// The error returned by the sends which reject a message rather than wait, because it would exceed
+/// the actor's [limits](../limit/index.html) or its mailbox is full, such as
+/// [`Address::do_send`](struct.Address.html#method.do_send) and
+/// [`Address::try_send`](struct.Address.html#method.try_send), by sinks, and by the sends of an
+/// [intercepted](../intercept/index.html) channel.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum SendError {
+    /// The actor is no longer running and disconnected from the sending address.
+    Disconnected,
+    /// The message was not sent, as it would have exceeded the actor's rate or concurrency limit.
+    RateLimited,
+    /// The message was not sent, as an [interceptor](../intercept/trait.Interceptor.html) refused it.
+    Refused,
+    /// The message was not sent, as the actor's mailbox is full.
+    MailboxFull,
+}
+
+impl Display for SendError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SendError::Disconnected => f.write_str("Actor address disconnected"),
+            SendError::RateLimited => f.write_str("Actor rate limit exceeded"),
+            SendError::Refused => f.write_str("Message refused by an interceptor"),
+            SendError::MailboxFull => f.write_str("Actor mailbox full"),
+        }
+    }
+}
+
+impl Error for SendError {}
+
+impl From<Disconnected> for SendError {
+    fn from(_: Disconnected) -> Self {
+        SendError::Disconnected
+    }
+}
+
 /// An `Address` is a reference to an actor through which [`Message`s](../trait.Message.html) can be
 /// sent. It can be cloned to create more addresses to the same actor.
 /// By default (i.e without specifying the second type parameter, `Rc`, to be
@@ -119,6 +237,10 @@ impl Error for Disconnected {}
 pub struct Address<A, Rc: RefCounter = Strong> {
     pub(crate) sender: Sender<AddressMessage<A>>,
     pub(crate) ref_counter: Rc,
+    pub(crate) coalesced: Arc<CoalesceTable>,
+    pub(crate) limiter: Arc<Limiter>,
+    pub(crate) envelopes: Arc<EnvelopePool>,
+    pub(crate) interceptors: Interceptors,
 }
 
 /// A `WeakAddress` is a reference to an actor through which [`Message`s](../trait.Message.html) can be
@@ -136,6 +258,10 @@ impl<A> Address<A, Strong> {
         WeakAddress {
             sender: self.sender.clone(),
             ref_counter: self.ref_counter.downgrade(),
+            coalesced: self.coalesced.clone(),
+            limiter: self.limiter.clone(),
+            envelopes: self.envelopes.clone(),
+            interceptors: self.interceptors.clone(),
         }
     }
 }
@@ -147,6 +273,10 @@ impl<A> Address<A, Either> {
         WeakAddress {
             sender: self.sender.clone(),
             ref_counter: self.ref_counter.clone().into_weak(),
+            coalesced: self.coalesced.clone(),
+            limiter: self.limiter.clone(),
+            envelopes: self.envelopes.clone(),
+            interceptors: self.interceptors.clone(),
         }
     }
 }
@@ -207,28 +337,124 @@ impl<A, Rc: RefCounter> Address<A, Rc> {
         Address {
             ref_counter: self.ref_counter.clone().into_either(),
             sender: self.sender.clone(),
+            coalesced: self.coalesced.clone(),
+            limiter: self.limiter.clone(),
+            envelopes: self.envelopes.clone(),
+            interceptors: self.interceptors.clone(),
         }
     }
 
     /// Send a [`Message`](../trait.Message.html) to the actor without waiting for a response.
-    /// If the actor's mailbox is full, it will block. If this returns `Err(Disconnected)`, then the
-    /// actor is stopped and not accepting messages. If this returns `Ok(())`, the will be delivered,
-    /// but may not be handled in the event that the actor stops itself (by calling
+    /// If this returns `Err(SendError::Disconnected)`, then the actor is stopped and not accepting
+    /// messages. If the message would exceed the actor's [limits](../limit/index.html), it is not
+    /// sent, and this returns `Err(SendError::RateLimited)`. If this returns `Ok(())`, the will be
+    /// delivered, but may not be handled in the event that the actor stops itself (by calling
     /// [`Context::stop`](../struct.Context.html#method.stop)) before it was handled.
-    pub fn do_send<M>(&self, message: M) -> Result<(), Disconnected>
+    ///
+    /// A full mailbox blocks the current thread until there is room, which can deadlock if it is
+    /// the thread of an executor which the actor runs on. In async code, use
+    /// [`Address::do_send_async`](struct.Address.html#method.do_send_async) to send to actors with
+    /// bounded mailboxes instead, which also waits for the message to be admitted by the actor's
+    /// limits.
+    pub fn do_send<M>(&self, message: M) -> Result<(), SendError>
     where
         M: Message,
-        A: Handler<M>,
+        A: NativeHandler<M>,
     {
-        if self.is_connected() {
-            // To read more about what an envelope is and why we use them, look under `envelope.rs`
-            let envelope = NonReturningEnvelope::<A, M>::new(message);
-            self.sender
-                .send(AddressMessage::Message(Box::new(envelope)))
-                .map_err(|_| Disconnected)
-        } else {
-            Err(Disconnected)
+        let message = self.interceptors.intercept(message)?;
+        // To read more about what an envelope is and why we use them, look under `envelope.rs`
+        let envelope = NonReturningEnvelope::<A, M>::pooled(message, &self.envelopes);
+        self.do_send_envelope(envelope)
+    }
+
+    /// Like [`Address::do_send`](struct.Address.html#method.do_send), but never waits. It also
+    /// returns `Err(SendError::MailboxFull)` without sending the message if the actor's mailbox is
+    /// full.
+    pub fn try_do_send<M>(&self, message: M) -> Result<(), SendError>
+    where
+        M: Message,
+        A: NativeHandler<M>,
+    {
+        if !self.is_connected() {
+            return Err(SendError::Disconnected);
+        }
+
+        let message = self.interceptors.intercept(message)?;
+        let permit = self
+            .limiter
+            .try_acquire()
+            .map_err(|_| SendError::RateLimited)?;
+        let envelope = NonReturningEnvelope::<A, M>::pooled(message, &self.envelopes);
+        let msg = AddressMessage::Message(LimitedEnvelope::wrap(envelope, permit));
+        match self.sender.try_send(msg) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => Err(SendError::MailboxFull),
+            Err(TrySendError::Disconnected(_)) => Err(SendError::Disconnected),
+        }
+    }
+
+    /// Send a batch of [`Message`s](../trait.Message.html) to the actor without waiting for a
+    /// response. The whole batch is put into the actor's mailbox with a single channel operation,
+    /// taking up one slot of its capacity, and is handled by a single call to
+    /// [`BatchHandler::handle_batch`](../batch/trait.BatchHandler.html#tymethod.handle_batch).
+    /// If the actor's mailbox is full, it will block. Nothing is sent if the iterator is empty, or
+    /// if an [interceptor](../intercept/index.html) of this address refuses any of the messages.
+    ///
+    /// If this returns `Err(SendError::Disconnected)`, then the actor is stopped and not accepting
+    /// messages. Like [`Address::do_send`](struct.Address.html#method.do_send), the batch is not
+    /// sent if it would exceed the actor's [limits](../limit/index.html), which it counts against
+    /// as a single message. If this returns `Ok(())`, the batch will be delivered, but may not be
+    /// handled in the event that the actor stops itself before it was handled.
+    pub fn do_send_batch<M, I>(&self, messages: I) -> Result<(), SendError>
+    where
+        M: Message,
+        A: BatchHandler<M>,
+        I: IntoIterator<Item = M>,
+    {
+        if !self.is_connected() {
+            return Err(SendError::Disconnected);
+        }
+
+        let messages = messages
+            .into_iter()
+            .map(|message| self.interceptors.intercept(message))
+            .collect::<Result<Vec<M>, _>>()?;
+        if messages.is_empty() {
+            return Ok(());
+        }
+
+        let envelope = BatchEnvelope::<A, M>::new(messages);
+        self.do_send_envelope(Box::new(envelope))
+    }
+
+    /// Send a [`Coalesce`](../coalesce/trait.Coalesce.html) message to the actor without waiting
+    /// for a response. If a message with the same key is still waiting in the actor's mailbox, it
+    /// is replaced by this one, which is then handled in its place. Otherwise, the message is
+    /// queued like with [`Address::do_send`](struct.Address.html#method.do_send), blocking if
+    /// the actor's mailbox is full, or rejected with `Err(SendError::RateLimited)` if it would
+    /// exceed the actor's [limits](../limit/index.html). Replacing a message is never rejected, as
+    /// it does not add another message.
+    ///
+    /// If this returns `Err(SendError::Disconnected)`, then the actor is stopped and not accepting
+    /// messages.
+    pub fn do_send_coalescing<M>(&self, message: M) -> Result<(), SendError>
+    where
+        M: Coalesce,
+        A: NativeHandler<M>,
+    {
+        if !self.is_connected() {
+            return Err(SendError::Disconnected);
         }
+
+        let message = self.interceptors.intercept(message)?;
+        let key = match self.coalesced.insert(message) {
+            Some(key) => key,
+            None => return Ok(()), // Replaced a waiting message
+        };
+
+        // If the send fails, dropping the envelope releases the key
+        let envelope = CoalescingEnvelope::<A, M>::new(key, self.coalesced.clone());
+        self.do_send_envelope(Box::new(envelope))
     }
 
     /// Send a [`Message`](../trait.Message.html) to the actor without waiting for a response.
@@ -239,37 +465,363 @@ impl<A, Rc: RefCounter> Address<A, Rc> {
     pub fn do_send_async<M>(&self, message: M) -> DoSendFuture<A>
     where
         M: Message,
-        A: Handler<M>,
+        A: NativeHandler<M>,
     {
-        if self.is_connected() {
-            let envelope = NonReturningEnvelope::<A, M>::new(message);
-            let fut = self
-                .sender
-                .clone()
-                .into_send_async(AddressMessage::Message(Box::new(envelope)));
-            DoSendFuture(DoSendFutureInner::Send(fut))
-        } else {
-            DoSendFuture(DoSendFutureInner::Disconnected)
+        let message = match self.interceptors.intercept(message) {
+            Ok(message) => message,
+            Err(_) => return DoSendFuture(DoSendFutureInner::Disconnected),
+        };
+        let envelope = NonReturningEnvelope::<A, M>::pooled(message, &self.envelopes);
+        self.do_send_envelope_async(envelope)
+    }
+
+    /// Sends an already constructed envelope to the actor, rejecting it if it would exceed the
+    /// actor's limits and blocking if its mailbox is full.
+    pub(crate) fn do_send_envelope(
+        &self,
+        envelope: Box<dyn MessageEnvelope<Actor = A>>,
+    ) -> Result<(), SendError>
+    where
+        A: Actor,
+    {
+        if !self.is_connected() {
+            return Err(SendError::Disconnected);
         }
+
+        let permit = self
+            .limiter
+            .try_acquire()
+            .map_err(|_| SendError::RateLimited)?;
+        let msg = AddressMessage::Message(LimitedEnvelope::wrap(envelope, permit));
+        self.sender.send(msg).map_err(|_| SendError::Disconnected)
+    }
+
+    /// Sends an already constructed envelope to the actor, asynchronously waiting until it is
+    /// admitted by the actor's limits and if its mailbox is full.
+    pub(crate) fn do_send_envelope_async(
+        &self,
+        envelope: Box<dyn MessageEnvelope<Actor = A>>,
+    ) -> DoSendFuture<A>
+    where
+        A: Actor,
+    {
+        if !self.is_connected() {
+            return DoSendFuture(DoSendFutureInner::Disconnected);
+        }
+
+        match self.limiter.try_acquire() {
+            Ok(permit) => {
+                let fut = self.sender.clone().into_send_async(AddressMessage::Message(
+                    LimitedEnvelope::wrap(envelope, permit),
+                ));
+                DoSendFuture(DoSendFutureInner::Send(fut))
+            }
+            Err(_) => DoSendFuture(DoSendFutureInner::Waiting(
+                self.send_when_admitted(envelope),
+            )),
+        }
+    }
+
+    /// Waits until the envelope is admitted by the actor's limits, and then sends it.
+    fn send_when_admitted(
+        &self,
+        envelope: Box<dyn MessageEnvelope<Actor = A>>,
+    ) -> BoxFuture<'static, Result<(), Disconnected>>
+    where
+        A: Actor,
+    {
+        let sender = self.sender.clone();
+        let limiter = self.limiter.clone();
+        Box::pin(async move {
+            let permit = limiter.acquire().await;
+            sender
+                .into_send_async(AddressMessage::Message(LimitedEnvelope::wrap(
+                    envelope, permit,
+                )))
+                .await
+                .map_err(|_| Disconnected)
+        })
     }
 
     /// Send a [`Message`](../trait.Message.html) to the actor and asynchronously wait for a response. If this
     /// returns `Err(Disconnected)`, then the actor is stopped and not accepting messages. Like most
-    /// futures, this must be polled to actually send the message.
+    /// futures, this must be polled to actually send the message. If the message would exceed the
+    /// actor's [limits](../limit/index.html), it waits until the message is admitted.
     pub fn send<M>(&self, message: M) -> SendFuture<A, M>
     where
         M: Message,
-        A: Handler<M>,
+        A: NativeHandler<M>,
     {
-        if self.is_connected() {
-            let (envelope, rx) = ReturningEnvelope::<A, M>::new(message);
-            let tx = self
-                .sender
+        if !self.is_connected() {
+            return SendFuture(SendFutureInner::Disconnected);
+        }
+
+        let message = match self.interceptors.intercept(message) {
+            Ok(message) => message,
+            Err(_) => return SendFuture::disconnected(),
+        };
+        match self.limiter.try_acquire() {
+            Ok(permit) => self.send_admitted(message, permit),
+            Err(_) => {
+                let (envelope, rx) = ReturningEnvelope::<A, M>::new(message, &self.envelopes);
+                let tx = self.send_when_admitted(envelope);
+                SendFuture(SendFutureInner::Waiting(tx, rx))
+            }
+        }
+    }
+
+    /// Sends a message which was already admitted by the actor's limits with the given permit,
+    /// and already passed through this address's interceptors, asynchronously waiting if its
+    /// mailbox is full.
+    pub(crate) fn send_admitted<M>(&self, message: M, permit: Option<Permit>) -> SendFuture<A, M>
+    where
+        M: Message,
+        A: NativeHandler<M>,
+    {
+        let (envelope, rx) = ReturningEnvelope::<A, M>::new(message, &self.envelopes);
+        let tx =
+            self.sender
                 .clone()
-                .into_send_async(AddressMessage::Message(Box::new(envelope)));
-            SendFuture(SendFutureInner::Sending(tx, rx))
+                .into_send_async(AddressMessage::Message(LimitedEnvelope::wrap(
+                    envelope, permit,
+                )));
+        SendFuture(SendFutureInner::Sending(tx, rx))
+    }
+
+    /// Like [`Address::send`](struct.Address.html#method.send), but resolves to
+    /// `Err(SendError::RateLimited)` without sending the message if it would exceed the actor's
+    /// [limits](../limit/index.html), rather than waiting until it is admitted.
+    pub fn try_send<M>(&self, message: M) -> TrySendFuture<A, M>
+    where
+        M: Message,
+        A: NativeHandler<M>,
+    {
+        if !self.is_connected() {
+            return TrySendFuture(Ok(SendFuture(SendFutureInner::Disconnected)));
+        }
+
+        let message = match self.interceptors.intercept(message) {
+            Ok(message) => message,
+            Err(err) => return TrySendFuture(Err(Some(err))),
+        };
+        match self.limiter.try_acquire() {
+            Ok(permit) => {
+                let (envelope, rx) = ReturningEnvelope::<A, M>::new(message, &self.envelopes);
+                let tx = self.sender.clone().into_send_async(AddressMessage::Message(
+                    LimitedEnvelope::wrap(envelope, permit),
+                ));
+                TrySendFuture(Ok(SendFuture(SendFutureInner::Sending(tx, rx))))
+            }
+            Err(_) => TrySendFuture(Err(Some(SendError::RateLimited))),
+        }
+    }
+
+    /// Send a [`Message`](../trait.Message.html) to the actor without waiting for a response,
+    /// blocking the current thread until the message is admitted by the actor's
+    /// [limits](../limit/index.html) and there is room in its mailbox. This behaves like
+    /// [`Address::do_send`](struct.Address.html#method.do_send), but is intended to be called from
+    /// synchronous code which is not running on an executor.
+    ///
+    /// # Panics
+    ///
+    /// In debug builds, this panics if it is called from inside of an actor, as blocking the
+    /// executor thread there can deadlock.
+    #[track_caller]
+    pub fn do_send_blocking<M>(&self, message: M) -> Result<(), Disconnected>
+    where
+        M: Message,
+        A: NativeHandler<M>,
+    {
+        assert_not_in_actor("Address::do_send_blocking");
+
+        if !self.is_connected() {
+            return Err(Disconnected);
+        }
+
+        let message = self
+            .interceptors
+            .intercept(message)
+            .map_err(|_| Disconnected)?;
+        let permit = self.limiter.acquire_blocking();
+        let envelope = NonReturningEnvelope::<A, M>::pooled(message, &self.envelopes);
+        self.sender
+            .send(AddressMessage::Message(LimitedEnvelope::wrap(
+                envelope, permit,
+            )))
+            .map_err(|_| Disconnected)
+    }
+
+    /// Like [`Address::do_send_blocking`](struct.Address.html#method.do_send_blocking), but gives
+    /// up with `Err(SendTimeoutError::Timeout)` if the message could not be admitted and put into
+    /// the actor's mailbox within the given timeout.
+    ///
+    /// # Panics
+    ///
+    /// In debug builds, this panics if it is called from inside of an actor, as blocking the
+    /// executor thread there can deadlock.
+    #[track_caller]
+    pub fn do_send_blocking_timeout<M>(
+        &self,
+        message: M,
+        timeout: Duration,
+    ) -> Result<(), SendTimeoutError>
+    where
+        M: Message,
+        A: NativeHandler<M>,
+    {
+        assert_not_in_actor("Address::do_send_blocking_timeout");
+
+        if !self.is_connected() {
+            return Err(SendTimeoutError::Disconnected);
+        }
+
+        let message = self
+            .interceptors
+            .intercept(message)
+            .map_err(|_| SendTimeoutError::Disconnected)?;
+        let deadline = Instant::now() + timeout;
+        let permit = self
+            .limiter
+            .acquire_blocking_until(Some(deadline))
+            .map_err(|_| SendTimeoutError::Timeout)?;
+        let envelope = NonReturningEnvelope::<A, M>::pooled(message, &self.envelopes);
+        self.sender
+            .send_deadline(
+                AddressMessage::Message(LimitedEnvelope::wrap(envelope, permit)),
+                deadline,
+            )
+            .map_err(Into::into)
+    }
+
+    /// Send a [`Message`](../trait.Message.html) to the actor and block the current thread until
+    /// it responds. This allows synchronous code which is not running on an executor to call
+    /// actors. If this returns `Err(Disconnected)`, then the actor is stopped and not accepting
+    /// messages. If the message would exceed the actor's [limits](../limit/index.html), this blocks
+    /// until it is admitted.
+    ///
+    /// # Panics
+    ///
+    /// In debug builds, this panics if it is called from inside of an actor, as blocking the
+    /// executor thread there can deadlock. Use [`Address::send`](struct.Address.html#method.send)
+    /// instead.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use xtra::prelude::*;
+    /// # use xtra::spawn::Smol;
+    /// # struct MyActor;
+    /// # #[async_trait::async_trait] impl Actor for MyActor {type Stop = (); async fn stopped(self) -> Self::Stop {} }
+    /// struct Ping;
+    ///
+    /// impl Message for Ping {
+    ///     type Result = &'static str;
+    /// }
+    ///
+    /// #[async_trait::async_trait]
+    /// impl Handler<Ping> for MyActor {
+    ///     async fn handle(&mut self, _: Ping, _ctx: &mut Context<Self>) -> &'static str {
+    ///         "pong"
+    ///     }
+    /// }
+    ///
+    /// let addr = MyActor.create(None).spawn(&mut Smol::Global);
+    /// let reply = std::thread::spawn(move || addr.send_blocking(Ping)).join().unwrap();
+    /// assert_eq!(reply, Ok("pong"));
+    /// ```
+    #[track_caller]
+    pub fn send_blocking<M>(&self, message: M) -> Result<M::Result, Disconnected>
+    where
+        M: Message,
+        A: NativeHandler<M>,
+    {
+        assert_not_in_actor("Address::send_blocking");
+
+        if !self.is_connected() {
+            return Err(Disconnected);
+        }
+
+        let message = self
+            .interceptors
+            .intercept(message)
+            .map_err(|_| Disconnected)?;
+        let permit = self.limiter.acquire_blocking();
+        let (envelope, rx) = ReturningEnvelope::<A, M>::new(message, &self.envelopes);
+        self.sender
+            .send(AddressMessage::Message(LimitedEnvelope::wrap(
+                envelope, permit,
+            )))
+            .map_err(|_| Disconnected)?;
+        pollster::block_on(rx).map_err(|_| Disconnected)
+    }
+
+    /// Like [`Address::send_blocking`](struct.Address.html#method.send_blocking), but gives up
+    /// with `Err(SendTimeoutError::Timeout)` if the actor has not responded within the given
+    /// timeout. The timeout covers waiting for the message to be admitted by the actor's limits,
+    /// for space in the actor's mailbox and for the response. If the message was delivered before the timeout elapsed, it may still be handled.
+    ///
+    /// # Panics
+    ///
+    /// In debug builds, this panics if it is called from inside of an actor, as blocking the
+    /// executor thread there can deadlock.
+    #[cfg(feature = "timing")]
+    #[track_caller]
+    pub fn send_blocking_timeout<M>(
+        &self,
+        message: M,
+        timeout: Duration,
+    ) -> Result<M::Result, SendTimeoutError>
+    where
+        M: Message,
+        A: NativeHandler<M>,
+    {
+        assert_not_in_actor("Address::send_blocking_timeout");
+
+        if !self.is_connected() {
+            return Err(SendTimeoutError::Disconnected);
+        }
+
+        let message = self
+            .interceptors
+            .intercept(message)
+            .map_err(|_| SendTimeoutError::Disconnected)?;
+        let deadline = Instant::now() + timeout;
+        let permit = self
+            .limiter
+            .acquire_blocking_until(Some(deadline))
+            .map_err(|_| SendTimeoutError::Timeout)?;
+        let (envelope, rx) = ReturningEnvelope::<A, M>::new(message, &self.envelopes);
+        self.sender.send_deadline(
+            AddressMessage::Message(LimitedEnvelope::wrap(envelope, permit)),
+            deadline,
+        )?;
+
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let timer = futures_timer::Delay::new(remaining);
+        match pollster::block_on(future::select(rx, timer)) {
+            future::Either::Left((res, _)) => res.map_err(|_| SendTimeoutError::Disconnected),
+            future::Either::Right(_) => Err(SendTimeoutError::Timeout),
+        }
+    }
+
+    /// Send a [`StreamingMessage`](../streaming/trait.StreamingMessage.html) to the actor and
+    /// receive its response as a stream of items, yielded as the actor emits them. If the actor is
+    /// stopped and not accepting messages, the stream yields only `Err(Disconnected)`. Like most
+    /// streams, this must be polled to actually send the message.
+    ///
+    /// The actor will only be able to emit [`StreamingMessage::BUFFER`](../streaming/trait.StreamingMessage.html#associatedconstant.BUFFER)
+    /// items ahead of the stream being polled, thereby applying backpressure to it.
+    pub fn send_streaming<M>(&self, message: M) -> ResponseStream<A, M>
+    where
+        M: StreamingMessage,
+        A: StreamingHandler<M>,
+    {
+        if self.is_connected() {
+            let (envelope, rx) = StreamingEnvelope::<A, M>::new(message);
+            let tx = self.do_send_envelope_async(Box::new(envelope));
+            ResponseStream::new(ResponseStreamInner::Sending(tx, rx))
         } else {
-            SendFuture(SendFutureInner::Disconnected)
+            ResponseStream::new(ResponseStreamInner::Disconnected)
         }
     }
 
@@ -288,7 +840,7 @@ impl<A, Rc: RefCounter> Address<A, Rc> {
     where
         K: Into<KeepRunning> + Send,
         M: Message<Result = K>,
-        A: Handler<M>,
+        A: NativeHandler<M>,
         S: Stream<Item = M> + Send,
     {
         let mut stopped = self.ref_counter.disconnect_notice();
@@ -307,11 +859,99 @@ impl<A, Rc: RefCounter> Address<A, Rc> {
         }
     }
 
+    /// Attaches a stream to this actor like [`Address::attach_stream`](struct.Address.html#method.attach_stream),
+    /// but keeps up to `n` messages from the stream in flight at once rather than waiting for each
+    /// message to be handled before pulling the next item from the stream. The results are still
+    /// observed in the order that the messages were produced by the stream, and forwarding stops
+    /// at the first result which does not convert to [`KeepRunning::Yes`](../enum.KeepRunning.html#variant.Yes).
+    /// Messages which were already in flight at that point will still be handled by the actor.
+    ///
+    /// Often, this should be spawned onto an executor to run in the background. **Do not await this
+    /// inside of an actor** - this will cause it to await forever and never receive any messages.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is zero.
+    pub async fn attach_stream_buffered<S, M, K>(self, stream: S, n: usize)
+    where
+        K: Into<KeepRunning> + Send,
+        M: Message<Result = K>,
+        A: NativeHandler<M>,
+        S: Stream<Item = M> + Send,
+    {
+        assert!(n > 0, "at least one message must be allowed in flight");
+
+        let mut stopped = self.ref_counter.disconnect_notice();
+        let results = stream.map(|m| self.send(m)).buffered(n);
+        futures_util::pin_mut!(results);
+
+        loop {
+            if let future::Either::Left((Some(res), _)) =
+                future::select(&mut results.next(), &mut stopped).await
+            {
+                if matches!(res.map(Into::into), Ok(KeepRunning::Yes)) {
+                    continue;
+                }
+            }
+            break;
+        }
+    }
+
+    /// Attaches a stream to this actor such that all messages produced by it are sent to the actor
+    /// with [`Address::do_send_async`](struct.Address.html#method.do_send_async), without waiting
+    /// for them to be handled. The stream is therefore only held back by the capacity of the
+    /// actor's mailbox. As the results are not observed, forwarding only stops once the stream ends
+    /// or the actor is disconnected.
+    ///
+    /// Often, this should be spawned onto an executor to run in the background. **Do not await this
+    /// inside of an actor** - this will cause it to await forever and never receive any messages.
+    pub async fn attach_stream_do_send<S, M>(self, stream: S)
+    where
+        M: Message<Result = ()>,
+        A: NativeHandler<M>,
+        S: Stream<Item = M> + Send,
+    {
+        let mut stopped = self.ref_counter.disconnect_notice();
+        futures_util::pin_mut!(stream);
+
+        loop {
+            if let future::Either::Left((Some(m), _)) =
+                future::select(&mut stream.next(), &mut stopped).await
+            {
+                let res = self.do_send_async(m); // Bound to make it Sync
+                if res.await.is_ok() {
+                    continue;
+                }
+            }
+            break;
+        }
+    }
+
     /// Converts this address into a [futures `Sink`](https://docs.rs/futures/0.3/futures/io/struct.Sink.html).
     pub fn into_sink(self) -> AddressSink<A, Rc> {
         AddressSink {
             sink: self.sender.clone().into_sink(),
             ref_counter: self.ref_counter.clone(),
+            limiter: self.limiter.clone(),
+            admission: Admission::default(),
+            interceptors: self.interceptors.clone(),
+        }
+    }
+
+    /// Returns an address to the same actor which passes the messages of type `M` sent through it,
+    /// and its clones, through the interceptor first. The interceptor is layered around the
+    /// address's existing interceptors, so that it sees each message before they do. Messages sent
+    /// through other addresses of the actor, such as this one, are not intercepted. See the
+    /// [`intercept`](../intercept/index.html) module for more details.
+    pub fn with_interceptor<M>(self, interceptor: impl Interceptor<M>) -> Self
+    where
+        M: Message,
+        A: NativeHandler<M>,
+    {
+        let interceptor: Arc<dyn Interceptor<M>> = Arc::new(interceptor);
+        Address {
+            interceptors: self.interceptors.with(interceptor),
+            ..self
         }
     }
 
@@ -327,17 +967,10 @@ impl<A, Rc: RefCounter> Clone for Address<A, Rc> {
         Address {
             sender: self.sender.clone(),
             ref_counter: self.ref_counter.clone(),
-        }
-    }
-}
-
-// Drop impls cannot be specialised, so a little bit of fanagling is used in the RefCounter impl
-impl<A, Rc: RefCounter> Drop for Address<A, Rc> {
-    fn drop(&mut self) {
-        // We should notify the ActorManager that there are no more strong Addresses and the actor
-        // should be stopped.
-        if self.ref_counter.is_last_strong() {
-            let _ = self.sender.send(AddressMessage::LastAddress);
+            coalesced: self.coalesced.clone(),
+            limiter: self.limiter.clone(),
+            envelopes: self.envelopes.clone(),
+            interceptors: self.interceptors.clone(),
         }
     }
 }
@@ -0,0 +1,65 @@
+//! Batch handlers receive many messages of the same type at once, rather than one at a time. This
+//! amortises the per-message overhead of the manage loop for actors which handle many tiny
+//! messages, such as events to be ingested.
+
+use crate::{Actor, Context, Message};
+
+/// A trait indicating that an [`Actor`](../trait.Actor.html) can handle a batch of messages of a
+/// given type at once. Batches are sent with
+/// [`Address::do_send_batch`](../address/struct.Address.html#method.do_send_batch). If several
+/// batches of the same type are queued back to back in the actor's mailbox, they are merged and
+/// handled in a single call, so the handler receives everything of that type which is queued.
+/// Messages of the same type sent on their own with `do_send` (or `do_send_async`) which are queued
+/// directly behind a batch are merged into it too, so `handle_batch` must handle a message the
+/// same way as [`Handler::handle`](../trait.Handler.html#tymethod.handle) does. Messages sent with
+/// `send` are never merged, as their results are returned.
+///
+/// The results of the messages are not returned to the sender, so this is best suited to messages
+/// whose result is `()`.
+///
+/// This is an [`async_trait`](https://docs.rs/async-trait), so implementations should
+/// be annotated `#[async_trait]`.
+///
+/// # Example
+///
+/// ```
+/// # use xtra::prelude::*;
+/// # use xtra::spawn::Smol;
+/// use xtra::batch::BatchHandler;
+///
+/// struct Ingest(Vec<u32>);
+/// # #[async_trait::async_trait] impl Actor for Ingest {type Stop = (); async fn stopped(self) -> Self::Stop {} }
+///
+/// struct Event(u32);
+///
+/// impl Message for Event {
+///     type Result = ();
+/// }
+///
+/// #[async_trait::async_trait]
+/// impl BatchHandler<Event> for Ingest {
+///     async fn handle_batch(&mut self, events: Vec<Event>, _ctx: &mut Context<Self>) {
+///         self.0.extend(events.into_iter().map(|e| e.0));
+///     }
+/// }
+/// # struct Get;
+/// # impl Message for Get { type Result = Vec<u32>; }
+/// # #[async_trait::async_trait]
+/// # impl Handler<Get> for Ingest {
+/// #     async fn handle(&mut self, _: Get, _ctx: &mut Context<Self>) -> Vec<u32> { self.0.clone() }
+/// # }
+///
+/// smol::block_on(async {
+///     let addr = Ingest(Vec::new()).create(None).spawn(&mut Smol::Global);
+///     addr.do_send_batch((0..3).map(Event)).unwrap();
+///     assert_eq!(addr.send(Get).await, Ok(vec![0, 1, 2]));
+/// })
+/// ```
+#[async_trait::async_trait]
+pub trait BatchHandler<M: Message>: Actor {
+    /// Handle a batch of messages, in the order that they were sent.
+    ///
+    /// This is an [`async_trait`](https://docs.rs/async-trait).
+    /// See the trait documentation to see an example of how this method can be declared.
+    async fn handle_batch(&mut self, messages: Vec<M>, ctx: &mut Context<Self>);
+}
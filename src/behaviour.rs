@@ -0,0 +1,187 @@
+//! Behaviours let an actor change which messages it accepts at runtime, so that a protocol state
+//! machine does not need a `match` on its state in every handler. The current behaviour is set with
+//! [`Context::set_behaviour`](../struct.Context.html#method.set_behaviour), and messages which it
+//! does not accept are stashed, dropped or rejected with a [`Rejected`](struct.Rejected.html) error
+//! before they reach a handler.
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{self, Debug, Display, Formatter};
+
+use crate::Message;
+
+/// A set of messages which an actor accepts while it is in a given state.
+///
+/// # Example
+///
+/// ```
+/// # use xtra::prelude::*;
+/// use xtra::behaviour::{Behaviour, Rejected};
+///
+/// struct Connect;
+///
+/// impl Message for Connect {
+///     type Result = ();
+/// }
+///
+/// struct Publish(String);
+///
+/// impl Message for Publish {
+///     type Result = Result<(), Rejected>;
+/// }
+///
+/// struct Query;
+///
+/// impl Message for Query {
+///     type Result = ();
+/// }
+///
+/// // While connecting, publishing fails straight away and queries wait until connected
+/// let connecting = Behaviour::new("connecting")
+///     .accept::<Connect>()
+///     .reject::<Publish>();
+///
+/// let connected = Behaviour::new("connected")
+///     .accept::<Publish>()
+///     .accept::<Query>();
+/// ```
+#[derive(Clone)]
+pub struct Behaviour {
+    name: &'static str,
+    rules: HashMap<TypeId, Rule>,
+    unaccepted: Unaccepted,
+}
+
+impl Behaviour {
+    /// Creates a behaviour with the given name, which accepts no messages.
+    pub fn new(name: &'static str) -> Self {
+        Behaviour {
+            name,
+            rules: HashMap::new(),
+            unaccepted: Unaccepted::Stash,
+        }
+    }
+
+    /// Handles messages of type `M` while in this behaviour.
+    pub fn accept<M: Message>(mut self) -> Self {
+        self.rules.insert(TypeId::of::<M>(), Rule::Accept);
+        self
+    }
+
+    /// Replies to messages of type `M` with a [`Rejected`](struct.Rejected.html) error while in
+    /// this behaviour, without handling them.
+    pub fn reject<M>(mut self) -> Self
+    where
+        M: Message,
+        M::Result: FromRejected,
+    {
+        self.rules
+            .insert(TypeId::of::<M>(), Rule::Reject(rejected_result::<M>));
+        self
+    }
+
+    /// Sets what to do with messages which this behaviour neither accepts nor rejects. By default,
+    /// they are stashed.
+    pub fn unaccepted(mut self, unaccepted: Unaccepted) -> Self {
+        self.unaccepted = unaccepted;
+        self
+    }
+
+    /// Returns the name of this behaviour.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub(crate) fn rule(&self, message_type: TypeId) -> Rule {
+        match self.rules.get(&message_type) {
+            Some(rule) => *rule,
+            None => match self.unaccepted {
+                Unaccepted::Stash => Rule::Stash,
+                Unaccepted::Drop => Rule::Drop,
+            },
+        }
+    }
+}
+
+impl Debug for Behaviour {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Behaviour")
+            .field("name", &self.name)
+            .field("unaccepted", &self.unaccepted)
+            .finish_non_exhaustive()
+    }
+}
+
+/// What a [`Behaviour`](struct.Behaviour.html) does with messages which it neither accepts nor
+/// rejects.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Unaccepted {
+    /// Stash the message, as with [`Context::stash`](../struct.Context.html#method.stash). Stashed
+    /// messages are replayed whenever the behaviour changes. Broadcast messages cannot be stashed,
+    /// and are dropped instead.
+    Stash,
+    /// Drop the message. If it was sent with
+    /// [`Address::send`](../address/struct.Address.html#method.send), the sender receives
+    /// `Err(Disconnected)`. A message from a [durable mailbox](../durable/index.html) is
+    /// acknowledged, so it is not delivered again.
+    Drop,
+}
+
+/// How the current behaviour treats a type of message.
+#[derive(Copy, Clone)]
+pub(crate) enum Rule {
+    Accept,
+    Stash,
+    Drop,
+    /// Reply with the result made by the function from the given `Rejected` error.
+    Reject(fn(Rejected) -> Box<dyn Any + Send>),
+}
+
+fn rejected_result<M>(rejected: Rejected) -> Box<dyn Any + Send>
+where
+    M: Message,
+    M::Result: FromRejected,
+{
+    Box::new(M::Result::from_rejected(rejected))
+}
+
+/// The error returned to the sender of a message which the actor's current
+/// [`Behaviour`](struct.Behaviour.html) rejects.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Rejected {
+    pub(crate) behaviour: &'static str,
+}
+
+impl Rejected {
+    /// Returns the name of the behaviour which rejected the message.
+    pub fn behaviour(&self) -> &'static str {
+        self.behaviour
+    }
+}
+
+impl Display for Rejected {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Message was rejected by the actor's current behaviour `{}`",
+            self.behaviour
+        )
+    }
+}
+
+impl Error for Rejected {}
+
+/// A message result which can be made from a [`Rejected`](struct.Rejected.html) error, so that
+/// the message can be rejected by a [`Behaviour`](struct.Behaviour.html). It is implemented for
+/// `Result<T, E>` where `E: From<Rejected>`.
+pub trait FromRejected {
+    /// Makes the result from the error.
+    fn from_rejected(rejected: Rejected) -> Self;
+}
+
+impl<T, E: From<Rejected>> FromRejected for Result<T, E> {
+    fn from_rejected(rejected: Rejected) -> Self {
+        Err(E::from(rejected))
+    }
+}
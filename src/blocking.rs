@@ -0,0 +1,105 @@
+//! Support for detecting blocking sends made from async code. Blocking on a reply from inside of an
+//! actor's manage loop, or from any other task of an async runtime, would stall the executor thread
+//! which the actor (and possibly the recipient) is running on, which can deadlock, so in debug
+//! builds it panics instead.
+//!
+//! This only applies to the `*_blocking` methods, which are meant for synchronous code.
+//!
+//! An actor's manage loop is always detected. Other tasks are only detected on async-std (with the
+//! `with-async_std-1` feature), which exposes whether the current thread is running one of its
+//! tasks. Tokio does not expose whether a thread is one of its workers rather than one of its
+//! `spawn_blocking` threads, which may block, so its tasks are not detected, and neither are those
+//! of other runtimes such as smol.
+
+use std::future::Future;
+
+#[cfg(debug_assertions)]
+use std::{
+    cell::Cell,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+#[cfg(debug_assertions)]
+thread_local! {
+    /// Whether an actor's manage loop is currently being polled on this thread.
+    static IN_ACTOR: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Wraps an actor's manage future such that the current thread is marked as running an actor
+/// while it is being polled. In release builds, this returns the future unchanged.
+#[cfg(debug_assertions)]
+pub(crate) fn mark_actor_thread<F: Future>(fut: F) -> impl Future<Output = F::Output> {
+    MarkActorThread(Box::pin(fut))
+}
+
+/// Wraps an actor's manage future such that the current thread is marked as running an actor
+/// while it is being polled. In release builds, this returns the future unchanged.
+#[cfg(not(debug_assertions))]
+pub(crate) fn mark_actor_thread<F: Future>(fut: F) -> impl Future<Output = F::Output> {
+    fut
+}
+
+/// Panics if called from inside of an actor's manage loop or a task of a detected async runtime on
+/// this thread. `method` is the name of the blocking method, used in the panic message. This does
+/// nothing in release builds.
+#[track_caller]
+pub(crate) fn assert_not_in_actor(method: &str) {
+    #[cfg(debug_assertions)]
+    {
+        if IN_ACTOR.with(Cell::get) {
+            panic!(
+                "`{}` was called from inside of an actor. Blocking the executor thread while \
+                 waiting for an actor can deadlock - use the asynchronous equivalent instead",
+                method
+            );
+        }
+
+        if in_runtime() {
+            panic!(
+                "`{}` was called from inside of an async runtime. Blocking the executor thread \
+                 while waiting for an actor can deadlock - use the asynchronous equivalent instead",
+                method
+            );
+        }
+    }
+
+    #[cfg(not(debug_assertions))]
+    let _ = method;
+}
+
+/// Whether the current thread is running a task of a runtime which can be detected.
+#[cfg(debug_assertions)]
+fn in_runtime() -> bool {
+    #[cfg(feature = "with-async_std-1")]
+    {
+        if async_std::task::try_current().is_some() {
+            return true;
+        }
+    }
+
+    false
+}
+
+#[cfg(debug_assertions)]
+struct MarkActorThread<F>(Pin<Box<F>>);
+
+#[cfg(debug_assertions)]
+impl<F: Future> Future for MarkActorThread<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        // Restores the previous value even if the actor panics, so that a caught panic does not
+        // leave the thread marked
+        struct Reset(bool);
+
+        impl Drop for Reset {
+            fn drop(&mut self) {
+                IN_ACTOR.with(|in_actor| in_actor.set(self.0));
+            }
+        }
+
+        let _reset = Reset(IN_ACTOR.with(|in_actor| in_actor.replace(true)));
+        self.0.as_mut().poll(cx)
+    }
+}
@@ -0,0 +1,100 @@
+//! Coalescing messages replace a message with the same key which is still waiting in the actor's
+//! mailbox, rather than being queued behind it. This suits notifications where only the latest
+//! one matters, such as "config changed" or "refresh view".
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Mutex;
+
+use crate::Message;
+
+/// A message which can be sent with
+/// [`Address::do_send_coalescing`](../address/struct.Address.html#method.do_send_coalescing).
+/// While a message with a given key is waiting in the actor's mailbox, sending another message with
+/// the same key replaces it instead of queueing another message.
+///
+/// # Example
+///
+/// ```
+/// # use xtra::prelude::*;
+/// # use xtra::spawn::Smol;
+/// use xtra::coalesce::Coalesce;
+///
+/// struct Refresh {
+///     view: &'static str,
+///     revision: u32,
+/// }
+///
+/// impl Message for Refresh {
+///     type Result = ();
+/// }
+///
+/// impl Coalesce for Refresh {
+///     type Key = &'static str;
+///
+///     fn key(&self) -> Self::Key {
+///         self.view
+///     }
+/// }
+/// # struct MyActor;
+/// # #[async_trait::async_trait] impl Actor for MyActor {type Stop = (); async fn stopped(self) -> Self::Stop {} }
+/// # #[async_trait::async_trait]
+/// # impl Handler<Refresh> for MyActor {
+/// #     async fn handle(&mut self, _: Refresh, _ctx: &mut Context<Self>) {}
+/// # }
+///
+/// smol::block_on(async {
+///     let addr = MyActor.create(None).spawn(&mut Smol::Global);
+///     for revision in 0..10 {
+///         // Unless the actor keeps up, only the latest revision is handled
+///         addr.do_send_coalescing(Refresh { view: "inbox", revision }).unwrap();
+///     }
+/// })
+/// ```
+pub trait Coalesce: Message {
+    /// The key which identifies messages that replace one another. Use `()` to coalesce all
+    /// messages of this type.
+    type Key: Eq + Hash + Clone + Send + 'static;
+
+    /// Returns the key of this message.
+    fn key(&self) -> Self::Key;
+}
+
+/// The coalescing messages which are waiting in an actor's mailbox, shared between all of its
+/// addresses. The mailbox itself only holds the keys of these messages, which lets a message be
+/// replaced in place without having to search the channel.
+#[derive(Default)]
+pub(crate) struct CoalesceTable {
+    /// A map from the type id of `M` to a `HashMap<M::Key, M>`.
+    pending: Mutex<HashMap<TypeId, Box<dyn Any + Send>>>,
+}
+
+impl CoalesceTable {
+    /// Stores the message, returning its key if no message with the same key was waiting. In that
+    /// case, an envelope with the key must be sent to the actor to pick the message up.
+    pub(crate) fn insert<M: Coalesce>(&self, message: M) -> Option<M::Key> {
+        let key = message.key();
+        let mut pending = self.pending.lock().unwrap();
+        let messages = pending
+            .entry(TypeId::of::<M>())
+            .or_insert_with(|| Box::new(HashMap::<M::Key, M>::new()))
+            .downcast_mut::<HashMap<M::Key, M>>()
+            .unwrap();
+
+        match messages.insert(key.clone(), message) {
+            Some(_replaced) => None,
+            None => Some(key),
+        }
+    }
+
+    /// Takes the latest message with the given key out of the table.
+    pub(crate) fn remove<M: Coalesce>(&self, key: &M::Key) -> Option<M> {
+        self.pending
+            .lock()
+            .unwrap()
+            .get_mut(&TypeId::of::<M>())
+            .and_then(|messages| messages.downcast_mut::<HashMap<M::Key, M>>())
+            .and_then(|messages| messages.remove(key))
+    }
+}
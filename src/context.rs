@@ -1,21 +1,42 @@
+use std::any::{Any, TypeId};
+use std::collections::{HashMap, VecDeque};
 use std::fmt;
 use std::fmt::{Display, Formatter};
 use std::future::Future;
 use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
 
-use flume::{Receiver, Sender};
+use flume::Sender;
 use futures_util::future::{self, Either};
 use futures_util::FutureExt;
 
 #[cfg(feature = "timing")]
-use {futures_timer::Delay, std::time::Duration};
+use {
+    crate::envelope::TickEnvelope,
+    crate::schedule::{self, CronSchedule, Schedule},
+    crate::spawn::Spawner,
+    crate::timer::{self, Interval, QueuedTickPolicy, Timer, TimerHandle},
+    futures_core::future::BoxFuture,
+    futures_timer::Delay,
+    std::sync::Mutex,
+    std::task::Poll,
+    std::time::{Duration, SystemTime},
+};
 
+use crate::behaviour::{Behaviour, Rejected, Rule};
+use crate::coalesce::CoalesceTable;
 use crate::drop_notice::DropNotifier;
-use crate::envelope::{MessageEnvelope, NonReturningEnvelope};
+use crate::envelope::{LimitedEnvelope, MessageEnvelope, NonReturningEnvelope};
+use crate::intercept::Interceptors;
+use crate::limit::Limiter;
+use crate::mailbox::Mailbox;
 use crate::manager::{AddressMessage, BroadcastMessage, ContinueManageLoop};
+use crate::middleware::Middleware;
+use crate::persistence::{Persistence, Recovery};
+use crate::pool::EnvelopePool;
 use crate::refcount::{RefCounter, Strong, Weak};
-use crate::{Actor, Address, Handler, KeepRunning, Message};
+use crate::snapshot::Snapshots;
+use crate::{Actor, Address, KeepRunning, Message, NativeHandler};
 
 /// `Context` is used to control how the actor is managed and to get the actor's address from inside
 /// of a message handler.
@@ -29,14 +50,61 @@ pub struct Context<A> {
     broadcaster: barrage::Sender<BroadcastMessage<A>>,
     /// Kept by the context to allow for it to check how many strong addresses exist to the actor
     ref_counter: Weak,
-    /// Notifications that must be stored for immediate processing.
-    self_notifications: Vec<Box<dyn MessageEnvelope<Actor = A>>>,
-    receiver: Receiver<AddressMessage<A>>,
+    /// Coalescing messages waiting in the mailbox, kept by the context to allow for the
+    /// `Context::address` method to work
+    coalesced: Arc<CoalesceTable>,
+    /// The limits on admitted messages, kept by the context to allow for the `Context::address`
+    /// and `Context::set_limits` methods to work
+    pub(crate) limiter: Arc<Limiter>,
+    /// The envelopes of handled messages, kept by the context to allow for the `Context::address`
+    /// method to work
+    pub(crate) envelopes: Arc<EnvelopePool>,
+    /// Timers started with `Context::start_timer`, by key
+    #[cfg(feature = "timing")]
+    timers: HashMap<String, TimerHandle>,
+    /// Notifications that must be stored for immediate processing, in the order they are handled.
+    self_notifications: VecDeque<Box<dyn MessageEnvelope<Actor = A>>>,
+    /// Notifications made with `Context::notify_deferred`, each with the value which `handled`
+    /// must reach before it is due.
+    deferred: VecDeque<(u64, Box<dyn MessageEnvelope<Actor = A>>)>,
+    /// How many messages this context has taken from the mailbox.
+    handled: u64,
+    /// How many self notifications may be handled in a row while messages are waiting in the
+    /// mailbox.
+    notification_limit: usize,
+    /// A message taken from the mailbox while merging batches, which must be handled next.
+    pending: Option<AddressMessage<A>>,
+    /// The message stashed by the handler which is currently running, if any. It is kept apart
+    /// until the handler finishes, so that its envelope can re-attach the reply channel.
+    stashing: Option<Stashing<A>>,
+    /// Messages postponed with `Context::stash`, in the order they were stashed.
+    stash: VecDeque<Box<dyn MessageEnvelope<Actor = A>>>,
+    /// Messages released by `Context::unstash_all`, which are handled before the mailbox.
+    unstashed: VecDeque<Box<dyn MessageEnvelope<Actor = A>>>,
+    /// The stack of behaviours set with `Context::set_behaviour` and `Context::push_behaviour`. The
+    /// last one is current, and if there are none, every message is accepted.
+    behaviours: Vec<Behaviour>,
+    /// Values kept on behalf of the modules which build on the context, such as the state
+    /// generation of an `FsmActor`, one for each type. These all need the `timing` feature so far.
+    #[cfg_attr(not(feature = "timing"), allow(dead_code))]
+    extensions: HashMap<TypeId, Box<dyn Any + Send>>,
+    /// The journal of a `PersistentActor`, once it has been recovered.
+    pub(crate) persistence: Option<Persistence>,
+    /// Recovers a `PersistentActor` before it starts, once persistence has been enabled.
+    pub(crate) recovery: Option<Recovery<A>>,
+    /// Whether the actor persists events, once persistence has been enabled or recovery has begun.
+    pub(crate) persistent: bool,
+    /// The snapshot hooks of a `SnapshotActor`, once snapshots have been enabled.
+    pub(crate) snapshots: Option<Snapshots<A>>,
+    /// Middleware added with `Context::add_middleware`, in the order it runs.
+    pub(crate) middleware: Arc<[Arc<dyn Middleware>]>,
+    pub(crate) receiver: Mailbox<A>,
     broadcast_receiver: barrage::SharedReceiver<BroadcastMessage<A>>,
     /// Shared between all contexts on the same address
     shared_drop_notifier: Arc<DropNotifier>,
     /// Activates when this context is dropped. Used in [`Context::notify_interval`] and [`Context::notify_after`]
     /// to shutdown the tasks as soon as the context stops.
+    #[cfg(feature = "timing")]
     drop_notifier: DropNotifier,
 }
 
@@ -83,12 +151,29 @@ impl<A: Actor> Context<A> {
 
         let shared_drop_notifier = Arc::new(DropNotifier::new());
 
-        let strong = Strong::new(AtomicBool::new(true), shared_drop_notifier.subscribe());
+        // The actor is notified once the last strong address (or sink) has been dropped, so that it
+        // can check whether it should stop.
+        let last_address_sender = sender.clone();
+        let strong = Strong::new(
+            AtomicBool::new(true),
+            shared_drop_notifier.subscribe(),
+            move || {
+                let _ = last_address_sender.send(AddressMessage::LastAddress);
+            },
+        );
         let weak = strong.downgrade();
 
+        let coalesced = Arc::new(CoalesceTable::default());
+        let limiter = Arc::new(Limiter::new());
+        let envelopes = Arc::new(EnvelopePool::default());
+
         let addr = Address {
             sender: sender.clone(),
             ref_counter: strong,
+            coalesced: coalesced.clone(),
+            limiter: limiter.clone(),
+            envelopes: envelopes.clone(),
+            interceptors: Interceptors::default(),
         };
 
         let context = Context {
@@ -96,10 +181,30 @@ impl<A: Actor> Context<A> {
             sender,
             broadcaster,
             ref_counter: weak,
-            self_notifications: Vec::new(),
-            receiver,
+            coalesced,
+            limiter,
+            envelopes,
+            #[cfg(feature = "timing")]
+            timers: HashMap::new(),
+            self_notifications: VecDeque::new(),
+            deferred: VecDeque::new(),
+            handled: 0,
+            notification_limit: DEFAULT_NOTIFICATION_LIMIT,
+            pending: None,
+            stashing: None,
+            stash: VecDeque::new(),
+            unstashed: VecDeque::new(),
+            behaviours: Vec::new(),
+            extensions: HashMap::new(),
+            persistence: None,
+            recovery: None,
+            persistent: false,
+            snapshots: None,
+            middleware: Arc::new([]),
+            receiver: Mailbox::new(receiver),
             broadcast_receiver: broadcast_rx.into_shared(),
             shared_drop_notifier,
+            #[cfg(feature = "timing")]
             drop_notifier: DropNotifier::new(),
         };
         (addr, context)
@@ -117,10 +222,30 @@ impl<A: Actor> Context<A> {
             sender: self.sender.clone(),
             broadcaster: self.broadcaster.clone(),
             ref_counter: self.ref_counter.clone(),
-            self_notifications: Vec::new(),
+            coalesced: self.coalesced.clone(),
+            limiter: self.limiter.clone(),
+            envelopes: self.envelopes.clone(),
+            #[cfg(feature = "timing")]
+            timers: HashMap::new(),
+            self_notifications: VecDeque::new(),
+            deferred: VecDeque::new(),
+            handled: 0,
+            notification_limit: DEFAULT_NOTIFICATION_LIMIT,
+            pending: None,
+            stashing: None,
+            stash: VecDeque::new(),
+            unstashed: VecDeque::new(),
+            behaviours: Vec::new(),
+            extensions: HashMap::new(),
+            persistence: None,
+            recovery: None,
+            persistent: false,
+            snapshots: None,
+            middleware: Arc::new([]),
             receiver: self.receiver.clone(),
             broadcast_receiver,
             shared_drop_notifier: self.shared_drop_notifier.clone(),
+            #[cfg(feature = "timing")]
             drop_notifier: DropNotifier::new(),
         };
         ctx.run(actor)
@@ -137,6 +262,10 @@ impl<A: Actor> Context<A> {
         Ok(Address {
             sender: self.sender.clone(),
             ref_counter: self.ref_counter.upgrade().ok_or(ActorShutdown)?,
+            coalesced: self.coalesced.clone(),
+            limiter: self.limiter.clone(),
+            envelopes: self.envelopes.clone(),
+            interceptors: Interceptors::default(),
         })
     }
 
@@ -181,38 +310,99 @@ impl<A: Actor> Context<A> {
 
     /// Handles a single self notification, returning whether to continue the manage loop
     async fn handle_self_notification(&mut self, actor: &mut A) -> Option<bool> {
-        if let Some(notification) = self.self_notifications.pop() {
-            notification.handle(actor, self).await;
+        if let Some(notification) = self.self_notifications.pop_front() {
+            if let Some(notification) = self.admit(notification) {
+                notification.handle(actor, self).await;
+                self.stash_unclaimed();
+            }
             return Some(self.check_running(actor).await);
         }
         None
     }
 
+    /// Handles the messages released by `Context::unstash_all`, along with any self notifications
+    /// they cause, returning whether to continue the manage loop
+    async fn handle_unstashed(&mut self, actor: &mut A) -> bool {
+        while let Some(envelope) = self.unstashed.pop_front() {
+            let envelope = match self.admit(envelope) {
+                Some(envelope) => envelope,
+                None => continue,
+            };
+
+            // Stashed messages were released from the concurrency limit, so they are in flight
+            // again until they have been handled
+            let envelope = LimitedEnvelope::wrap(envelope, self.limiter.readmit());
+            envelope.handle(actor, self).await;
+            self.stash_unclaimed();
+
+            if !self.check_running(actor).await || !self.handle_self_notifications(actor).await {
+                return false;
+            }
+        }
+
+        true
+    }
+
     /// Handle all self notifications, returning whether to continue the manage loop
     async fn handle_self_notifications(&mut self, actor: &mut A) -> bool {
-        while let Some(continue_running) = self.handle_self_notification(actor).await {
-            if !continue_running {
-                return false;
+        self.release_deferred();
+
+        // Once the limit is reached, the rest wait until a message from the mailbox is handled
+        let mut handled = 0;
+        while handled < self.notification_limit || self.mailbox_is_empty() {
+            match self.handle_self_notification(actor).await {
+                Some(true) => handled += 1,
+                Some(false) => return false,
+                None => break,
             }
         }
 
         true
     }
 
+    /// Queues the deferred notifications which are due, which is once the messages that were in the
+    /// mailbox when they were made have been handled, or once the mailbox is empty.
+    fn release_deferred(&mut self) {
+        while let Some((due, _)) = self.deferred.front() {
+            if *due > self.handled && !self.mailbox_is_empty() {
+                break;
+            }
+
+            if let Some((_, notification)) = self.deferred.pop_front() {
+                self.self_notifications.push_back(notification);
+            }
+        }
+    }
+
     /// Run the given actor's main loop, handling incoming messages to its mailbox.
-    pub async fn run(mut self, mut actor: A) -> A::Stop {
+    pub fn run(self, actor: A) -> impl Future<Output = A::Stop> {
+        crate::blocking::mark_actor_thread(self.manage(actor))
+    }
+
+    async fn manage(mut self, mut actor: A) -> A::Stop {
+        self.restore_snapshot(&mut actor).await;
+        // An actor which could not be recovered would run with the wrong state
+        if !self.recover_persistence(&mut actor).await {
+            self.stop_all();
+            return actor.stopped().await;
+        }
         actor.started(&mut self).await;
 
         // Idk why anyone would do this, but we have to check that they didn't do ctx.stop()
         // in the started method, otherwise it would kinda be a bug
         if !self.check_running(&mut actor).await {
             self.stop_all();
-            return actor.stopped().await;
+            return self.stopped(actor).await;
         }
 
         // Similar to above
         if let Some(BroadcastMessage::Shutdown) = self.broadcast_receiver.try_recv().unwrap() {
-            return actor.stopped().await;
+            return self.stopped(actor).await;
+        }
+
+        // Handle any notifications made in the started method
+        if !self.handle_self_notifications(&mut actor).await {
+            return self.stopped(actor).await;
         }
 
         // Listen for any messages for the ActorManager
@@ -223,6 +413,16 @@ impl<A: Actor> Context<A> {
         let mut broadcast_recv = broadcast_rx.recv_async();
 
         loop {
+            // Notifications which were held back by the notification limit, or deferred behind
+            // messages which another actor on the address took, are handled once the mailbox is
+            // empty
+            if (!self.self_notifications.is_empty() || !self.deferred.is_empty())
+                && self.mailbox_is_empty()
+                && !self.handle_self_notifications(&mut actor).await
+            {
+                return self.stopped(actor).await;
+            }
+
             let next = future::select(addr_recv, broadcast_recv).await;
 
             let msg = match next {
@@ -243,7 +443,7 @@ impl<A: Actor> Context<A> {
                 match self.tick(Either::Left(broadcast), &mut actor).await {
                     ContinueManageLoop::Yes => {}
                     ContinueManageLoop::ExitImmediately => {
-                        return actor.stopped().await;
+                        return self.stopped(actor).await;
                     }
                 }
             }
@@ -251,12 +451,28 @@ impl<A: Actor> Context<A> {
             match self.tick(msg, &mut actor).await {
                 ContinueManageLoop::Yes => {}
                 ContinueManageLoop::ExitImmediately => {
-                    return actor.stopped().await;
+                    return self.stopped(actor).await;
+                }
+            }
+
+            // Handle the message which was taken from the mailbox while merging batches, if any
+            while let Some(pending) = self.pending.take() {
+                match self.tick(Either::Right(pending), &mut actor).await {
+                    ContinueManageLoop::Yes => {}
+                    ContinueManageLoop::ExitImmediately => {
+                        return self.stopped(actor).await;
+                    }
                 }
             }
         }
     }
 
+    /// Takes a final snapshot of the actor, if snapshots are enabled, and then stops it.
+    async fn stopped(&mut self, mut actor: A) -> A::Stop {
+        self.save_snapshot(&mut actor).await;
+        actor.stopped().await
+    }
+
     /// Handle a message and immediate notifications, returning whether to exit from the manage loop
     /// or not.
     async fn tick(
@@ -264,64 +480,42 @@ impl<A: Actor> Context<A> {
         msg: Either<BroadcastMessage<A>, AddressMessage<A>>,
         actor: &mut A,
     ) -> ContinueManageLoop {
+        if let Either::Right(_) = msg {
+            self.handled += 1;
+            self.limiter.notify_received();
+        }
+        let is_message = matches!(
+            msg,
+            Either::Left(BroadcastMessage::Message(_)) | Either::Right(AddressMessage::Message(_))
+        );
+
         match msg {
             Either::Left(BroadcastMessage::Message(msg)) => {
-                let mut time_spent = 0;
-                let sleep = 10;
-                let msg_str = msg.name();
-                let mut msg_handler = msg.handle(actor, self);
-
-                loop {
-                    match future::select(msg_handler, Delay::new(Duration::from_secs(sleep))).await
-                    {
-                        Either::Left(((), _)) => break,
-                        Either::Right(((), unfinished_handler)) => {
-                            time_spent += sleep;
-
-                            let actor_name = std::any::type_name::<A>();
-
-                            log::warn!(
-                                "Actor {} has been processing message {} for {} seconds",
-                                actor_name,
-                                msg_str,
-                                time_spent
-                            );
-
-                            msg_handler = unfinished_handler;
-                        }
+                // Broadcasts cannot be stashed or replied to, so they are dropped unless accepted
+                if let Some(behaviour) = self.behaviours.last() {
+                    if !matches!(behaviour.rule(msg.message_type()), Rule::Accept) {
+                        return ContinueManageLoop::Yes;
                     }
                 }
+
+                let msg_str = msg.name();
+                let msg_handler = msg.handle(actor, self);
+                handle_warning_if_slow(msg_handler, std::any::type_name::<A>(), msg_str).await;
             }
             Either::Left(BroadcastMessage::Shutdown) => {
                 self.running = RunningState::Stopped;
                 return ContinueManageLoop::ExitImmediately;
             }
             Either::Right(AddressMessage::Message(msg)) => {
-                let mut time_spent = 0;
-                let sleep = 10;
-                let msg_str = msg.name();
-                let mut msg_handler = msg.handle(actor, self);
-
-                loop {
-                    match future::select(msg_handler, Delay::new(Duration::from_secs(sleep))).await
-                    {
-                        Either::Left(((), _)) => break,
-                        Either::Right(((), unfinished_handler)) => {
-                            time_spent += sleep;
-
-                            let actor_name = std::any::type_name::<A>();
+                let mut msg = match self.admit(msg) {
+                    Some(msg) => msg,
+                    None => return ContinueManageLoop::Yes,
+                };
+                self.merge_batches(&mut msg);
 
-                            log::warn!(
-                                "Actor {} has been processing message {} for {} seconds",
-                                actor_name,
-                                msg_str,
-                                time_spent
-                            );
-
-                            msg_handler = unfinished_handler;
-                        }
-                    }
-                }
+                let msg_str = msg.name();
+                let msg_handler = msg.handle(actor, self);
+                handle_warning_if_slow(msg_handler, std::any::type_name::<A>(), msg_str).await;
             }
             Either::Right(AddressMessage::LastAddress) => {
                 if self.ref_counter.strong_count() == 0 {
@@ -332,16 +526,59 @@ impl<A: Actor> Context<A> {
             }
         }
 
+        self.stash_unclaimed();
+        if is_message {
+            self.count_snapshot_message(actor).await;
+        }
+
         if !self.check_running(actor).await {
             return ContinueManageLoop::ExitImmediately;
         }
         if !self.handle_self_notifications(actor).await {
             return ContinueManageLoop::ExitImmediately;
         }
+        if !self.handle_unstashed(actor).await {
+            return ContinueManageLoop::ExitImmediately;
+        }
 
         ContinueManageLoop::Yes
     }
 
+    /// Merges batches and single `do_send` messages of the same type which are queued directly
+    /// behind the given envelope into it, so that they are handled together. The first message
+    /// which cannot be merged is kept to be handled next.
+    fn merge_batches(&mut self, envelope: &mut Box<dyn MessageEnvelope<Actor = A>>) {
+        if envelope.batch_mut().is_none() {
+            return;
+        }
+
+        while self.pending.is_none() {
+            match self.receiver.try_recv() {
+                Ok(AddressMessage::Message(next)) => match envelope.merge_batch(next) {
+                    Ok(()) => {
+                        self.handled += 1;
+                        self.limiter.notify_received();
+                    }
+                    Err(next) => self.pending = Some(AddressMessage::Message(next)),
+                },
+                Ok(msg) => self.pending = Some(msg),
+                Err(_) => break,
+            }
+        }
+    }
+
+    /// Returns how many messages are waiting to be handled, including the one which was taken from
+    /// the mailbox while merging batches.
+    fn mailbox_len(&self) -> usize {
+        self.receiver.len() + self.pending.is_some() as usize
+    }
+
+    /// Returns whether no messages are waiting to be handled, including the one which was taken
+    /// from the mailbox while merging batches.
+    fn mailbox_is_empty(&self) -> bool {
+        self.pending.is_none() && self.receiver.is_empty()
+    }
+
     /// This is a combinator to avoid holding !Sync references across await points
     fn recv_once(
         &self,
@@ -365,6 +602,22 @@ impl<A: Actor> Context<A> {
             return;
         }
 
+        if let Some(envelope) = self.unstashed.pop_front() {
+            if let Some(envelope) = self.admit(envelope) {
+                envelope.handle(act, self).await;
+                self.stash_unclaimed();
+            }
+            if !self.check_running(act).await {
+                self.stop();
+            }
+            return;
+        }
+
+        if let Some(pending) = self.pending.take() {
+            self.tick(Either::Right(pending), act).await;
+            return;
+        }
+
         self.tick(self.recv_once().await, act).await;
     }
 
@@ -419,6 +672,9 @@ impl<A: Actor> Context<A> {
             }
 
             self.tick(msg, actor).await;
+            while let Some(pending) = self.pending.take() {
+                self.tick(Either::Right(pending), actor).await;
+            }
             fut = unfinished;
         }
     }
@@ -427,13 +683,260 @@ impl<A: Actor> Context<A> {
     /// queue are processed (therefore, immediately). If multiple `notify` messages are queued,
     /// they will still be processed in the order that they are queued (i.e the immediate priority
     /// is only over other messages).
+    ///
+    /// So that a handler which keeps notifying the actor cannot starve the mailbox, a message from
+    /// the mailbox is handled after every so many notifications in a row. See
+    /// [`Context::set_notification_limit`](struct.Context.html#method.set_notification_limit).
     pub fn notify<M>(&mut self, msg: M)
     where
         M: Message,
-        A: Handler<M>,
+        A: NativeHandler<M>,
+    {
+        let envelope = NonReturningEnvelope::<A, M>::pooled(msg, &self.envelopes);
+        self.self_notifications.push_back(envelope);
+    }
+
+    /// Notify this actor with a message that is handled before any other notifications which are
+    /// queued, as well as before any messages from the mailbox.
+    pub fn notify_front<M>(&mut self, msg: M)
+    where
+        M: Message,
+        A: NativeHandler<M>,
     {
-        let envelope = Box::new(NonReturningEnvelope::<A, M>::new(msg));
-        self.self_notifications.push(envelope);
+        let envelope = NonReturningEnvelope::<A, M>::pooled(msg, &self.envelopes);
+        self.self_notifications.push_front(envelope);
+    }
+
+    /// Notify this actor with a message that is handled after the messages which are currently in
+    /// its mailbox, but before any which arrive later. Unlike sending the message to the actor's
+    /// own address, this never waits for space in the mailbox.
+    pub fn notify_deferred<M>(&mut self, msg: M)
+    where
+        M: Message,
+        A: NativeHandler<M>,
+    {
+        let envelope = NonReturningEnvelope::<A, M>::pooled(msg, &self.envelopes);
+        let due = self.handled + self.mailbox_len() as u64;
+        self.deferred.push_back((due, envelope));
+    }
+
+    /// Sets how many self notifications may be handled in a row while messages are waiting in the
+    /// mailbox. Once the limit is reached, a message from the mailbox is handled before the
+    /// remaining notifications. The default is 64.
+    pub fn set_notification_limit(&mut self, limit: usize) {
+        self.notification_limit = limit.max(1);
+    }
+
+    /// Postpone the message which is being handled, to be handled again once
+    /// [`Context::unstash_all`](struct.Context.html#method.unstash_all) is called. This lets an
+    /// actor which is not ready for some messages yet, such as while it is initialising, put them
+    /// aside without losing their order. If the message was sent with
+    /// [`Address::send`](address/struct.Address.html#method.send), the handler's return value is
+    /// discarded, and the sender instead receives the result of handling the message once it is
+    /// replayed. Stashed messages are dropped if the actor stops.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use xtra::prelude::*;
+    /// # struct MyActor { ready: bool }
+    /// # #[async_trait::async_trait] impl Actor for MyActor {type Stop = (); async fn stopped(self) -> Self::Stop {} }
+    /// struct Query;
+    ///
+    /// impl Message for Query {
+    ///     type Result = u32;
+    /// }
+    ///
+    /// struct Ready;
+    ///
+    /// impl Message for Ready {
+    ///     type Result = ();
+    /// }
+    ///
+    /// #[async_trait::async_trait]
+    /// impl Handler<Query> for MyActor {
+    ///     async fn handle(&mut self, query: Query, ctx: &mut Context<Self>) -> u32 {
+    ///         if !self.ready {
+    ///             ctx.stash(query);
+    ///             return 0; // Discarded, as the query will be answered once it is replayed
+    ///         }
+    ///
+    ///         42
+    ///     }
+    /// }
+    ///
+    /// #[async_trait::async_trait]
+    /// impl Handler<Ready> for MyActor {
+    ///     async fn handle(&mut self, _: Ready, ctx: &mut Context<Self>) {
+    ///         self.ready = true;
+    ///         ctx.unstash_all();
+    ///     }
+    /// }
+    /// ```
+    pub fn stash<M>(&mut self, message: M)
+    where
+        M: Message,
+        A: NativeHandler<M>,
+    {
+        self.stash_unclaimed();
+        self.stashing = Some(Stashing {
+            message: Box::new(message),
+            wrap: wrap_stashed::<A, M>,
+        });
+    }
+
+    /// Replay all messages postponed with [`Context::stash`](struct.Context.html#method.stash), in
+    /// the order that they were stashed. They are handled once the current handler has finished,
+    /// before any self notifications made after this call and before any more messages from the
+    /// mailbox.
+    pub fn unstash_all(&mut self) {
+        self.stash_unclaimed();
+        self.unstashed.append(&mut self.stash);
+    }
+
+    /// Returns how many messages are stashed.
+    pub fn stashed(&self) -> usize {
+        self.stash.len() + self.stashing.is_some() as usize
+    }
+
+    /// Switch to the given behaviour, replacing the current one. From now on, messages which the
+    /// behaviour does not accept are not handled, but stashed, dropped or rejected as it decides.
+    /// Any stashed messages are replayed, so that those which the new behaviour accepts are handled
+    /// before any more messages from the mailbox.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use xtra::prelude::*;
+    /// use xtra::behaviour::Behaviour;
+    /// # struct Connection;
+    /// # #[async_trait::async_trait] impl Handler<Send> for Connection { async fn handle(&mut self, _: Send, _ctx: &mut Context<Self>) {} }
+    ///
+    /// struct Connected;
+    ///
+    /// impl Message for Connected {
+    ///     type Result = ();
+    /// }
+    ///
+    /// struct Send(Vec<u8>);
+    ///
+    /// impl Message for Send {
+    ///     type Result = ();
+    /// }
+    ///
+    /// #[async_trait::async_trait]
+    /// impl Actor for Connection {
+    ///     type Stop = ();
+    ///
+    ///     async fn started(&mut self, ctx: &mut Context<Self>) {
+    ///         // Sends are stashed until the connection is established
+    ///         ctx.set_behaviour(Behaviour::new("connecting").accept::<Connected>());
+    ///     }
+    ///
+    ///     async fn stopped(self) {}
+    /// }
+    ///
+    /// #[async_trait::async_trait]
+    /// impl Handler<Connected> for Connection {
+    ///     async fn handle(&mut self, _: Connected, ctx: &mut Context<Self>) {
+    ///         ctx.set_behaviour(Behaviour::new("connected").accept::<Send>());
+    ///     }
+    /// }
+    /// ```
+    pub fn set_behaviour(&mut self, behaviour: Behaviour) {
+        self.behaviours.pop();
+        self.push_behaviour(behaviour);
+    }
+
+    /// Switch to the given behaviour, keeping the current one to return to with
+    /// [`Context::pop_behaviour`](struct.Context.html#method.pop_behaviour). Otherwise, this is
+    /// like [`Context::set_behaviour`](struct.Context.html#method.set_behaviour).
+    pub fn push_behaviour(&mut self, behaviour: Behaviour) {
+        self.behaviours.push(behaviour);
+        self.unstash_all();
+    }
+
+    /// Return to the behaviour which was current before the last call to
+    /// [`Context::push_behaviour`](struct.Context.html#method.push_behaviour), returning the
+    /// behaviour which was left. If there is no previous behaviour, every message is accepted
+    /// again. Any stashed messages are replayed.
+    pub fn pop_behaviour(&mut self) -> Option<Behaviour> {
+        let behaviour = self.behaviours.pop();
+        self.unstash_all();
+        behaviour
+    }
+
+    /// Returns the current behaviour, or `None` if every message is accepted.
+    pub fn behaviour(&self) -> Option<&Behaviour> {
+        self.behaviours.last()
+    }
+
+    /// Applies the current behaviour to an envelope, returning it if it should be handled now.
+    fn admit(
+        &mut self,
+        envelope: Box<dyn MessageEnvelope<Actor = A>>,
+    ) -> Option<Box<dyn MessageEnvelope<Actor = A>>> {
+        let behaviour = match self.behaviours.last() {
+            Some(behaviour) => behaviour,
+            None => return Some(envelope),
+        };
+
+        match behaviour.rule(envelope.message_type()) {
+            Rule::Accept => return Some(envelope),
+            Rule::Stash => {
+                // A stashed message no longer counts against the concurrency limit, as otherwise
+                // it could hold back the message which the actor is waiting for to unstash it
+                let mut envelope = envelope;
+                drop(envelope.take_permit());
+                self.stash.push_back(envelope);
+            }
+            Rule::Drop => envelope.discard(),
+            Rule::Reject(make_result) => {
+                let rejected = Rejected {
+                    behaviour: behaviour.name(),
+                };
+                envelope.reject(make_result(rejected));
+            }
+        }
+
+        None
+    }
+
+    /// Takes the message stashed by the current handler if it is of type `M`, so that its envelope
+    /// can stash it along with its reply channel.
+    pub(crate) fn take_stashed<M: Message>(&mut self) -> Option<M> {
+        let Stashing { message, wrap } = self.stashing.take()?;
+        match message.downcast::<M>() {
+            Ok(message) => Some(*message),
+            Err(message) => {
+                self.stashing = Some(Stashing { message, wrap });
+                None
+            }
+        }
+    }
+
+    /// Returns the value of type `T` kept in this context on behalf of another module, inserting
+    /// the default value if there is none yet.
+    #[cfg_attr(not(feature = "timing"), allow(dead_code))]
+    pub(crate) fn extension_mut<T: Default + Send + 'static>(&mut self) -> &mut T {
+        self.extensions
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(T::default()))
+            .downcast_mut()
+            .expect("extensions are keyed by their type")
+    }
+
+    /// Adds an envelope to the back of the stash.
+    pub(crate) fn stash_envelope(&mut self, envelope: Box<dyn MessageEnvelope<Actor = A>>) {
+        self.stash.push_back(envelope);
+    }
+
+    /// Stashes the message stashed by the last handler without a reply channel, if its envelope did
+    /// not take it.
+    fn stash_unclaimed(&mut self) {
+        if let Some(Stashing { message, wrap }) = self.stashing.take() {
+            self.stash.push_back(wrap(message));
+        }
     }
 
     /// Notify all actors on this address with a given message, in a broadcast fashion. The message
@@ -442,7 +945,7 @@ impl<A: Actor> Context<A> {
     pub fn notify_all<M>(&mut self, msg: M)
     where
         M: Message + Clone + Sync,
-        A: Handler<M>,
+        A: NativeHandler<M>,
     {
         let envelope = NonReturningEnvelope::<A, M>::new(msg);
         let _ = self
@@ -453,68 +956,389 @@ impl<A: Actor> Context<A> {
     /// Notify the actor with a message every interval until it is stopped (either directly with
     /// [`Context::stop`](struct.Context.html#method.stop), or for a lack of strong
     /// [`Address`es](address/struct.Address.html)). This does not take priority over other messages.
+    ///
+    /// Each tick is due `duration` after the previous one was delivered, and is queued behind any
+    /// earlier ticks which are still in the mailbox. Use
+    /// [`Context::notify_interval_with`](struct.Context.html#method.notify_interval_with) to tick at
+    /// a fixed rate instead, or to skip or coalesce queued ticks.
+    ///
+    /// The returned [`Timer`](timer/struct.Timer.html) must be spawned in order to run, and can be
+    /// cancelled or reset through its [`TimerHandle`](timer/struct.TimerHandle.html).
     #[cfg(feature = "timing")]
     pub fn notify_interval<F, M>(
         &mut self,
         duration: Duration,
         constructor: F,
-    ) -> Result<impl Future<Output = ()>, ActorShutdown>
+    ) -> Result<Timer, ActorShutdown>
+    where
+        F: Send + 'static + Fn() -> M,
+        M: Message,
+        A: NativeHandler<M>,
+    {
+        self.notify_interval_with(Interval::fixed_delay(duration), constructor)
+    }
+
+    /// Notify the actor with a message at the given [`Interval`](timer/struct.Interval.html) until
+    /// it is stopped. The interval sets whether the ticks are at a fixed rate or a fixed delay
+    /// apart, and what happens when a tick is due while the previous one is still waiting in the
+    /// mailbox. Ticks are sent without blocking, so a full mailbox holds up only this timer. This
+    /// does not take priority over other messages.
+    ///
+    /// The returned [`Timer`](timer/struct.Timer.html) must be spawned in order to run, and can be
+    /// cancelled or reset through its [`TimerHandle`](timer/struct.TimerHandle.html).
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use xtra::prelude::*;
+    /// # use xtra::spawn::Smol;
+    /// # use std::time::Duration;
+    /// use xtra::timer::{Interval, QueuedTickPolicy};
+    /// # struct MyActor;
+    /// # #[async_trait::async_trait] impl Handler<Heartbeat> for MyActor { async fn handle(&mut self, _: Heartbeat, _ctx: &mut Context<Self>) {} }
+    ///
+    /// struct Heartbeat;
+    ///
+    /// impl Message for Heartbeat {
+    ///     type Result = ();
+    /// }
+    ///
+    /// #[async_trait::async_trait]
+    /// impl Actor for MyActor {
+    ///     type Stop = ();
+    ///
+    ///     async fn started(&mut self, ctx: &mut Context<Self>) {
+    ///         // A heartbeat every second on the second, which never piles up if the actor is busy
+    ///         let interval = Interval::fixed_rate(Duration::from_secs(1))
+    ///             .queued_ticks(QueuedTickPolicy::Skip);
+    ///         if let Ok(timer) = ctx.notify_interval_with(interval, || Heartbeat) {
+    ///             ctx.start_timer("heartbeat", timer, &mut Smol::Global);
+    ///         }
+    ///     }
+    ///
+    ///     async fn stopped(self) {}
+    /// }
+    /// ```
+    #[cfg(feature = "timing")]
+    pub fn notify_interval_with<F, M>(
+        &mut self,
+        interval: Interval,
+        constructor: F,
+    ) -> Result<Timer, ActorShutdown>
     where
         F: Send + 'static + Fn() -> M,
         M: Message,
-        A: Handler<M>,
+        A: NativeHandler<M>,
     {
         let addr = self.address()?.downgrade();
-        let mut stopped = self.drop_notifier.subscribe();
-
-        let fut = async move {
-            loop {
-                let delay = Delay::new(duration);
-                match future::select(delay, &mut stopped).await {
-                    Either::Left(_) => {
-                        if addr.do_send(constructor()).is_err() {
-                            break;
+        let stopped = self.drop_notifier.subscribe();
+        let Interval {
+            period,
+            mode,
+            queued_ticks,
+        } = interval;
+        // The message of the tick which is waiting in the mailbox, if any
+        let queued = Arc::new(Mutex::new(None));
+
+        Ok(Timer::new(move |commands| {
+            Box::pin(timer::run(
+                commands,
+                period,
+                stopped,
+                Some(mode),
+                move || {
+                    let message = constructor();
+                    let envelope: Box<dyn MessageEnvelope<Actor = A>> = match queued_ticks {
+                        QueuedTickPolicy::Queue => {
+                            Box::new(NonReturningEnvelope::<A, M>::new(message))
                         }
-                    }
-                    Either::Right(_) => {
-                        // Context stopped before the end of the delay was reached
-                        break;
-                    }
-                }
-            }
-        };
+                        policy => {
+                            let mut queued_message = queued.lock().unwrap();
+                            if queued_message.is_some() {
+                                if policy == QueuedTickPolicy::Coalesce {
+                                    *queued_message = Some(message);
+                                }
+                                return Either::Left(future::ready(true));
+                            }
+
+                            *queued_message = Some(message);
+                            Box::new(TickEnvelope::<A, M>::new(queued.clone()))
+                        }
+                    };
 
-        Ok(fut)
+                    Either::Right(addr.do_send_envelope_async(envelope).map(|res| res.is_ok()))
+                },
+            ))
+        }))
     }
 
     /// Notify the actor with a message after a certain duration has elapsed. This does not take
     /// priority over other messages.
+    ///
+    /// The returned [`Timer`](timer/struct.Timer.html) must be spawned in order to run, and can be
+    /// cancelled or reset through its [`TimerHandle`](timer/struct.TimerHandle.html).
+    #[cfg(feature = "timing")]
     pub fn notify_after<M>(
         &mut self,
         duration: Duration,
         notification: M,
-    ) -> Result<impl Future<Output = ()>, ActorShutdown>
+    ) -> Result<Timer, ActorShutdown>
     where
         M: Message,
-        A: Handler<M>,
+        A: NativeHandler<M>,
     {
         let addr = self.address()?.downgrade();
-        let mut stopped = self.drop_notifier.subscribe();
+        let stopped = self.drop_notifier.subscribe();
+        let mut notification = Some(notification);
 
-        let fut = async move {
-            let delay = Delay::new(duration);
-            match future::select(delay, &mut stopped).await {
-                Either::Left(_) => {
-                    let _ = addr.do_send(notification);
-                }
-                Either::Right(_) => {
-                    // Context stopped before the end of the delay was reached
-                }
+        Ok(Timer::new(move |commands| {
+            Box::pin(timer::run(
+                commands,
+                duration,
+                stopped,
+                None,
+                move || match notification.take() {
+                    Some(notification) => {
+                        Either::Right(addr.do_send_async(notification).map(|res| res.is_ok()))
+                    }
+                    None => Either::Left(future::ready(false)),
+                },
+            ))
+        }))
+    }
+
+    /// Notify the actor with a message, constructed anew each time, whenever the wall clock matches
+    /// the given cron schedule, until it is stopped. Ticks which are missed, for instance because
+    /// the machine was asleep, are handled according to the schedule's
+    /// [`MissedTickPolicy`](schedule/enum.MissedTickPolicy.html). This does not take priority over
+    /// other messages.
+    ///
+    /// The returned [`Timer`](timer/struct.Timer.html) must be spawned in order to run, and can be
+    /// cancelled or reset through its [`TimerHandle`](timer/struct.TimerHandle.html). Resetting it
+    /// moves the next notification to the given duration from now, after which the schedule
+    /// carries on as before.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use xtra::prelude::*;
+    /// # use xtra::spawn::Smol;
+    /// # struct MyActor;
+    /// # #[async_trait::async_trait] impl Handler<Cleanup> for MyActor { async fn handle(&mut self, _: Cleanup, _ctx: &mut Context<Self>) {} }
+    /// struct Cleanup;
+    ///
+    /// impl Message for Cleanup {
+    ///     type Result = ();
+    /// }
+    ///
+    /// #[async_trait::async_trait]
+    /// impl Actor for MyActor {
+    ///     type Stop = ();
+    ///
+    ///     async fn started(&mut self, ctx: &mut Context<Self>) {
+    ///         // Every five minutes, on the minute
+    ///         let schedule = "0 */5 * * * *".parse().unwrap();
+    ///         if let Ok(timer) = ctx.notify_cron(schedule, || Cleanup) {
+    ///             ctx.start_timer("cleanup", timer, &mut Smol::Global);
+    ///         }
+    ///     }
+    ///
+    ///     async fn stopped(self) {}
+    /// }
+    /// ```
+    #[cfg(feature = "timing")]
+    pub fn notify_cron<F, M>(
+        &mut self,
+        schedule: CronSchedule,
+        constructor: F,
+    ) -> Result<Timer, ActorShutdown>
+    where
+        F: Send + 'static + Fn() -> M,
+        M: Message,
+        A: NativeHandler<M>,
+    {
+        let addr = self.address()?.downgrade();
+        let stopped = self.drop_notifier.subscribe();
+
+        Ok(Timer::new(move |commands| {
+            Box::pin(schedule::run(
+                commands,
+                stopped,
+                Schedule::Cron(schedule),
+                move || addr.do_send_async(constructor()).map(|res| res.is_ok()),
+            ))
+        }))
+    }
+
+    /// Notify the actor with a message once the wall clock reaches the given time, or as soon as
+    /// possible if it is already in the past. Unlike
+    /// [`Context::notify_after`](struct.Context.html#method.notify_after), this stays on time if
+    /// the system clock is adjusted or the machine sleeps in the meantime. This does not take
+    /// priority over other messages.
+    ///
+    /// The returned [`Timer`](timer/struct.Timer.html) must be spawned in order to run, and can be
+    /// cancelled or reset through its [`TimerHandle`](timer/struct.TimerHandle.html).
+    #[cfg(feature = "timing")]
+    pub fn notify_at<M>(
+        &mut self,
+        time: SystemTime,
+        notification: M,
+    ) -> Result<Timer, ActorShutdown>
+    where
+        M: Message,
+        A: NativeHandler<M>,
+    {
+        let addr = self.address()?.downgrade();
+        let stopped = self.drop_notifier.subscribe();
+        let mut notification = Some(notification);
+
+        Ok(Timer::new(move |commands| {
+            Box::pin(schedule::run(
+                commands,
+                stopped,
+                Schedule::At(time),
+                move || match notification.take() {
+                    Some(notification) => {
+                        Either::Right(addr.do_send_async(notification).map(|res| res.is_ok()))
+                    }
+                    None => Either::Left(future::ready(false)),
+                },
+            ))
+        }))
+    }
+
+    /// Spawns the timer on the given spawner under the given key. If a timer was previously started
+    /// under the same key, it is cancelled, so re-arming a key replaces its timer. Timers which have
+    /// finished are forgotten, so that keys which are used only once do not pile up.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use xtra::prelude::*;
+    /// # use xtra::spawn::Smol;
+    /// # use std::time::Duration;
+    /// # struct MyActor;
+    /// # #[async_trait::async_trait] impl Actor for MyActor {type Stop = (); async fn stopped(self) -> Self::Stop {} }
+    /// struct Timeout;
+    ///
+    /// impl Message for Timeout {
+    ///     type Result = ();
+    /// }
+    /// # #[async_trait::async_trait] impl Handler<Timeout> for MyActor { async fn handle(&mut self, _: Timeout, _ctx: &mut Context<Self>) {} }
+    ///
+    /// struct Activity;
+    ///
+    /// impl Message for Activity {
+    ///     type Result = ();
+    /// }
+    ///
+    /// #[async_trait::async_trait]
+    /// impl Handler<Activity> for MyActor {
+    ///     async fn handle(&mut self, _: Activity, ctx: &mut Context<Self>) {
+    ///         // Push the idle timeout back whenever there is activity
+    ///         if let Ok(timer) = ctx.notify_after(Duration::from_secs(30), Timeout) {
+    ///             ctx.start_timer("idle", timer, &mut Smol::Global);
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    #[cfg(feature = "timing")]
+    pub fn start_timer<S: Spawner>(
+        &mut self,
+        key: impl Into<String>,
+        timer: Timer,
+        spawner: &mut S,
+    ) -> TimerHandle {
+        self.timers
+            .retain(|_, timer| !timer.is_finished() && !timer.is_cancelled());
+
+        let handle = timer.spawn(spawner);
+        if let Some(previous) = self.timers.insert(key.into(), handle.clone()) {
+            previous.cancel();
+        }
+        handle
+    }
+
+    /// Cancels the timer started under the given key with
+    /// [`Context::start_timer`](struct.Context.html#method.start_timer), returning whether there
+    /// was one.
+    #[cfg(feature = "timing")]
+    pub fn cancel_timer(&mut self, key: &str) -> bool {
+        match self.timers.remove(key) {
+            Some(timer) => {
+                timer.cancel();
+                true
             }
-        };
+            None => false,
+        }
+    }
+}
 
-        Ok(fut)
+/// Waits until a message has been handled, logging a warning every ten seconds while it has not.
+#[cfg(feature = "timing")]
+async fn handle_warning_if_slow(
+    mut msg_handler: BoxFuture<'_, ()>,
+    actor_name: &str,
+    msg_str: &str,
+) {
+    // Most handlers finish without waiting, so the timer is only started for those which do not
+    let first = future::poll_fn(|cx| Poll::Ready(msg_handler.as_mut().poll(cx))).await;
+    if first.is_ready() {
+        return;
     }
+
+    let mut time_spent = 0;
+    let sleep = 10;
+
+    loop {
+        match future::select(msg_handler, Delay::new(Duration::from_secs(sleep))).await {
+            Either::Left(((), _)) => break,
+            Either::Right(((), unfinished_handler)) => {
+                time_spent += sleep;
+
+                log::warn!(
+                    "Actor {} has been processing message {} for {} seconds",
+                    actor_name,
+                    msg_str,
+                    time_spent
+                );
+
+                msg_handler = unfinished_handler;
+            }
+        }
+    }
+}
+
+/// Waits until a message has been handled. Slow messages are only warned about with the `timing`
+/// feature.
+#[cfg(not(feature = "timing"))]
+async fn handle_warning_if_slow(
+    msg_handler: impl Future<Output = ()>,
+    _actor_name: &str,
+    _msg_str: &str,
+) {
+    msg_handler.await
+}
+
+/// The default for `Context::set_notification_limit`.
+pub(crate) const DEFAULT_NOTIFICATION_LIMIT: usize = 64;
+
+/// A message stashed by the handler which is currently running, along with how to put it into an
+/// envelope without a reply channel.
+struct Stashing<A> {
+    message: Box<dyn Any + Send>,
+    wrap: fn(Box<dyn Any + Send>) -> Box<dyn MessageEnvelope<Actor = A>>,
+}
+
+fn wrap_stashed<A, M>(message: Box<dyn Any + Send>) -> Box<dyn MessageEnvelope<Actor = A>>
+where
+    M: Message,
+    A: NativeHandler<M>,
+{
+    let message = message
+        .downcast::<M>()
+        .expect("stashed message has the wrong type");
+    Box::new(NonReturningEnvelope::<A, M>::new(*message))
 }
 
 /// The operation failed because the actor is being shut down
@@ -0,0 +1,501 @@
+//! Durable mailboxes. A durable mailbox is opened on an actor's context with
+//! [`Context::durable_mailbox`](../struct.Context.html#method.durable_mailbox), and is backed by an
+//! append-only log on disk. Messages sent through its
+//! [`DurableAddress`](struct.DurableAddress.html) are written to the log before they are put in the
+//! mailbox, and marked as acknowledged in the log once the actor is done with them. When the
+//! mailbox is next opened, for instance after the process crashed, the messages which were never
+//! acknowledged are put back into it, ahead of any new ones.
+//!
+//! The actor is done with a message once it has been handled, or once it was dropped by
+//! [middleware](../middleware/index.html) or dropped or rejected by a
+//! [behaviour](../behaviour/index.html). A message which is stashed is acknowledged once it has
+//! been replayed, and a message which is still in the mailbox or the stash when the actor stops is
+//! delivered again.
+//!
+//! Delivery is at-least-once: a message which was handled just before a crash, but not yet
+//! acknowledged, is handled again. Handlers of durable messages should therefore be idempotent.
+//!
+//! Each durable mailbox carries messages of a single type, which are encoded with their
+//! [`Codec`](../persistence/trait.Codec.html). The same message type sent through the actor's
+//! plain [`Address`](../address/struct.Address.html), a message channel or a sink bypasses the log
+//! and is lost if the process stops before it is handled. To make every message of the type
+//! durable, only hand out the durable address.
+
+use std::collections::{BTreeMap, HashSet};
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use flume::Sender;
+
+use crate::address::SendError;
+use crate::envelope::{DurableEnvelope, LimitedEnvelope, MessageEnvelope};
+use crate::manager::AddressMessage;
+use crate::persistence::{Codec, DecodeError};
+use crate::{Actor, ActorManager, Address, Context, Disconnected, Message, NativeHandler};
+
+/// The tag of a record which holds a message.
+const MESSAGE: u8 = 0;
+/// The tag of a record which acknowledges a message.
+const ACK: u8 = 1;
+
+/// The address of an actor's durable mailbox, which logs every message it sends so that messages
+/// which the actor is not done with survive a restart. It is returned by
+/// [`Context::durable_mailbox`](../struct.Context.html#method.durable_mailbox), and keeps the actor
+/// running like an [`Address`](../address/struct.Address.html). To send from several places, clone
+/// the durable address.
+///
+/// Every send appends to the log and waits for it to be synced to disk on the calling thread, so
+/// sending blocks briefly. The durable mailbox is unbounded, whatever the capacity of the actor's
+/// mailbox, as its messages are kept on disk anyway.
+///
+/// # Example
+///
+/// ```no_run
+/// # use std::convert::TryInto;
+/// # use xtra::prelude::*;
+/// # use xtra::spawn::Smol;
+/// use xtra::durable::DurableAddress;
+/// use xtra::persistence::{Codec, DecodeError};
+///
+/// struct Charge(u64);
+///
+/// impl Message for Charge {
+///     type Result = ();
+/// }
+///
+/// impl Codec for Charge {
+///     fn encode(&self) -> Vec<u8> {
+///         self.0.to_le_bytes().to_vec()
+///     }
+///
+///     fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+///         let bytes = bytes.try_into().map_err(|_| DecodeError::new("expected 8 bytes"))?;
+///         Ok(Charge(u64::from_le_bytes(bytes)))
+///     }
+/// }
+///
+/// struct Billing;
+/// # #[async_trait::async_trait] impl Actor for Billing {type Stop = (); async fn stopped(self) -> Self::Stop {} }
+///
+/// #[async_trait::async_trait]
+/// impl Handler<Charge> for Billing {
+///     async fn handle(&mut self, charge: Charge, _ctx: &mut Context<Self>) {
+///         println!("Charging {}", charge.0);
+///     }
+/// }
+///
+/// smol::block_on(async {
+///     let mut manager = Billing.create(None);
+///     // Any charges which were not handled before the last shutdown are delivered again
+///     let charges = manager.durable_mailbox::<Charge>("charges.log").unwrap();
+///     manager.spawn(&mut Smol::Global);
+///     charges.do_send(Charge(100)).unwrap();
+/// })
+/// ```
+pub struct DurableAddress<A, M> {
+    address: Address<A>,
+    sender: Sender<AddressMessage<A>>,
+    log: Arc<MailboxLog>,
+    phantom: PhantomData<fn(M)>,
+}
+
+impl<A: Actor> ActorManager<A> {
+    /// Opens a durable mailbox for messages of type `M`, as with
+    /// [`Context::durable_mailbox`](struct.Context.html#method.durable_mailbox).
+    pub fn durable_mailbox<M>(
+        &mut self,
+        path: impl AsRef<Path>,
+    ) -> Result<DurableAddress<A, M>, MailboxError>
+    where
+        A: NativeHandler<M>,
+        M: Message + Codec,
+    {
+        self.ctx.durable_mailbox(path)
+    }
+}
+
+impl<A: Actor> Context<A> {
+    /// Opens a durable mailbox for messages of type `M`, backed by the log at the given path, and
+    /// returns the address to send them through. The log is created if it does not exist. Every
+    /// message in it which was not acknowledged is put back into the mailbox, in the order they
+    /// were first sent, and received by the actor before the messages in its other mailboxes.
+    ///
+    /// Opening the log also compacts it, so that it only holds the messages which are delivered
+    /// again. A message which was only partly written, because the process crashed while writing
+    /// it, is discarded, as its send never returned. The log is locked while it is open, so opening
+    /// it again before every durable address and message from it has been dropped fails with
+    /// `MailboxError::Locked`.
+    ///
+    /// This must be called before the context is run or attached to other actors, and fails with
+    /// `MailboxError::Disconnected` if the actor has no address left.
+    pub fn durable_mailbox<M>(
+        &mut self,
+        path: impl AsRef<Path>,
+    ) -> Result<DurableAddress<A, M>, MailboxError>
+    where
+        A: NativeHandler<M>,
+        M: Message + Codec,
+    {
+        let address = self.address().map_err(|_| MailboxError::Disconnected)?;
+        let (log, messages) = MailboxLog::open::<M>(path.as_ref())?;
+        let log = Arc::new(log);
+        let sender = self.receiver.durable_sender();
+
+        for (id, message) in messages {
+            let ack = Ack {
+                log: log.clone(),
+                id,
+            };
+            let envelope = Box::new(DurableEnvelope::<A, M>::new(message, ack));
+            // The channel is unbounded and its receiver is held by this context, so this succeeds
+            let _ = sender.send(AddressMessage::Message(envelope));
+        }
+
+        Ok(DurableAddress {
+            address,
+            sender,
+            log,
+            phantom: PhantomData,
+        })
+    }
+}
+
+impl<A, M> DurableAddress<A, M>
+where
+    A: NativeHandler<M>,
+    M: Message + Codec,
+{
+    /// Writes the message to the log and sends it to the actor without waiting for a response. The
+    /// message is synced to disk before it is sent.
+    ///
+    /// If the actor is stopped, this returns `Err(MailboxError::Disconnected)`, and if the message
+    /// would exceed the actor's [limits](../limit/index.html), `Err(MailboxError::RateLimited)`,
+    /// like [`Address::do_send`](../address/struct.Address.html#method.do_send). In both cases,
+    /// the message is not logged.
+    pub fn do_send(&self, message: M) -> Result<(), MailboxError> {
+        if !self.address.is_connected() {
+            return Err(MailboxError::Disconnected);
+        }
+
+        let permit = self
+            .address
+            .limiter
+            .try_acquire()
+            .map_err(|_| MailboxError::RateLimited)?;
+        let envelope = self.log_envelope(message)?;
+        self.send_envelope(LimitedEnvelope::wrap(envelope, permit))
+    }
+
+    /// Like [`DurableAddress::do_send`](#method.do_send), but asynchronously waits until the message
+    /// is admitted by the actor's limits. Writing and syncing the log still blocks.
+    pub async fn do_send_async(&self, message: M) -> Result<(), MailboxError> {
+        if !self.address.is_connected() {
+            return Err(MailboxError::Disconnected);
+        }
+
+        let envelope = self.log_envelope(message)?;
+        let permit = self.address.limiter.clone().acquire().await;
+        self.send_envelope(LimitedEnvelope::wrap(envelope, permit))
+    }
+
+    /// Writes the message to the log, and puts it in an envelope which acknowledges it.
+    fn log_envelope(
+        &self,
+        message: M,
+    ) -> Result<Box<dyn MessageEnvelope<Actor = A>>, MailboxError> {
+        let id = self.log.append(&message.encode())?;
+        let ack = Ack {
+            log: self.log.clone(),
+            id,
+        };
+
+        Ok(Box::new(DurableEnvelope::<A, M>::new(message, ack)))
+    }
+
+    /// Puts a logged message into the durable mailbox. If the actor has stopped in the meantime,
+    /// the message stays in the log to be delivered when the mailbox is next opened.
+    fn send_envelope(
+        &self,
+        envelope: Box<dyn MessageEnvelope<Actor = A>>,
+    ) -> Result<(), MailboxError> {
+        self.sender
+            .send(AddressMessage::Message(envelope))
+            .map_err(|_| MailboxError::Disconnected)
+    }
+
+    /// Returns how many messages in the log have not yet been acknowledged.
+    pub fn pending(&self) -> usize {
+        self.log.state.lock().unwrap().pending.len()
+    }
+
+    /// Returns the address which messages are sent to.
+    pub fn address(&self) -> &Address<A> {
+        &self.address
+    }
+}
+
+impl<A, M> Clone for DurableAddress<A, M> {
+    fn clone(&self) -> Self {
+        DurableAddress {
+            address: self.address.clone(),
+            sender: self.sender.clone(),
+            log: self.log.clone(),
+            phantom: PhantomData,
+        }
+    }
+}
+
+/// An error from a [`DurableAddress`](struct.DurableAddress.html).
+#[derive(Debug)]
+pub enum MailboxError {
+    /// Reading or writing the log failed.
+    Io(io::Error),
+    /// A message in the log could not be decoded.
+    Decode(DecodeError),
+    /// The log is already open, in this or another process.
+    Locked,
+    /// The actor is stopped.
+    Disconnected,
+    /// The message was not sent, as it would exceed the actor's [limits](../limit/index.html).
+    RateLimited,
+}
+
+impl Display for MailboxError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            MailboxError::Io(err) => write!(f, "Mailbox log I/O failed: {}", err),
+            MailboxError::Decode(err) => Display::fmt(err, f),
+            MailboxError::Locked => f.write_str("Mailbox log is already open"),
+            MailboxError::Disconnected => Display::fmt(&Disconnected, f),
+            MailboxError::RateLimited => Display::fmt(&SendError::RateLimited, f),
+        }
+    }
+}
+
+impl Error for MailboxError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MailboxError::Io(err) => Some(err),
+            MailboxError::Decode(err) => Some(err),
+            MailboxError::Locked | MailboxError::Disconnected | MailboxError::RateLimited => None,
+        }
+    }
+}
+
+impl From<io::Error> for MailboxError {
+    fn from(err: io::Error) -> Self {
+        MailboxError::Io(err)
+    }
+}
+
+impl From<DecodeError> for MailboxError {
+    fn from(err: DecodeError) -> Self {
+        MailboxError::Decode(err)
+    }
+}
+
+impl From<Disconnected> for MailboxError {
+    fn from(_: Disconnected) -> Self {
+        MailboxError::Disconnected
+    }
+}
+
+/// The acknowledgement of a logged message, which the message's envelope gives once the message
+/// has been handled.
+pub(crate) struct Ack {
+    log: Arc<MailboxLog>,
+    id: u64,
+}
+
+impl Ack {
+    pub(crate) fn ack(self) {
+        if let Err(err) = self.log.ack(self.id) {
+            log::warn!(
+                "Message {} could not be acknowledged in mailbox log {}, and will be delivered again: {}",
+                self.id,
+                self.log.path.display(),
+                err
+            );
+        }
+    }
+}
+
+struct MailboxLog {
+    path: PathBuf,
+    state: Mutex<LogState>,
+    /// The file next to the log which is locked while the log is open. The log itself is replaced
+    /// when it is compacted, so it cannot hold the lock.
+    _lock: File,
+}
+
+struct LogState {
+    file: File,
+    next_id: u64,
+    /// The ids of the messages which have been logged but not acknowledged.
+    pending: HashSet<u64>,
+}
+
+impl MailboxLog {
+    /// Locks and opens the log at the given path, returning the messages in it which were not
+    /// acknowledged. Every message is decoded before the log is compacted, so that a bad log is
+    /// left as it was.
+    fn open<M: Codec>(path: &Path) -> Result<(Self, Vec<(u64, M)>), MailboxError> {
+        let mut lock_path = path.as_os_str().to_owned();
+        lock_path.push(".lock");
+        let lock = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_path)?;
+        match lock.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => return Err(MailboxError::Locked),
+            Err(TryLockError::Error(err)) => return Err(err.into()),
+        }
+
+        let pending = read_log(path)?;
+        let messages = pending
+            .iter()
+            .map(|(id, bytes)| Ok((*id, M::decode(bytes)?)))
+            .collect::<Result<Vec<_>, DecodeError>>()?;
+
+        let log = MailboxLog::compact(path, &pending, lock)?;
+        Ok((log, messages))
+    }
+
+    /// Rewrites the log at the given path so that it only holds the given messages, and opens it.
+    fn compact(path: &Path, pending: &BTreeMap<u64, Vec<u8>>, lock: File) -> io::Result<Self> {
+        let mut compacted = Vec::new();
+        for (id, message) in pending {
+            compacted.extend_from_slice(&message_record(*id, message)?);
+        }
+
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        let mut file = File::create(&temporary)?;
+        file.write_all(&compacted)?;
+        file.sync_data()?;
+        fs::rename(&temporary, path)?;
+
+        let file = OpenOptions::new().append(true).open(path)?;
+        Ok(MailboxLog {
+            path: path.to_path_buf(),
+            state: Mutex::new(LogState {
+                file,
+                next_id: pending.keys().next_back().map_or(1, |id| id + 1),
+                pending: pending.keys().copied().collect(),
+            }),
+            _lock: lock,
+        })
+    }
+
+    /// Appends a message to the log and syncs it to disk, returning its id. If this fails, the log
+    /// is cut back to where it was, so that the message is not delivered after its send failed.
+    fn append(&self, message: &[u8]) -> io::Result<u64> {
+        let mut state = self.state.lock().unwrap();
+        let id = state.next_id;
+        let record = message_record(id, message)?;
+        let offset = state.file.seek(SeekFrom::End(0))?;
+        if let Err(err) = state
+            .file
+            .write_all(&record)
+            .and_then(|()| state.file.sync_data())
+        {
+            let _ = state.file.set_len(offset);
+            return Err(err);
+        }
+
+        state.next_id += 1;
+        state.pending.insert(id);
+        Ok(id)
+    }
+
+    /// Acknowledges a message. Acknowledgements are not synced, as losing one only means that the
+    /// message is delivered again. Once every message has been acknowledged, the log is emptied.
+    fn ack(&self, id: u64) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.pending.remove(&id);
+
+        if state.pending.is_empty() {
+            state.file.set_len(0)
+        } else {
+            let mut record = vec![ACK];
+            record.extend_from_slice(&id.to_le_bytes());
+            state.file.write_all(&record)
+        }
+    }
+}
+
+fn message_record(id: u64, message: &[u8]) -> io::Result<Vec<u8>> {
+    let len = u32::try_from(message.len()).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "an encoded message must be shorter than 4 GiB",
+        )
+    })?;
+    let mut record = Vec::with_capacity(13 + message.len());
+    record.push(MESSAGE);
+    record.extend_from_slice(&id.to_le_bytes());
+    record.extend_from_slice(&len.to_le_bytes());
+    record.extend_from_slice(message);
+    Ok(record)
+}
+
+/// Reads the messages in the log at the given path which were not acknowledged, by id. Reading
+/// stops at the first record which was only partly written.
+fn read_log(path: &Path) -> io::Result<BTreeMap<u64, Vec<u8>>> {
+    let mut bytes = Vec::new();
+    match File::open(path) {
+        Ok(mut file) => file.read_to_end(&mut bytes)?,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => 0,
+        Err(err) => return Err(err),
+    };
+
+    let mut pending = BTreeMap::new();
+    let mut rest = &bytes[..];
+
+    while let Some((&tag, record)) = rest.split_first() {
+        let id = match record.get(..8) {
+            Some(id) => u64::from_le_bytes(le_bytes(id)),
+            None => break,
+        };
+
+        match tag {
+            MESSAGE => {
+                let len = match record.get(8..12) {
+                    Some(len) => u32::from_le_bytes(le_bytes(len)) as usize,
+                    None => break,
+                };
+                match record.get(12..12 + len) {
+                    Some(message) => pending.insert(id, message.to_vec()),
+                    None => break,
+                };
+                rest = &record[12 + len..];
+            }
+            ACK => {
+                pending.remove(&id);
+                rest = &record[8..];
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "mailbox log is corrupt",
+                ))
+            }
+        }
+    }
+
+    Ok(pending)
+}
+
+fn le_bytes<const N: usize>(bytes: &[u8]) -> [u8; N] {
+    let mut buf = [0; N];
+    buf.copy_from_slice(bytes);
+    buf
+}
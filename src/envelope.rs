@@ -1,18 +1,33 @@
+use std::any::{Any, TypeId};
 use std::marker::PhantomData;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
 
 use catty::{Receiver, Sender};
 use futures_core::future::BoxFuture;
 use futures_util::FutureExt;
 
+use crate::batch::BatchHandler;
+use crate::coalesce::{Coalesce, CoalesceTable};
 use crate::context::Context;
-use crate::{Actor, Handler, Message, MessageName};
+use crate::durable::Ack;
+use crate::limit::Permit;
+use crate::middleware;
+use crate::pool::EnvelopePool;
+use crate::streaming::{Emitter, Receiving, StreamingHandler, StreamingMessage};
+use crate::{Actor, Message, MessageName, NativeHandler};
+
+#[cfg(feature = "timing")]
+use std::sync::Mutex;
 
 /// A message envelope is a struct that encapsulates a message and its return channel sender (if applicable).
 /// Firstly, this allows us to be generic over returning and non-returning messages (as all use the
 /// same `handle` method and return the same pinned & boxed future), but almost more importantly it
 /// allows us to erase the type of the message when this is in dyn Trait format, thereby being able to
 /// use only one channel to send all the kinds of messages that the actor can receives. This does,
-/// however, induce a bit of allocation (as envelopes have to be boxed).
+/// however, induce a bit of allocation (as envelopes have to be boxed). To make up for it, the boxes
+/// of single messages are given back to the actor's `EnvelopePool` once their messages have been
+/// taken out, and reused by the messages sent after them.
 pub(crate) trait MessageEnvelope: Send + MessageName {
     /// The type of actor that this envelope carries a message for
     type Actor;
@@ -44,94 +59,591 @@ pub(crate) trait MessageEnvelope: Send + MessageName {
         act: &'a mut Self::Actor,
         ctx: &'a mut Context<Self::Actor>,
     ) -> BoxFuture<'a, ()>;
+
+    /// Returns the type of the message which this envelope carries, which decides how the actor's
+    /// current behaviour treats it.
+    fn message_type(&self) -> TypeId;
+
+    /// Replies to the sender, if there is one, with the result made by a behaviour which rejected
+    /// the message, without handling it. The result must be of the message's result type.
+    fn reject(self: Box<Self>, _result: Box<dyn Any + Send>) {}
+
+    /// Called when a behaviour drops the message without handling it. An envelope which is dropped
+    /// without this or another method being called was never seen by the actor, such as when the
+    /// actor stopped with the message still in its mailbox.
+    fn discard(self: Box<Self>) {}
+
+    /// If this envelope carries a batch of messages, returns its `Vec<M>` so that another batch of
+    /// the same type can be merged into it.
+    fn batch_mut(&mut self) -> Option<&mut dyn Any> {
+        None
+    }
+
+    /// If this envelope carries a single message whose result is not returned, returns its
+    /// `Option<M>` so that the message can be taken into a batch of the same type.
+    fn single_mut(&mut self) -> Option<&mut dyn Any> {
+        None
+    }
+
+    /// Moves the messages of `other` into this envelope's batch if it is a batch or a single
+    /// message of the same type, or returns it otherwise. A merged envelope is kept until the batch
+    /// has been handled, so that anything it holds (such as a permit) is released no earlier than
+    /// if it had been handled itself.
+    fn merge_batch(
+        &mut self,
+        other: Box<dyn MessageEnvelope<Actor = Self::Actor>>,
+    ) -> Result<(), Box<dyn MessageEnvelope<Actor = Self::Actor>>> {
+        Err(other)
+    }
+
+    /// Takes the permit which the message was admitted with under a concurrency limit, if any, so
+    /// that it can be released while the message waits in the stash.
+    fn take_permit(&mut self) -> Option<Permit> {
+        None
+    }
 }
 
 /// An envelope that returns a result from a message. Constructed by the `AddressExt::do_send` method.
 pub(crate) struct ReturningEnvelope<A, M: Message> {
-    message: M,
-    result_sender: Sender<M::Result>,
+    /// Both are only taken when the envelope is handled, after which it is given back to the pool
+    message: Option<M>,
+    result_sender: Option<Sender<M::Result>>,
     phantom: PhantomData<fn() -> A>,
 }
 
 impl<A: Actor, M: Message> ReturningEnvelope<A, M> {
-    pub(crate) fn new(message: M) -> (Self, Receiver<M::Result>) {
+    /// Puts the message into an envelope from the pool, or into a new one if none is kept.
+    pub(crate) fn new(message: M, pool: &EnvelopePool) -> (Box<Self>, Receiver<M::Result>) {
         let (tx, rx) = catty::oneshot();
-        let envelope = ReturningEnvelope {
-            message,
-            result_sender: tx,
-            phantom: PhantomData,
+        let envelope = match pool.take::<Self>() {
+            Some(mut envelope) => {
+                envelope.message = Some(message);
+                envelope.result_sender = Some(tx);
+                envelope
+            }
+            None => Box::new(ReturningEnvelope {
+                message: Some(message),
+                result_sender: Some(tx),
+                phantom: PhantomData,
+            }),
         };
 
         (envelope, rx)
     }
 }
 
-impl<A: Handler<M>, M: Message> MessageEnvelope for ReturningEnvelope<A, M> {
+impl<A: NativeHandler<M>, M: Message> MessageEnvelope for ReturningEnvelope<A, M> {
     type Actor = A;
 
+    fn message_type(&self) -> TypeId {
+        TypeId::of::<M>()
+    }
+
     fn handle<'a>(
-        self: Box<Self>,
+        mut self: Box<Self>,
         act: &'a mut Self::Actor,
         ctx: &'a mut Context<Self::Actor>,
     ) -> BoxFuture<'a, ()> {
-        let Self {
-            message,
-            result_sender,
-            ..
-        } = *self;
-        Box::pin(act.handle(message, ctx).map(move |r| {
-            // We don't actually care if the receiver is listening
-            let _ = result_sender.send(r);
-        }))
+        let message = self
+            .message
+            .take()
+            .expect("an envelope is only handled once");
+        let result_sender = self
+            .result_sender
+            .take()
+            .expect("an envelope is only handled once");
+        ctx.envelopes.give_back(self);
+
+        Box::pin(async move {
+            let r = middleware::handle(act, message, &mut *ctx).await;
+
+            // If the handler stashed the message, the reply is sent once it has been replayed
+            match ctx.take_stashed::<M>() {
+                Some(message) => ctx.stash_envelope(Box::new(ReturningEnvelope {
+                    message: Some(message),
+                    result_sender: Some(result_sender),
+                    phantom: PhantomData,
+                })),
+                // We don't actually care if the receiver is listening. If the middleware dropped
+                // the message, dropping the sender tells the receiver.
+                None => {
+                    if let Some(r) = r {
+                        let _ = result_sender.send(r);
+                    }
+                }
+            }
+        })
+    }
+
+    fn reject(self: Box<Self>, result: Box<dyn Any + Send>) {
+        if let (Some(result_sender), Ok(result)) =
+            (self.result_sender, result.downcast::<M::Result>())
+        {
+            let _ = result_sender.send(*result);
+        }
     }
 }
 
-impl<A: Handler<M>, M: Message> MessageName for ReturningEnvelope<A, M> {
+impl<A: NativeHandler<M>, M: Message> MessageName for ReturningEnvelope<A, M> {
     fn name(&self) -> &'static str {
-        self.message.name()
+        match &self.message {
+            Some(message) => message.name(),
+            None => std::any::type_name::<M>(),
+        }
     }
 }
 
 /// An envelope that does not return a result from a message. Constructed  by the `AddressExt::do_send`
 /// method.
 pub(crate) struct NonReturningEnvelope<A, M: Message> {
-    message: M,
+    /// Taken when the envelope is handled, after which it is given back to the pool, or when the
+    /// message is merged into a batch, after which the envelope is never handled
+    message: Option<M>,
     phantom: PhantomData<fn() -> A>,
 }
 
 impl<A: Actor, M: Message> NonReturningEnvelope<A, M> {
     pub(crate) fn new(message: M) -> Self {
         NonReturningEnvelope {
+            message: Some(message),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Puts the message into an envelope from the pool, or into a new one if none is kept.
+    pub(crate) fn pooled(message: M, pool: &EnvelopePool) -> Box<Self> {
+        match pool.take::<Self>() {
+            Some(mut envelope) => {
+                envelope.message = Some(message);
+                envelope
+            }
+            None => Box::new(Self::new(message)),
+        }
+    }
+}
+
+impl<A: NativeHandler<M>, M: Message> MessageEnvelope for NonReturningEnvelope<A, M> {
+    type Actor = A;
+
+    fn message_type(&self) -> TypeId {
+        TypeId::of::<M>()
+    }
+
+    fn handle<'a>(
+        mut self: Box<Self>,
+        act: &'a mut Self::Actor,
+        ctx: &'a mut Context<Self::Actor>,
+    ) -> BoxFuture<'a, ()> {
+        let message = self
+            .message
+            .take()
+            .expect("an envelope merged into a batch is not handled");
+        ctx.envelopes.give_back(self);
+        Box::pin(middleware::handle(act, message, ctx).map(|_| ()))
+    }
+
+    fn single_mut(&mut self) -> Option<&mut dyn Any> {
+        Some(&mut self.message)
+    }
+}
+
+impl<A: NativeHandler<M>, M: Message> MessageName for NonReturningEnvelope<A, M> {
+    fn name(&self) -> &'static str {
+        match &self.message {
+            Some(message) => message.name(),
+            None => std::any::type_name::<M>(),
+        }
+    }
+}
+
+/// An envelope that carries a message from a durable mailbox, which is acknowledged in its log once
+/// the actor is done with it: once it has been handled or dropped by middleware, or dropped or
+/// rejected by a behaviour. If the envelope is dropped before then, the message stays in the log to
+/// be delivered again. Constructed by the `DurableAddress::do_send` method, and when a durable
+/// mailbox is opened.
+pub(crate) struct DurableEnvelope<A, M: Message> {
+    message: M,
+    ack: Ack,
+    phantom: PhantomData<fn() -> A>,
+}
+
+impl<A: Actor, M: Message> DurableEnvelope<A, M> {
+    pub(crate) fn new(message: M, ack: Ack) -> Self {
+        DurableEnvelope {
             message,
+            ack,
             phantom: PhantomData,
         }
     }
 }
 
-impl<A: Handler<M>, M: Message> MessageEnvelope for NonReturningEnvelope<A, M> {
+impl<A: NativeHandler<M>, M: Message> MessageEnvelope for DurableEnvelope<A, M> {
     type Actor = A;
 
+    fn message_type(&self) -> TypeId {
+        TypeId::of::<M>()
+    }
+
     fn handle<'a>(
         self: Box<Self>,
         act: &'a mut Self::Actor,
         ctx: &'a mut Context<Self::Actor>,
     ) -> BoxFuture<'a, ()> {
-        Box::pin(act.handle(self.message, ctx).map(|_| ()))
+        let Self { message, ack, .. } = *self;
+        Box::pin(async move {
+            middleware::handle(act, message, &mut *ctx).await;
+
+            // If the handler stashed the message, it is acknowledged once it has been replayed
+            match ctx.take_stashed::<M>() {
+                Some(message) => ctx.stash_envelope(Box::new(DurableEnvelope::new(message, ack))),
+                None => ack.ack(),
+            }
+        })
+    }
+
+    fn reject(self: Box<Self>, _result: Box<dyn Any + Send>) {
+        // There is no sender to reply to, but the message will not be handled either
+        self.ack.ack();
+    }
+
+    fn discard(self: Box<Self>) {
+        self.ack.ack();
     }
 }
 
-impl<A: Handler<M>, M: Message> MessageName for NonReturningEnvelope<A, M> {
+impl<A: NativeHandler<M>, M: Message> MessageName for DurableEnvelope<A, M> {
     fn name(&self) -> &'static str {
         self.message.name()
     }
 }
 
+/// An envelope which holds the permit of a message admitted under a concurrency limit, releasing it
+/// once the envelope it wraps has been handled or dropped. Constructed by the sends of an address.
+pub(crate) struct LimitedEnvelope<A> {
+    envelope: Box<dyn MessageEnvelope<Actor = A>>,
+    /// Taken when the message is stashed
+    permit: Option<Permit>,
+}
+
+impl<A: Actor> LimitedEnvelope<A> {
+    /// Wraps the envelope if it was admitted with a permit.
+    pub(crate) fn wrap(
+        envelope: Box<dyn MessageEnvelope<Actor = A>>,
+        permit: Option<Permit>,
+    ) -> Box<dyn MessageEnvelope<Actor = A>> {
+        match permit {
+            Some(permit) => Box::new(LimitedEnvelope {
+                envelope,
+                permit: Some(permit),
+            }),
+            None => envelope,
+        }
+    }
+}
+
+impl<A: Actor> MessageEnvelope for LimitedEnvelope<A> {
+    type Actor = A;
+
+    fn message_type(&self) -> TypeId {
+        self.envelope.message_type()
+    }
+
+    fn handle<'a>(
+        self: Box<Self>,
+        act: &'a mut Self::Actor,
+        ctx: &'a mut Context<Self::Actor>,
+    ) -> BoxFuture<'a, ()> {
+        let Self { envelope, permit } = *self;
+        Box::pin(async move {
+            envelope.handle(act, ctx).await;
+            drop(permit);
+        })
+    }
+
+    fn reject(self: Box<Self>, result: Box<dyn Any + Send>) {
+        self.envelope.reject(result)
+    }
+
+    fn discard(self: Box<Self>) {
+        self.envelope.discard()
+    }
+
+    fn batch_mut(&mut self) -> Option<&mut dyn Any> {
+        self.envelope.batch_mut()
+    }
+
+    fn single_mut(&mut self) -> Option<&mut dyn Any> {
+        self.envelope.single_mut()
+    }
+
+    fn merge_batch(
+        &mut self,
+        other: Box<dyn MessageEnvelope<Actor = Self::Actor>>,
+    ) -> Result<(), Box<dyn MessageEnvelope<Actor = Self::Actor>>> {
+        self.envelope.merge_batch(other)
+    }
+
+    fn take_permit(&mut self) -> Option<Permit> {
+        self.permit.take()
+    }
+}
+
+impl<A> MessageName for LimitedEnvelope<A> {
+    fn name(&self) -> &'static str {
+        self.envelope.name()
+    }
+}
+
+/// An envelope that carries a streaming message and the sending half of its response stream.
+/// Constructed by the `Address::send_streaming` method.
+pub(crate) struct StreamingEnvelope<A, M: StreamingMessage> {
+    message: M,
+    emitter: Emitter<M::Item>,
+    phantom: PhantomData<fn() -> A>,
+}
+
+impl<A: Actor, M: StreamingMessage> StreamingEnvelope<A, M> {
+    pub(crate) fn new(message: M) -> (Self, Receiving<M::Item>) {
+        let (tx, rx) = flume::bounded(M::BUFFER);
+        let finished = Arc::new(AtomicBool::new(false));
+        let envelope = StreamingEnvelope {
+            message,
+            emitter: Emitter::new(tx, finished.clone()),
+            phantom: PhantomData,
+        };
+
+        let rx = Receiving {
+            items: rx.into_stream(),
+            finished,
+        };
+        (envelope, rx)
+    }
+}
+
+impl<A: StreamingHandler<M>, M: StreamingMessage> MessageEnvelope for StreamingEnvelope<A, M> {
+    type Actor = A;
+
+    fn message_type(&self) -> TypeId {
+        TypeId::of::<M>()
+    }
+
+    fn handle<'a>(
+        self: Box<Self>,
+        act: &'a mut Self::Actor,
+        ctx: &'a mut Context<Self::Actor>,
+    ) -> BoxFuture<'a, ()> {
+        let Self {
+            message, emitter, ..
+        } = *self;
+        Box::pin(middleware::handle_streaming(act, message, emitter, ctx))
+    }
+}
+
+impl<A: StreamingHandler<M>, M: StreamingMessage> MessageName for StreamingEnvelope<A, M> {
+    fn name(&self) -> &'static str {
+        std::any::type_name::<M>()
+    }
+}
+
+/// An envelope that carries a batch of messages of the same type. Constructed by the
+/// `Address::do_send_batch` method.
+pub(crate) struct BatchEnvelope<A, M: Message> {
+    messages: Vec<M>,
+    /// The envelopes whose messages were merged into this batch, dropped once it has been handled
+    merged: Vec<Box<dyn MessageEnvelope<Actor = A>>>,
+}
+
+impl<A: Actor, M: Message> BatchEnvelope<A, M> {
+    pub(crate) fn new(messages: Vec<M>) -> Self {
+        BatchEnvelope {
+            messages,
+            merged: Vec::new(),
+        }
+    }
+}
+
+impl<A: BatchHandler<M>, M: Message> MessageEnvelope for BatchEnvelope<A, M> {
+    type Actor = A;
+
+    fn message_type(&self) -> TypeId {
+        TypeId::of::<M>()
+    }
+
+    fn handle<'a>(
+        self: Box<Self>,
+        act: &'a mut Self::Actor,
+        ctx: &'a mut Context<Self::Actor>,
+    ) -> BoxFuture<'a, ()> {
+        let Self { messages, merged } = *self;
+        Box::pin(async move {
+            middleware::handle_batch(act, messages, ctx).await;
+            drop(merged);
+        })
+    }
+
+    fn batch_mut(&mut self) -> Option<&mut dyn Any> {
+        Some(&mut self.messages)
+    }
+
+    fn merge_batch(
+        &mut self,
+        mut other: Box<dyn MessageEnvelope<Actor = Self::Actor>>,
+    ) -> Result<(), Box<dyn MessageEnvelope<Actor = Self::Actor>>> {
+        if let Some(messages) = other
+            .batch_mut()
+            .and_then(|batch| batch.downcast_mut::<Vec<M>>())
+        {
+            self.messages.append(messages);
+        } else if let Some(message) = other
+            .single_mut()
+            .and_then(|single| single.downcast_mut::<Option<M>>())
+            .and_then(Option::take)
+        {
+            self.messages.push(message);
+        } else {
+            return Err(other);
+        }
+
+        self.merged.push(other);
+        Ok(())
+    }
+}
+
+impl<A: BatchHandler<M>, M: Message> MessageName for BatchEnvelope<A, M> {
+    fn name(&self) -> &'static str {
+        std::any::type_name::<Vec<M>>()
+    }
+}
+
+/// An envelope that carries the key of a coalescing message, which is taken out of the address's
+/// coalesce table when the envelope is handled. Constructed by the `Address::do_send_coalescing`
+/// method.
+pub(crate) struct CoalescingEnvelope<A, M: Coalesce> {
+    key: M::Key,
+    table: Arc<CoalesceTable>,
+    /// Whether the message was taken out of the table to be handled
+    taken: bool,
+    phantom: PhantomData<fn() -> A>,
+}
+
+impl<A: Actor, M: Coalesce> CoalescingEnvelope<A, M> {
+    pub(crate) fn new(key: M::Key, table: Arc<CoalesceTable>) -> Self {
+        CoalescingEnvelope {
+            key,
+            table,
+            taken: false,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<A, M: Coalesce> Drop for CoalescingEnvelope<A, M> {
+    fn drop(&mut self) {
+        // An envelope which is dropped without being handled, such as by a behaviour or when the
+        // send fails, must release its key. Otherwise, later messages with the key would replace
+        // one which is never picked up.
+        if !self.taken {
+            self.table.remove::<M>(&self.key);
+        }
+    }
+}
+
+impl<A: NativeHandler<M>, M: Coalesce> MessageEnvelope for CoalescingEnvelope<A, M> {
+    type Actor = A;
+
+    fn message_type(&self) -> TypeId {
+        TypeId::of::<M>()
+    }
+
+    fn handle<'a>(
+        mut self: Box<Self>,
+        act: &'a mut Self::Actor,
+        ctx: &'a mut Context<Self::Actor>,
+    ) -> BoxFuture<'a, ()> {
+        self.taken = true;
+        match self.table.remove::<M>(&self.key) {
+            Some(message) => Box::pin(middleware::handle(act, message, ctx).map(|_| ())),
+            None => Box::pin(futures_util::future::ready(())),
+        }
+    }
+}
+
+impl<A: NativeHandler<M>, M: Coalesce> MessageName for CoalescingEnvelope<A, M> {
+    fn name(&self) -> &'static str {
+        std::any::type_name::<M>()
+    }
+}
+
+/// An envelope for an interval timer's tick, whose message is taken out of a slot shared with the
+/// timer when the envelope is handled. While the slot is full, the tick is still queued, so the
+/// timer can skip or replace it rather than sending another. Constructed by
+/// `Context::notify_interval_with`.
+#[cfg(feature = "timing")]
+pub(crate) struct TickEnvelope<A, M> {
+    slot: Arc<Mutex<Option<M>>>,
+    /// Whether the message was taken out of the slot to be handled
+    taken: bool,
+    phantom: PhantomData<fn() -> A>,
+}
+
+#[cfg(feature = "timing")]
+impl<A: Actor, M: Message> TickEnvelope<A, M> {
+    pub(crate) fn new(slot: Arc<Mutex<Option<M>>>) -> Self {
+        TickEnvelope {
+            slot,
+            taken: false,
+            phantom: PhantomData,
+        }
+    }
+}
+
+#[cfg(feature = "timing")]
+impl<A, M> Drop for TickEnvelope<A, M> {
+    fn drop(&mut self) {
+        // A tick which is dropped without being handled, such as by a behaviour or when the timer
+        // is cancelled while delivering it, must empty the slot. Otherwise, the timer would keep
+        // skipping or coalescing into a tick which is never picked up.
+        if !self.taken {
+            self.slot.lock().unwrap().take();
+        }
+    }
+}
+
+#[cfg(feature = "timing")]
+impl<A: NativeHandler<M>, M: Message> MessageEnvelope for TickEnvelope<A, M> {
+    type Actor = A;
+
+    fn message_type(&self) -> TypeId {
+        TypeId::of::<M>()
+    }
+
+    fn handle<'a>(
+        mut self: Box<Self>,
+        act: &'a mut Self::Actor,
+        ctx: &'a mut Context<Self::Actor>,
+    ) -> BoxFuture<'a, ()> {
+        self.taken = true;
+        let message = self.slot.lock().unwrap().take();
+        match message {
+            Some(message) => Box::pin(middleware::handle(act, message, ctx).map(|_| ())),
+            None => Box::pin(futures_util::future::ready(())),
+        }
+    }
+}
+
+#[cfg(feature = "timing")]
+impl<A: NativeHandler<M>, M: Message> MessageName for TickEnvelope<A, M> {
+    fn name(&self) -> &'static str {
+        std::any::type_name::<M>()
+    }
+}
+
 /// Like MessageEnvelope, but can be cloned.
 pub(crate) trait BroadcastMessageEnvelope: MessageEnvelope + Sync {
     fn clone(&self) -> Box<dyn BroadcastMessageEnvelope<Actor = Self::Actor>>;
 }
 
-impl<A: Handler<M>, M: Message + Clone + Sync> BroadcastMessageEnvelope
+impl<A: NativeHandler<M>, M: Message + Clone + Sync> BroadcastMessageEnvelope
     for NonReturningEnvelope<A, M>
 {
     fn clone(&self) -> Box<dyn BroadcastMessageEnvelope<Actor = Self::Actor>> {
@@ -0,0 +1,276 @@
+//! Finite-state machine actors. An [`FsmActor`](trait.FsmActor.html) has explicit `State` and
+//! `Event` types, and decides how each event moves it between states. Entering and leaving states
+//! runs hooks, states can time out, and every transition is logged at the debug level.
+
+use std::fmt::Debug;
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use crate::address::SendFuture;
+use crate::refcount::RefCounter;
+use crate::spawn::Spawner;
+use crate::{Actor, Address, Context, Handler, Message};
+
+/// The key under which the timer of a state timeout is started, with
+/// [`Context::start_timer`](../struct.Context.html#method.start_timer).
+pub const STATE_TIMEOUT_TIMER: &str = "xtra::fsm::state_timeout";
+
+/// An actor which is a finite-state machine. Events are sent to it with
+/// [`Address::fire`](../address/struct.Address.html#method.fire), which resolves to the state
+/// which the actor is in after handling the event.
+///
+/// How the actor reacts to events is decided by [`FsmActor::on_event`](#tymethod.on_event), which
+/// does not get the actor's context, so the transitions can be tested without running the actor.
+/// Side effects belong in [`FsmActor::on_enter`](#method.on_enter) and
+/// [`FsmActor::on_exit`](#method.on_exit).
+///
+/// The hooks and the timeout are not run for the initial state unless
+/// [`fsm::start`](fn.start.html) is called, usually from
+/// [`Actor::started`](../trait.Actor.html#method.started).
+///
+/// # Example
+///
+/// ```
+/// # use std::time::Duration;
+/// # use xtra::prelude::*;
+/// # use xtra::spawn::Smol;
+/// use xtra::fsm::{FsmActor, Transition};
+///
+/// #[derive(Clone, Debug, PartialEq)]
+/// enum State {
+///     Disconnected,
+///     Connecting,
+///     Connected,
+/// }
+///
+/// #[derive(Debug)]
+/// enum Event {
+///     Connect,
+///     Established,
+///     Lost,
+/// }
+///
+/// struct Connection {
+///     state: State,
+/// }
+///
+/// #[async_trait::async_trait]
+/// impl Actor for Connection {
+///     type Stop = ();
+///
+///     async fn started(&mut self, ctx: &mut Context<Self>) {
+///         xtra::fsm::start(self, ctx).await;
+///     }
+///
+///     async fn stopped(self) {}
+/// }
+///
+/// #[async_trait::async_trait]
+/// impl FsmActor for Connection {
+///     type State = State;
+///     type Event = Event;
+///     type Spawner = Smol<'static>;
+///
+///     fn state_mut(&mut self) -> &mut State {
+///         &mut self.state
+///     }
+///
+///     async fn on_event(&mut self, state: &State, event: Event) -> Transition<State> {
+///         match (state, event) {
+///             (State::Disconnected, Event::Connect) => Transition::Goto(State::Connecting),
+///             (State::Connecting, Event::Established) => Transition::Goto(State::Connected),
+///             (_, Event::Lost) => Transition::Goto(State::Disconnected),
+///             _ => Transition::Stay,
+///         }
+///     }
+///
+///     // Give up on connecting after ten seconds
+///     fn state_timeout(&self, state: &State) -> Option<Duration> {
+///         match state {
+///             State::Connecting => Some(Duration::from_secs(10)),
+///             _ => None,
+///         }
+///     }
+///
+///     async fn on_timeout(&mut self, _state: &State) -> Transition<State> {
+///         Transition::Goto(State::Disconnected)
+///     }
+/// }
+///
+/// smol::block_on(async {
+///     let addr = Connection { state: State::Disconnected }.create(None).spawn(&mut Smol::Global);
+///     assert_eq!(addr.fire(Event::Connect).await, Ok(State::Connecting));
+///     assert_eq!(addr.fire(Event::Established).await, Ok(State::Connected));
+/// })
+/// ```
+#[async_trait]
+pub trait FsmActor: Actor {
+    /// The states which the actor can be in.
+    type State: Clone + Debug + Send + Sync + 'static;
+
+    /// The events which move the actor between states.
+    type Event: Debug + Send + 'static;
+
+    /// The spawner which runs the timers of state timeouts.
+    type Spawner: Spawner + Default;
+
+    /// Returns the actor's current state, which is kept in the actor itself.
+    fn state_mut(&mut self) -> &mut Self::State;
+
+    /// Decides how the actor reacts to an event in the given state.
+    async fn on_event(
+        &mut self,
+        state: &Self::State,
+        event: Self::Event,
+    ) -> Transition<Self::State>;
+
+    /// Called when the actor enters a state, after the state has been changed.
+    async fn on_enter(&mut self, state: &Self::State, ctx: &mut Context<Self>) {
+        let _ = (state, ctx);
+    }
+
+    /// Called when the actor leaves a state, before the state has been changed.
+    async fn on_exit(&mut self, state: &Self::State, ctx: &mut Context<Self>) {
+        let _ = (state, ctx);
+    }
+
+    /// Returns how long the actor may stay in the given state before
+    /// [`FsmActor::on_timeout`](#method.on_timeout) is called. The timeout is started whenever the
+    /// actor enters the state, including when it re-enters the state it was already in. By
+    /// default, states do not time out.
+    fn state_timeout(&self, state: &Self::State) -> Option<Duration> {
+        let _ = state;
+        None
+    }
+
+    /// Decides how the actor reacts to the given state timing out. By default, it stays in it.
+    async fn on_timeout(&mut self, state: &Self::State) -> Transition<Self::State> {
+        let _ = state;
+        Transition::Stay
+    }
+}
+
+/// What an [`FsmActor`](trait.FsmActor.html) does in reaction to an event or a timeout.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Transition<S> {
+    /// Stay in the current state, without running any hooks.
+    Stay,
+    /// Move to the given state, leaving the current state and entering the given one. This
+    /// re-enters the current state if it is given again.
+    Goto(S),
+}
+
+/// Enters the actor's current state, calling [`FsmActor::on_enter`](trait.FsmActor.html#method.on_enter)
+/// and starting its timeout. This should be called from
+/// [`Actor::started`](../trait.Actor.html#method.started).
+pub async fn start<F: FsmActor>(actor: &mut F, ctx: &mut Context<F>) {
+    let state = actor.state_mut().clone();
+    enter(actor, state, ctx).await;
+}
+
+/// The message which carries an event to an [`FsmActor`](trait.FsmActor.html). It resolves to the
+/// state which the actor is in after handling the event. It is usually sent with
+/// [`Address::fire`](../address/struct.Address.html#method.fire).
+pub struct Fire<F: FsmActor>(pub F::Event);
+
+impl<F: FsmActor> Message for Fire<F> {
+    type Result = F::State;
+}
+
+#[async_trait]
+impl<F: FsmActor> Handler<Fire<F>> for F {
+    async fn handle(&mut self, fire: Fire<F>, ctx: &mut Context<Self>) -> F::State {
+        let state = self.state_mut().clone();
+        let cause = match log::log_enabled!(log::Level::Debug) {
+            true => format!("{:?}", fire.0),
+            false => String::new(),
+        };
+        let transition = self.on_event(&state, fire.0).await;
+        apply(self, state, transition, &cause, ctx).await;
+        self.state_mut().clone()
+    }
+}
+
+/// Counts the states entered by an [`FsmActor`](trait.FsmActor.html), so that the timeout of a
+/// state which has been left can be told apart. It is kept in the actor's context.
+#[derive(Default)]
+struct Generation(u64);
+
+/// The message which ends a state timeout. It is sent by the actor to itself, and ignored if the
+/// actor has changed state since the timeout was started.
+pub struct StateTimeout {
+    generation: u64,
+}
+
+impl Message for StateTimeout {
+    type Result = ();
+}
+
+#[async_trait]
+impl<F: FsmActor> Handler<StateTimeout> for F {
+    async fn handle(&mut self, timeout: StateTimeout, ctx: &mut Context<Self>) {
+        if timeout.generation != ctx.extension_mut::<Generation>().0 {
+            return;
+        }
+
+        let state = self.state_mut().clone();
+        let transition = self.on_timeout(&state).await;
+        apply(self, state, transition, "state timeout", ctx).await;
+    }
+}
+
+impl<F: FsmActor, Rc: RefCounter> Address<F, Rc> {
+    /// Send an event to the [`FsmActor`](../fsm/trait.FsmActor.html), resolving to the state which
+    /// it is in after handling the event.
+    pub fn fire(&self, event: F::Event) -> SendFuture<F, Fire<F>> {
+        self.send(Fire(event))
+    }
+}
+
+async fn apply<F: FsmActor>(
+    actor: &mut F,
+    previous: F::State,
+    transition: Transition<F::State>,
+    cause: &str,
+    ctx: &mut Context<F>,
+) {
+    let next = match transition {
+        Transition::Stay => return,
+        Transition::Goto(next) => next,
+    };
+
+    log::debug!(
+        "Actor {} transitioned from {:?} to {:?} on {}",
+        std::any::type_name::<F>(),
+        previous,
+        next,
+        cause
+    );
+
+    actor.on_exit(&previous, ctx).await;
+    *actor.state_mut() = next.clone();
+    enter(actor, next, ctx).await;
+}
+
+async fn enter<F: FsmActor>(actor: &mut F, state: F::State, ctx: &mut Context<F>) {
+    // Any timeout of the previous state which is already in the mailbox is now stale
+    let generation = {
+        let generation = ctx.extension_mut::<Generation>();
+        generation.0 += 1;
+        generation.0
+    };
+
+    match actor.state_timeout(&state) {
+        Some(timeout) => {
+            if let Ok(timer) = ctx.notify_after(timeout, StateTimeout { generation }) {
+                ctx.start_timer(STATE_TIMEOUT_TIMER, timer, &mut F::Spawner::default());
+            }
+        }
+        None => {
+            ctx.cancel_timer(STATE_TIMEOUT_TIMER);
+        }
+    }
+
+    actor.on_enter(&state, ctx).await;
+}
@@ -0,0 +1,369 @@
+//! Interceptors which see every message sent through an address or message channel, before it
+//! reaches the actor's mailbox. They are the sender's counterpart to
+//! [middleware](../middleware/index.html), and can add correlation ids to messages, refuse
+//! messages, or record outgoing traffic.
+//!
+//! An address is given an interceptor with
+//! [`Address::with_interceptor`](../address/struct.Address.html#method.with_interceptor), which
+//! returns an address whose messages of that type are intercepted, whichever method they are sent
+//! with, including batches, attached streams and its sink. Other addresses of the actor, such as
+//! the one which was intercepted, are not. Streaming messages are not intercepted, as they are not
+//! [`Message`](../trait.Message.html)s.
+//!
+//! Any message channel is wrapped in an interceptor with
+//! [`Intercepted::new`](struct.Intercepted.html#method.new). Further interceptors are layered
+//! around it with [`Intercepted::with_interceptor`](struct.Intercepted.html#method.with_interceptor),
+//! like tower layers around a service, and the outermost one sees the message first.
+//!
+//! An interceptor refuses a message by returning an error, which the sender receives as it was
+//! returned, such as [`SendError::Refused`](../address/enum.SendError.html#variant.Refused), from
+//! the methods which return a `SendError`, such as `do_send`, the `try_` sends, the sinks and
+//! [`Intercepted::send_checked`](struct.Intercepted.html#method.send_checked). Through the other
+//! methods, whose errors are `Disconnected`, such as `send`, a refused message cannot be told apart
+//! from a stopped actor. This is also the case for the `send` of a message channel which an
+//! `Intercepted` wraps, such as another `Intercepted` which was boxed.
+
+use std::any::{Any, TypeId};
+use std::future::Future;
+use std::iter;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures_core::future::BoxFuture;
+use futures_core::stream::BoxStream;
+use futures_sink::Sink;
+use futures_util::{FutureExt, SinkExt, StreamExt};
+
+use crate::address::SendError;
+use crate::message_channel::{MessageChannel, SendFuture};
+use crate::refcount::Shared;
+use crate::sink::MessageSink;
+use crate::{KeepRunning, Message};
+
+/// Sees every message sent through an intercepted address or
+/// [`Intercepted`](struct.Intercepted.html) channel before it is sent. It is implemented for closures of the same signature as
+/// [`Interceptor::intercept`](#tymethod.intercept).
+///
+/// # Example
+///
+/// ```
+/// # use xtra::prelude::*;
+/// # use xtra::spawn::Smol;
+/// # use xtra::address::SendError;
+/// use std::sync::atomic::{AtomicU64, Ordering};
+/// use xtra::intercept::{Intercepted, Interceptor};
+///
+/// struct Request {
+///     correlation_id: u64,
+/// }
+///
+/// impl Message for Request {
+///     type Result = u64;
+/// }
+///
+/// struct Server;
+/// # #[async_trait::async_trait] impl Actor for Server {type Stop = (); async fn stopped(self) -> Self::Stop {} }
+///
+/// #[async_trait::async_trait]
+/// impl Handler<Request> for Server {
+///     async fn handle(&mut self, request: Request, _ctx: &mut Context<Self>) -> u64 {
+///         request.correlation_id
+///     }
+/// }
+///
+/// struct CorrelationIds(AtomicU64);
+///
+/// impl Interceptor<Request> for CorrelationIds {
+///     fn intercept(&self, mut request: Request) -> Result<Request, SendError> {
+///         request.correlation_id = self.0.fetch_add(1, Ordering::Relaxed);
+///         Ok(request)
+///     }
+/// }
+///
+/// smol::block_on(async {
+///     let addr = Server.create(None).spawn(&mut Smol::Global);
+///     let addr = addr.with_interceptor(CorrelationIds(AtomicU64::new(1)));
+///     assert_eq!(addr.send(Request { correlation_id: 0 }).await, Ok(1));
+///     assert_eq!(addr.send(Request { correlation_id: 0 }).await, Ok(2));
+/// })
+/// ```
+pub trait Interceptor<M: Message>: Send + Sync + 'static {
+    /// Called with each message before it is sent, returning the message to send, which may have
+    /// been changed. Returning an error, usually
+    /// [`SendError::Refused`](../address/enum.SendError.html#variant.Refused), refuses the
+    /// message, and the sender receives the error without the message being sent.
+    fn intercept(&self, message: M) -> Result<M, SendError>;
+}
+
+impl<M, F> Interceptor<M> for F
+where
+    M: Message,
+    F: Fn(M) -> Result<M, SendError> + Send + Sync + 'static,
+{
+    fn intercept(&self, message: M) -> Result<M, SendError> {
+        self(message)
+    }
+}
+
+/// The interceptors of an [`Address`](../address/struct.Address.html), outermost first. Each
+/// intercepts one type of message, so that an address can hold interceptors for any of the types
+/// which its actor handles.
+#[derive(Clone, Default)]
+pub(crate) struct Interceptors(Option<Arc<[Layer]>>);
+
+/// The type of message which an interceptor intercepts, and the interceptor as an
+/// `Arc<dyn Interceptor<M>>`.
+type Layer = (TypeId, Arc<dyn Any + Send + Sync>);
+
+impl Interceptors {
+    /// Returns these interceptors with another one around them, which sees messages first.
+    pub(crate) fn with<M: Message>(&self, interceptor: Arc<dyn Interceptor<M>>) -> Self {
+        let layer: Layer = (TypeId::of::<M>(), Arc::new(interceptor));
+        let inner = self.0.iter().flat_map(|layers| layers.iter().cloned());
+        Interceptors(Some(iter::once(layer).chain(inner).collect()))
+    }
+
+    /// Passes the message through each interceptor of its type, returning the message to send or
+    /// the error of the interceptor which refused it.
+    pub(crate) fn intercept<M: Message>(&self, mut message: M) -> Result<M, SendError> {
+        let layers = match &self.0 {
+            Some(layers) => layers,
+            None => return Ok(message),
+        };
+
+        for (ty, interceptor) in layers.iter() {
+            if *ty == TypeId::of::<M>() {
+                let interceptor = interceptor
+                    .downcast_ref::<Arc<dyn Interceptor<M>>>()
+                    .expect("interceptor is stored with the type id of its message");
+                message = interceptor.intercept(message)?;
+            }
+        }
+
+        Ok(message)
+    }
+}
+
+/// The future returned by [`Intercepted::send_checked`](struct.Intercepted.html#method.send_checked).
+/// It resolves to `Result<M::Result, SendError>`.
+#[must_use]
+pub struct InterceptedSendFuture<M: Message>(Result<SendFuture<M>, Option<SendError>>);
+
+impl<M: Message> InterceptedSendFuture<M> {
+    /// A future which resolves to the result of the sent message.
+    fn sent(future: SendFuture<M>) -> Self {
+        InterceptedSendFuture(Ok(future))
+    }
+
+    /// A future which resolves to the error straight away.
+    fn refused(err: SendError) -> Self {
+        InterceptedSendFuture(Err(Some(err)))
+    }
+}
+
+impl<M: Message> Future for InterceptedSendFuture<M> {
+    type Output = Result<M::Result, SendError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        match &mut self.get_mut().0 {
+            Ok(future) => future.poll_unpin(cx).map(|res| res.map_err(Into::into)),
+            Err(err) => Poll::Ready(Err(err.take().expect("polled after completion"))),
+        }
+    }
+}
+
+/// A message channel which passes every message through its
+/// [`Interceptor`s](trait.Interceptor.html) before sending it through the channel which it wraps.
+/// This includes messages sent through its [sink](#method.sink) and attached streams.
+pub struct Intercepted<M: Message> {
+    inner: Box<dyn MessageChannel<M>>,
+    interceptors: Interceptors,
+}
+
+impl<M: Message> Intercepted<M> {
+    /// Wraps the channel, so that messages sent through it are passed through the interceptor.
+    pub fn new(inner: Box<dyn MessageChannel<M>>, interceptor: impl Interceptor<M>) -> Self {
+        Intercepted {
+            inner,
+            interceptors: Interceptors::default(),
+        }
+        .with_interceptor(interceptor)
+    }
+
+    /// Layers another interceptor around this channel's interceptors, which sees each message
+    /// before they do. Unlike wrapping this channel with
+    /// [`Intercepted::new`](struct.Intercepted.html#method.new), a message refused by any of the
+    /// interceptors makes [`Intercepted::send_checked`](#method.send_checked) resolve to its error.
+    pub fn with_interceptor(self, interceptor: impl Interceptor<M>) -> Self {
+        let interceptor: Arc<dyn Interceptor<M>> = Arc::new(interceptor);
+        Intercepted {
+            inner: self.inner,
+            interceptors: self.interceptors.with(interceptor),
+        }
+    }
+
+    /// Passes the message through the interceptors and sends it like
+    /// [`MessageChannel::send`](../message_channel/trait.MessageChannel.html#tymethod.send),
+    /// but resolves to the error of the interceptor which refused the message, rather than
+    /// `Disconnected`.
+    pub fn send_checked(&self, message: M) -> InterceptedSendFuture<M> {
+        match self.interceptors.intercept(message) {
+            Ok(message) => InterceptedSendFuture::sent(self.inner.send(message)),
+            Err(err) => InterceptedSendFuture::refused(err),
+        }
+    }
+}
+
+impl<M: Message> Clone for Intercepted<M> {
+    fn clone(&self) -> Self {
+        Intercepted {
+            inner: self.inner.clone_channel(),
+            interceptors: self.interceptors.clone(),
+        }
+    }
+}
+
+impl<M: Message> MessageChannel<M> for Intercepted<M> {
+    fn is_connected(&self) -> bool {
+        self.inner.is_connected()
+    }
+
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn capacity(&self) -> Option<usize> {
+        self.inner.capacity()
+    }
+
+    /// Passes the message through the interceptors and sends it like
+    /// [`MessageChannel::do_send`](../message_channel/trait.MessageChannel.html#tymethod.do_send),
+    /// returning the error of the interceptor which refused the message.
+    fn do_send(&self, message: M) -> Result<(), SendError> {
+        let message = self.interceptors.intercept(message)?;
+        self.inner.do_send(message)
+    }
+
+    /// Sends the message like
+    /// [`Intercepted::send_checked`](struct.Intercepted.html#method.send_checked), but a refused
+    /// message resolves to `Err(Disconnected)`.
+    fn send(&self, message: M) -> SendFuture<M> {
+        match self.interceptors.intercept(message) {
+            Ok(message) => self.inner.send(message),
+            Err(_) => SendFuture::disconnected(),
+        }
+    }
+
+    /// Attaches a stream like
+    /// [`MessageChannel::attach_stream`](../message_channel/trait.MessageChannel.html#tymethod.attach_stream).
+    /// As the channel which this wraps may be a trait object, forwarding only notices that the actor
+    /// has stopped once the stream produces another message.
+    fn attach_stream(self, stream: BoxStream<M>) -> BoxFuture<()>
+    where
+        M::Result: Into<KeepRunning> + Send,
+    {
+        self.attach_stream_buffered(stream, 1)
+    }
+
+    fn attach_stream_buffered(self, stream: BoxStream<M>, n: usize) -> BoxFuture<()>
+    where
+        M::Result: Into<KeepRunning> + Send,
+    {
+        assert!(n > 0, "at least one message must be allowed in flight");
+
+        Box::pin(async move {
+            let results = stream.map(|m| MessageChannel::send(&self, m)).buffered(n);
+            futures_util::pin_mut!(results);
+
+            while let Some(res) = results.next().await {
+                if !matches!(res.map(Into::into), Ok(KeepRunning::Yes)) {
+                    break;
+                }
+            }
+        })
+    }
+
+    /// Attaches a stream like
+    /// [`MessageChannel::attach_stream_do_send`](../message_channel/trait.MessageChannel.html#tymethod.attach_stream_do_send).
+    /// The messages are sent through this channel's [sink](#method.sink), which waits
+    /// asynchronously while the actor's mailbox is full. Forwarding only notices that the actor has
+    /// stopped once the stream produces another message.
+    fn attach_stream_do_send(self, stream: BoxStream<M>) -> BoxFuture<()>
+    where
+        M: Message<Result = ()>,
+    {
+        let mut sink = MessageChannel::sink(&self);
+        Box::pin(async move {
+            let _ = sink.send_all(&mut stream.map(Ok)).await;
+        })
+    }
+
+    fn clone_channel(&self) -> Box<dyn MessageChannel<M>> {
+        Box::new(self.clone())
+    }
+
+    fn sink(&self) -> Box<dyn MessageSink<M>> {
+        Box::new(InterceptedSink {
+            inner: self.inner.sink(),
+            interceptors: self.interceptors.clone(),
+        })
+    }
+
+    fn eq(&self, other: &dyn MessageChannel<M>) -> bool {
+        self.inner.eq(other)
+    }
+
+    fn _ref_counter_eq(&self, other: *const Shared) -> bool {
+        self.inner._ref_counter_eq(other)
+    }
+}
+
+/// The sink of an [`Intercepted`](struct.Intercepted.html) channel, which passes every message
+/// through its interceptors.
+pub struct InterceptedSink<M: Message> {
+    inner: Box<dyn MessageSink<M>>,
+    interceptors: Interceptors,
+}
+
+impl<M: Message> Sink<M> for InterceptedSink<M> {
+    type Error = SendError;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut *self.inner).poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: M) -> Result<(), Self::Error> {
+        let item = self.interceptors.intercept(item)?;
+        Pin::new(&mut *self.inner).start_send(item)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut *self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut *self.inner).poll_close(cx)
+    }
+}
+
+impl<M: Message> MessageSink<M> for InterceptedSink<M> {
+    fn is_connected(&self) -> bool {
+        self.inner.is_connected()
+    }
+
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn capacity(&self) -> Option<usize> {
+        self.inner.capacity()
+    }
+
+    fn clone_message_sink(&self) -> Box<dyn MessageSink<M>> {
+        Box::new(InterceptedSink {
+            inner: self.inner.clone_message_sink(),
+            interceptors: self.interceptors.clone(),
+        })
+    }
+}
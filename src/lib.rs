@@ -9,22 +9,51 @@ pub use self::manager::ActorManager;
 
 pub use async_trait::async_trait;
 
+use std::future::Future;
+
+#[cfg(feature = "macros")]
+pub use xtra_macros::{handlers, Message};
+
 pub mod address;
+pub mod batch;
+pub mod behaviour;
+mod blocking;
+pub mod coalesce;
 mod context;
 mod drop_notice;
+pub mod durable;
 mod envelope;
+#[cfg(feature = "timing")]
+pub mod fsm;
+pub mod intercept;
+pub mod limit;
+pub mod local;
+mod mailbox;
 mod manager;
 pub mod message_channel;
+pub mod middleware;
+pub mod persistence;
+mod pool;
 /// This module contains types representing the strength of an address's reference counting, which
 /// influences whether the address will keep the actor alive for as long as it lives.
 pub mod refcount;
+#[cfg(feature = "timing")]
+pub mod schedule;
 pub mod sink;
+pub mod snapshot;
 /// This module contains a trait to spawn actors, implemented for all major async runtimes by default.
 pub mod spawn;
+pub mod streaming;
+#[cfg(feature = "timing")]
+pub mod timer;
 #[cfg(feature = "with-tracing-0_1")]
 /// Integration with [`tracing`](https://tracing.rs).
 pub mod tracing;
 
+#[cfg(feature = "with-tower-service-0_3")]
+/// Integration with [`tower`](https://docs.rs/tower).
+pub mod tower;
+
 /// Commonly used types from xtra
 pub mod prelude {
     pub use crate::address::Address;
@@ -112,6 +141,64 @@ pub trait Handler<M: Message>: Actor {
     async fn handle(&mut self, message: M, ctx: &mut Context<Self>) -> M::Result;
 }
 
+/// A variant of [`Handler`](trait.Handler.html) which is declared with a native `async fn` (or a
+/// function returning `impl Future`) rather than through `async_trait`. As its future does not
+/// need to be boxed, this saves an allocation for every message handled, which can be worthwhile
+/// for actors which handle a very large number of small messages.
+///
+/// There is no native counterpart to the lifecycle methods of [`Actor`](trait.Actor.html), as they
+/// are only called a few times in an actor's life rather than for every message, so boxing their
+/// futures costs next to nothing.
+///
+/// Every [`Handler`](trait.Handler.html) is also a `NativeHandler`, so an actor should implement
+/// one or the other for a given message, and all methods which send messages accept either. This
+/// trait is not in the prelude, as having both traits in scope makes calls to `handle` ambiguous.
+///
+/// # Example
+///
+/// ```
+/// # use xtra::prelude::*;
+/// # use xtra::spawn::Smol;
+/// use xtra::NativeHandler;
+///
+/// # struct MyActor;
+/// # #[async_trait::async_trait] impl Actor for MyActor {type Stop = (); async fn stopped(self) -> Self::Stop {} }
+/// struct Msg;
+///
+/// impl Message for Msg {
+///     type Result = u32;
+/// }
+///
+/// impl NativeHandler<Msg> for MyActor {
+///     async fn handle(&mut self, message: Msg, ctx: &mut Context<Self>) -> u32 {
+///         20
+///     }
+/// }
+///
+/// smol::block_on(async {
+///     let addr = MyActor.create(None).spawn(&mut Smol::Global);
+///     assert_eq!(addr.send(Msg).await, Ok(20));
+/// })
+/// ```
+pub trait NativeHandler<M: Message>: Actor {
+    /// Handle a given message, returning its result. The returned future must be `Send`.
+    fn handle<'a>(
+        &'a mut self,
+        message: M,
+        ctx: &'a mut Context<Self>,
+    ) -> impl Future<Output = M::Result> + Send + 'a;
+}
+
+impl<A: Handler<M>, M: Message> NativeHandler<M> for A {
+    fn handle<'a>(
+        &'a mut self,
+        message: M,
+        ctx: &'a mut Context<Self>,
+    ) -> impl Future<Output = M::Result> + Send + 'a {
+        Handler::handle(self, message, ctx)
+    }
+}
+
 /// An actor which can handle [`Message`s](trait.Message.html) one at a time. Actors can only be
 /// communicated with by sending [`Message`s](trait.Message.html) through their [`Address`es](address/struct.Address.html).
 /// They can modify their private state, respond to messages, and spawn other actors. They can also
@@ -281,9 +368,10 @@ impl From<()> for KeepRunning {
 }
 
 mod private {
+    use crate::intercept::{Intercepted, InterceptedSink};
     use crate::refcount::{Either, RefCounter, Strong, Weak};
     use crate::sink::AddressSink;
-    use crate::{Actor, Address};
+    use crate::{Actor, Address, Message};
 
     pub trait Sealed {}
 
@@ -292,4 +380,6 @@ mod private {
     impl Sealed for Either {}
     impl<A: Actor, Rc: RefCounter> Sealed for Address<A, Rc> {}
     impl<A: Actor, Rc: RefCounter> Sealed for AddressSink<A, Rc> {}
+    impl<M: Message> Sealed for Intercepted<M> {}
+    impl<M: Message> Sealed for InterceptedSink<M> {}
 }
@@ -0,0 +1,389 @@
+//! Rate and concurrency limits on the messages which an actor admits, so that an actor facing
+//! callers which do not hold back can protect itself without a separate limiter actor in front of
+//! it. [`Limits`](struct.Limits.html) are set when the actor is created with
+//! [`ActorManager::with_limits`](../struct.ActorManager.html#method.with_limits), or later with
+//! [`Context::set_limits`](../struct.Context.html#method.set_limits), and apply to every address of
+//! the actor.
+//!
+//! The asynchronous sends, such as `send`, `do_send_async`, `send_streaming`, message channels'
+//! `send`, sinks and services, wait until a message is admitted. The blocking sends, such as
+//! `send_blocking`, block the thread until it is admitted, or give up once their timeout has
+//! elapsed.
+//!
+//! The other synchronous sends, such as `do_send`, batches, coalescing messages and durable
+//! mailboxes' `do_send`, cannot wait without blocking the thread, which deadlocks if an actor sends
+//! to itself. They, like [`Address::try_send`](../address/struct.Address.html#method.try_send) and
+//! [`Address::try_do_send`](../address/struct.Address.html#method.try_do_send), reject a message
+//! which exceeds the limits with
+//! [`SendError::RateLimited`](../address/enum.SendError.html#variant.RateLimited) instead.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context as TaskContext, Poll};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use futures_core::future::BoxFuture;
+use futures_core::ready;
+use futures_util::FutureExt;
+
+use event_listener::Event;
+#[cfg(feature = "with-tower-service-0_3")]
+use event_listener::EventListener;
+
+use crate::{Actor, ActorManager, Context};
+
+/// The limits on the messages admitted by an actor. By default, nothing is limited.
+///
+/// # Example
+///
+/// ```
+/// # use std::time::Duration;
+/// # use xtra::prelude::*;
+/// # use xtra::spawn::Smol;
+/// use xtra::address::SendError;
+/// use xtra::limit::Limits;
+///
+/// struct Query;
+///
+/// impl Message for Query {
+///     type Result = ();
+/// }
+///
+/// struct PublicApi;
+/// # #[async_trait::async_trait] impl Actor for PublicApi {type Stop = (); async fn stopped(self) -> Self::Stop {} }
+///
+/// #[async_trait::async_trait]
+/// impl Handler<Query> for PublicApi {
+///     async fn handle(&mut self, _: Query, _ctx: &mut Context<Self>) {}
+/// }
+///
+/// smol::block_on(async {
+///     let limits = Limits::new().rate(1, Duration::from_secs(60)).concurrency(8);
+///     let addr = PublicApi.create(None).with_limits(limits).spawn(&mut Smol::Global);
+///
+///     assert_eq!(addr.try_send(Query).await, Ok(()));
+///     assert_eq!(addr.try_send(Query).await, Err(SendError::RateLimited));
+/// })
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Limits {
+    rate: Option<(u32, Duration)>,
+    burst: Option<u32>,
+    concurrency: Option<usize>,
+}
+
+impl Limits {
+    /// Creates limits which do not limit anything.
+    pub fn new() -> Self {
+        Limits::default()
+    }
+
+    /// Admits at most `messages` messages per period `per`, refilled evenly over the period. Up to
+    /// `messages` may be admitted at once after the actor has been idle, unless a different
+    /// [burst](#method.burst) is set.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `messages` or `per` is zero.
+    #[cfg(feature = "timing")]
+    pub fn rate(mut self, messages: u32, per: Duration) -> Self {
+        assert!(messages > 0, "the rate must admit at least one message");
+        assert!(
+            per > Duration::from_secs(0),
+            "the rate's period must not be zero"
+        );
+        self.rate = Some((messages, per));
+        self
+    }
+
+    /// Sets how many messages may be admitted at once after the actor has been idle. It has no
+    /// effect without a [rate](#method.rate).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `burst` is zero.
+    #[cfg(feature = "timing")]
+    pub fn burst(mut self, burst: u32) -> Self {
+        assert!(burst > 0, "the burst must admit at least one message");
+        self.burst = Some(burst);
+        self
+    }
+
+    /// Admits at most `max` messages which have not yet been handled, counting those which are
+    /// waiting in the mailbox and the one being handled.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max` is zero.
+    pub fn concurrency(mut self, max: usize) -> Self {
+        assert!(max > 0, "at least one message must be allowed in flight");
+        self.concurrency = Some(max);
+        self
+    }
+}
+
+impl<A: Actor> ActorManager<A> {
+    /// Limits the messages which the actor admits, as with
+    /// [`Context::set_limits`](struct.Context.html#method.set_limits).
+    pub fn with_limits(self, limits: Limits) -> Self {
+        self.ctx.set_limits(limits);
+        self
+    }
+}
+
+impl<A: Actor> Context<A> {
+    /// Replaces the limits on the messages which the actor admits. They are shared by every address
+    /// of the actor and every actor attached to it. Messages which were already admitted are not
+    /// affected.
+    pub fn set_limits(&self, limits: Limits) {
+        self.limiter.set(limits);
+    }
+}
+
+/// A token bucket, which holds between zero and `capacity` tokens and is refilled continuously.
+struct Bucket {
+    tokens: f64,
+    capacity: f64,
+    per_second: f64,
+    refilled: Instant,
+}
+
+impl Bucket {
+    /// Adds the tokens which were refilled since the bucket was last used.
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.per_second).clamp(0.0, self.capacity);
+        self.refilled = now;
+    }
+
+    /// Takes a token, or returns how long it will be until one is available.
+    fn take(&mut self) -> Result<(), Duration> {
+        self.refill();
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - self.tokens) / self.per_second,
+            ))
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    bucket: Option<Bucket>,
+    max_in_flight: Option<usize>,
+    in_flight: usize,
+}
+
+/// Why a message was not admitted.
+pub(crate) enum Exceeded {
+    /// The rate was exceeded, and a message can be admitted after the duration.
+    Rate(Duration),
+    /// The maximum number of messages are in flight, and a message can be admitted once a permit
+    /// has been released.
+    Concurrency,
+}
+
+/// The limits of an actor, shared by its addresses and contexts.
+pub(crate) struct Limiter {
+    /// Whether any limit is set, so that the state need not be locked for every message when not.
+    limited: AtomicBool,
+    state: Mutex<State>,
+    /// Notified when a permit is released or the limits change.
+    changed: Event,
+    /// Notified when the actor takes a message from its mailbox, making room for another.
+    received: Event,
+}
+
+impl Limiter {
+    pub(crate) fn new() -> Self {
+        Limiter {
+            limited: AtomicBool::new(false),
+            state: Mutex::new(State::default()),
+            changed: Event::new(),
+            received: Event::new(),
+        }
+    }
+
+    /// Tells senders waiting for room in the mailbox that a message was taken from it.
+    pub(crate) fn notify_received(&self) {
+        self.received.notify(usize::MAX);
+    }
+
+    /// Listens for the actor to take a message from its mailbox.
+    #[cfg(feature = "with-tower-service-0_3")]
+    pub(crate) fn listen_received(&self) -> EventListener {
+        self.received.listen()
+    }
+
+    fn set(&self, limits: Limits) {
+        let mut state = self.state.lock().unwrap();
+        state.bucket = limits.rate.map(|(messages, per)| {
+            let capacity = f64::from(limits.burst.unwrap_or(messages));
+            Bucket {
+                tokens: capacity,
+                capacity,
+                per_second: f64::from(messages) / per.as_secs_f64(),
+                refilled: Instant::now(),
+            }
+        });
+        state.max_in_flight = limits.concurrency;
+
+        let limited = state.bucket.is_some() || state.max_in_flight.is_some();
+        self.limited.store(limited, Ordering::Release);
+        drop(state);
+
+        self.changed.notify(usize::MAX);
+    }
+
+    /// Admits a message if the limits allow it, returning the permit which must be held until it has
+    /// been handled if the concurrency is limited.
+    pub(crate) fn try_acquire(self: &Arc<Self>) -> Result<Option<Permit>, Exceeded> {
+        if !self.limited.load(Ordering::Acquire) {
+            return Ok(None);
+        }
+
+        let mut state = self.state.lock().unwrap();
+        if matches!(state.max_in_flight, Some(max) if state.in_flight >= max) {
+            return Err(Exceeded::Concurrency);
+        }
+
+        if let Some(bucket) = &mut state.bucket {
+            bucket.take().map_err(Exceeded::Rate)?;
+        }
+
+        if state.max_in_flight.is_none() {
+            return Ok(None);
+        }
+
+        state.in_flight += 1;
+        Ok(Some(Permit(self.clone())))
+    }
+
+    /// Waits until a message is admitted.
+    pub(crate) async fn acquire(self: Arc<Self>) -> Option<Permit> {
+        if !self.limited.load(Ordering::Acquire) {
+            return None;
+        }
+
+        loop {
+            // Listen before trying, so that a permit released in between is not missed
+            let released = self.changed.listen();
+            match self.try_acquire() {
+                Ok(permit) => return permit,
+                Err(Exceeded::Rate(wait)) => sleep(wait).await,
+                Err(Exceeded::Concurrency) => released.await,
+            }
+        }
+    }
+
+    /// Blocks the current thread until a message is admitted, for the blocking sends.
+    pub(crate) fn acquire_blocking(self: &Arc<Self>) -> Option<Permit> {
+        match self.acquire_blocking_until(None) {
+            Ok(permit) => permit,
+            Err(_) => unreachable!("only a deadline gives up on admitting a message"),
+        }
+    }
+
+    /// Blocks the current thread until a message is admitted, like
+    /// [`Limiter::acquire_blocking`](#method.acquire_blocking). If there is a deadline, this gives
+    /// up once it has passed, or straight away if the rate would only admit the message after it,
+    /// returning why the message was not admitted.
+    pub(crate) fn acquire_blocking_until(
+        self: &Arc<Self>,
+        deadline: Option<Instant>,
+    ) -> Result<Option<Permit>, Exceeded> {
+        loop {
+            // Listen before trying, so that a permit released in between is not missed
+            let released = self.changed.listen();
+            match (self.try_acquire(), deadline) {
+                (Ok(permit), _) => return Ok(permit),
+                (Err(Exceeded::Rate(wait)), Some(deadline)) if Instant::now() + wait > deadline => {
+                    return Err(Exceeded::Rate(wait))
+                }
+                (Err(Exceeded::Rate(wait)), _) => thread::sleep(wait),
+                (Err(Exceeded::Concurrency), None) => released.wait(),
+                (Err(Exceeded::Concurrency), Some(deadline)) => {
+                    if !released.wait_deadline(deadline) {
+                        return Err(Exceeded::Concurrency);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Admits a message which was released from the concurrency limit again, without counting it
+    /// against the rate a second time.
+    pub(crate) fn readmit(self: &Arc<Self>) -> Option<Permit> {
+        if !self.limited.load(Ordering::Acquire) {
+            return None;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        state.max_in_flight?;
+        state.in_flight += 1;
+        Some(Permit(self.clone()))
+    }
+}
+
+/// Waits for a message to be admitted before it is sent, for senders which are polled for
+/// readiness before they are given the message, such as sinks.
+#[derive(Default)]
+pub(crate) struct Admission {
+    /// The admission which was waited for, with its permit if any
+    admitted: Option<Option<Permit>>,
+    /// Waits until a message is admitted
+    admitting: Option<BoxFuture<'static, Option<Permit>>>,
+}
+
+impl Admission {
+    /// Waits until a message is admitted, holding the admission until it is taken.
+    pub(crate) fn poll_admit(&mut self, limiter: &Arc<Limiter>, cx: &mut TaskContext) -> Poll<()> {
+        while self.admitted.is_none() {
+            match &mut self.admitting {
+                Some(admitting) => {
+                    let permit = ready!(admitting.poll_unpin(cx));
+                    self.admitting = None;
+                    self.admitted = Some(permit);
+                }
+                None => match limiter.try_acquire() {
+                    Ok(permit) => self.admitted = Some(permit),
+                    Err(_) => self.admitting = Some(Box::pin(limiter.clone().acquire())),
+                },
+            }
+        }
+
+        Poll::Ready(())
+    }
+
+    /// Takes the admission which was waited for, if any.
+    pub(crate) fn take(&mut self) -> Option<Option<Permit>> {
+        self.admitted.take()
+    }
+}
+
+#[cfg(feature = "timing")]
+async fn sleep(duration: Duration) {
+    futures_timer::Delay::new(duration).await
+}
+
+#[cfg(not(feature = "timing"))]
+async fn sleep(_duration: Duration) {
+    unreachable!("a rate can only be set with the `timing` feature")
+}
+
+/// A message admitted under a concurrency limit, which counts as in flight until this is dropped.
+pub(crate) struct Permit(Arc<Limiter>);
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.0.state.lock().unwrap().in_flight -= 1;
+        // Every waiter is woken, as the first might be held back by the rate instead
+        self.0.changed.notify(usize::MAX);
+    }
+}
@@ -0,0 +1,585 @@
+//! Local actors are actors which are not `Send`, and can therefore hold state such as `Rc` or
+//! `RefCell`. They are spawned onto a single-threaded executor through a
+//! [`LocalSpawner`](../spawn/trait.LocalSpawner.html), but their [`LocalAddress`es](struct.LocalAddress.html)
+//! are `Send` and `Sync`, so they can still be sent messages from any thread.
+//!
+//! Local actors are a simpler counterpart to [`Actor`](../trait.Actor.html), with a runtime of
+//! their own. They support only what is documented here:
+//!
+//! - [`LocalAddress::send`](struct.LocalAddress.html#method.send) and
+//!   [`LocalAddress::do_send`](struct.LocalAddress.html#method.do_send), with weak addresses and
+//!   [`LocalAddress::join`](struct.LocalAddress.html#method.join);
+//! - [`LocalContext::notify`](struct.LocalContext.html#method.notify) and
+//!   [`LocalContext::notify_front`](struct.LocalContext.html#method.notify_front), handled in
+//!   order before the mailbox, up to the same
+//!   [notification limit](struct.LocalContext.html#method.set_notification_limit) as `Context`;
+//! - stopping with [`LocalContext::stop`](struct.LocalContext.html#method.stop), or once the last
+//!   strong address is dropped.
+//!
+//! Everything else which `Actor` offers is not available to local actors. This includes attaching
+//! several actors to an address, broadcasts, message channels and sinks, attached streams,
+//! streaming, batched and coalescing messages, timers and scheduling, stashing, behaviours,
+//! finite-state machines, persistence and snapshots, middleware, interceptors, limits and the
+//! blocking send methods.
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::mem;
+use std::pin::Pin;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::task::{Context as TaskContext, Poll};
+
+use catty::{Receiver, Sender as ResultSender};
+use flume::r#async::SendFut as ChannelSendFuture;
+use flume::Sender;
+use futures_core::future::LocalBoxFuture;
+use futures_util::FutureExt;
+
+use crate::address::{self, Disconnected};
+use crate::context::DEFAULT_NOTIFICATION_LIMIT;
+use crate::drop_notice::DropNotifier;
+use crate::refcount::{RefCounter, Strong, Weak};
+use crate::spawn::LocalSpawner;
+use crate::{ActorShutdown, KeepRunning, Message};
+
+/// An actor which does not need to be `Send`. It is the local counterpart of
+/// [`Actor`](../trait.Actor.html), and its lifecycle methods behave in the same way.
+///
+/// This is an [`async_trait`](https://docs.rs/async-trait) without the `Send` bound on its
+/// futures, so implementations should be annotated `#[async_trait(?Send)]`.
+///
+/// # Example
+///
+/// ```rust
+/// # use std::cell::RefCell;
+/// # use std::rc::Rc;
+/// # use xtra::local::{LocalActor, LocalContext, LocalHandler};
+/// # use xtra::Message;
+/// struct Counter(Rc<RefCell<usize>>);
+///
+/// #[async_trait::async_trait(?Send)]
+/// impl LocalActor for Counter {
+///     type Stop = ();
+///     async fn stopped(self) {}
+/// }
+///
+/// struct Increment;
+///
+/// impl Message for Increment {
+///     type Result = usize;
+/// }
+///
+/// #[async_trait::async_trait(?Send)]
+/// impl LocalHandler<Increment> for Counter {
+///     async fn handle(&mut self, _: Increment, _ctx: &mut LocalContext<Self>) -> usize {
+///         *self.0.borrow_mut() += 1;
+///         *self.0.borrow()
+///     }
+/// }
+///
+/// let executor = smol::LocalExecutor::new();
+/// smol::block_on(executor.run(async {
+///     let (addr, fut) = Counter(Rc::new(RefCell::new(0))).create(None).run();
+///     executor.spawn(fut).detach();
+///
+///     // The address is `Send` even though the actor is not
+///     let addr = std::thread::spawn(move || addr).join().unwrap();
+///     assert_eq!(addr.send(Increment).await, Ok(1));
+/// }))
+/// ```
+#[async_trait::async_trait(?Send)]
+pub trait LocalActor: 'static + Sized {
+    /// Value returned from the actor when [`LocalActor::stopped`] is called.
+    type Stop: 'static;
+
+    /// Called as soon as the actor has been started.
+    #[allow(unused_variables)]
+    async fn started(&mut self, ctx: &mut LocalContext<Self>) {}
+
+    /// Called when the actor calls [`LocalContext::stop`](struct.LocalContext.html#method.stop).
+    /// This method can prevent the actor from stopping by returning
+    /// [`KeepRunning::Yes`](../enum.KeepRunning.html#variant.Yes). As local actors cannot share
+    /// an address, [`KeepRunning::StopSelf`](../enum.KeepRunning.html#variant.StopSelf) and
+    /// [`KeepRunning::StopAll`](../enum.KeepRunning.html#variant.StopAll) both stop the actor.
+    #[allow(unused_variables)]
+    async fn stopping(&mut self, ctx: &mut LocalContext<Self>) -> KeepRunning {
+        KeepRunning::StopSelf
+    }
+
+    /// Called when the actor is in the process of stopping, either because it stopped itself or
+    /// because there are no more strong addresses to it. This should be used for any final cleanup
+    /// before the actor is dropped.
+    async fn stopped(self) -> Self::Stop;
+
+    /// Returns the actor's address and manager in a ready-to-start state, given the cap for the
+    /// actor's mailbox. If `None` is passed, it will be of unbounded size. To spawn the actor,
+    /// [`LocalActorManager::spawn`](struct.LocalActorManager.html#method.spawn) must be called, or
+    /// the [`LocalActorManager::run`](struct.LocalActorManager.html#method.run) method must be
+    /// called and the future it returns spawned onto a local executor.
+    fn create(self, message_cap: Option<usize>) -> LocalActorManager<Self> {
+        let (address, ctx) = LocalContext::new(message_cap);
+        LocalActorManager {
+            address,
+            actor: self,
+            ctx,
+        }
+    }
+}
+
+/// A trait indicating that a [`LocalActor`](trait.LocalActor.html) can handle a given
+/// [`Message`](../trait.Message.html). It is the local counterpart of
+/// [`Handler`](../trait.Handler.html), and the future it returns does not need to be `Send`.
+///
+/// This is an [`async_trait`](https://docs.rs/async-trait) without the `Send` bound on its
+/// futures, so implementations should be annotated `#[async_trait(?Send)]`.
+#[async_trait::async_trait(?Send)]
+pub trait LocalHandler<M: Message>: LocalActor {
+    /// Handle a given message, returning its result.
+    async fn handle(&mut self, message: M, ctx: &mut LocalContext<Self>) -> M::Result;
+}
+
+/// The local counterpart of `MessageEnvelope`. The envelope itself is `Send` so that it can travel
+/// through the mailbox, but the future returned by handling it is not.
+trait LocalMessageEnvelope: Send {
+    type Actor;
+
+    fn handle<'a>(
+        self: Box<Self>,
+        act: &'a mut Self::Actor,
+        ctx: &'a mut LocalContext<Self::Actor>,
+    ) -> LocalBoxFuture<'a, ()>;
+}
+
+struct LocalEnvelope<A, M: Message> {
+    message: M,
+    result_sender: Option<ResultSender<M::Result>>,
+    phantom: PhantomData<fn() -> A>,
+}
+
+impl<A: LocalHandler<M>, M: Message> LocalMessageEnvelope for LocalEnvelope<A, M> {
+    type Actor = A;
+
+    fn handle<'a>(
+        self: Box<Self>,
+        act: &'a mut Self::Actor,
+        ctx: &'a mut LocalContext<Self::Actor>,
+    ) -> LocalBoxFuture<'a, ()> {
+        let LocalEnvelope {
+            message,
+            result_sender,
+            ..
+        } = *self;
+        Box::pin(act.handle(message, ctx).map(move |r| {
+            if let Some(tx) = result_sender {
+                // We don't actually care if the receiver is listening
+                let _ = tx.send(r);
+            }
+        }))
+    }
+}
+
+/// A message that can be sent by a local address to the manage loop
+enum LocalAddressMessage<A> {
+    /// A message from the last address telling the actor that it should shut down
+    LastAddress,
+    /// A message being sent to the actor
+    Message(Box<dyn LocalMessageEnvelope<Actor = A>>),
+}
+
+/// The future returned by [`LocalAddress::send`](struct.LocalAddress.html#method.send).
+/// It resolves to `Result<M::Result, Disconnected>`.
+#[must_use]
+pub struct LocalSendFuture<A: LocalActor, M: Message>(LocalSendFutureInner<A, M>);
+
+#[derive(Default)]
+enum LocalSendFutureInner<A: LocalActor, M: Message> {
+    #[default]
+    Disconnected,
+    Sending(
+        ChannelSendFuture<'static, LocalAddressMessage<A>>,
+        Receiver<M::Result>,
+    ),
+    Receiving(Receiver<M::Result>),
+}
+
+impl<A: LocalActor, M: Message> Future for LocalSendFuture<A, M> {
+    type Output = Result<M::Result, Disconnected>;
+
+    fn poll(self: Pin<&mut Self>, ctx: &mut TaskContext) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let (poll, new) = match mem::take(&mut this.0) {
+            old @ LocalSendFutureInner::Disconnected => (Poll::Ready(Err(Disconnected)), old),
+            LocalSendFutureInner::Sending(mut tx, mut rx) => match tx.poll_unpin(ctx) {
+                Poll::Ready(Ok(())) => (
+                    address::poll_rx(&mut rx, ctx),
+                    LocalSendFutureInner::Receiving(rx),
+                ),
+                Poll::Ready(Err(_)) => (
+                    Poll::Ready(Err(Disconnected)),
+                    LocalSendFutureInner::Disconnected,
+                ),
+                Poll::Pending => (Poll::Pending, LocalSendFutureInner::Sending(tx, rx)),
+            },
+            LocalSendFutureInner::Receiving(mut rx) => (
+                address::poll_rx(&mut rx, ctx),
+                LocalSendFutureInner::Receiving(rx),
+            ),
+        };
+
+        this.0 = new;
+        poll
+    }
+}
+
+/// An address to a [`LocalActor`](trait.LocalActor.html). Unlike the actor itself, it is `Send`
+/// and `Sync`, so local actors can be sent messages from any thread. Like
+/// [`Address`](../address/struct.Address.html), it is strong by default, and the actor will be
+/// stopped once all strong addresses to it are dropped.
+pub struct LocalAddress<A, Rc: RefCounter = Strong> {
+    sender: Sender<LocalAddressMessage<A>>,
+    ref_counter: Rc,
+}
+
+/// A `WeakLocalAddress` is a [`LocalAddress`](struct.LocalAddress.html) which does not inhibit
+/// the dropping of the actor.
+pub type WeakLocalAddress<A> = LocalAddress<A, Weak>;
+
+impl<A> LocalAddress<A, Strong> {
+    /// Create a weak address to the actor, which will not prevent it from being dropped.
+    pub fn downgrade(&self) -> WeakLocalAddress<A> {
+        LocalAddress {
+            sender: self.sender.clone(),
+            ref_counter: self.ref_counter.downgrade(),
+        }
+    }
+}
+
+impl<A, Rc: RefCounter> LocalAddress<A, Rc> {
+    /// Returns whether the actor referred to by this address is running and accepting messages.
+    pub fn is_connected(&self) -> bool {
+        self.ref_counter.is_connected()
+    }
+
+    /// Returns the number of messages in the actor's mailbox.
+    pub fn len(&self) -> usize {
+        self.sender.len()
+    }
+
+    /// The total capacity of the actor's mailbox.
+    pub fn capacity(&self) -> Option<usize> {
+        self.sender.capacity()
+    }
+
+    /// Returns whether the actor's mailbox is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Send a [`Message`](../trait.Message.html) to the actor without waiting for a response.
+    /// If the actor's mailbox is full, it will block, so this should not be called from the thread
+    /// which the actor is running on if the mailbox is bounded. If this returns
+    /// `Err(Disconnected)`, then the actor is stopped and not accepting messages.
+    pub fn do_send<M>(&self, message: M) -> Result<(), Disconnected>
+    where
+        M: Message,
+        A: LocalHandler<M>,
+    {
+        if self.is_connected() {
+            let envelope = LocalEnvelope::<A, M> {
+                message,
+                result_sender: None,
+                phantom: PhantomData,
+            };
+            self.sender
+                .send(LocalAddressMessage::Message(Box::new(envelope)))
+                .map_err(|_| Disconnected)
+        } else {
+            Err(Disconnected)
+        }
+    }
+
+    /// Send a [`Message`](../trait.Message.html) to the actor and asynchronously wait for a
+    /// response. If this returns `Err(Disconnected)`, then the actor is stopped and not accepting
+    /// messages. Like most futures, this must be polled to actually send the message.
+    pub fn send<M>(&self, message: M) -> LocalSendFuture<A, M>
+    where
+        M: Message,
+        A: LocalHandler<M>,
+    {
+        if self.is_connected() {
+            let (tx, rx) = catty::oneshot();
+            let envelope = LocalEnvelope::<A, M> {
+                message,
+                result_sender: Some(tx),
+                phantom: PhantomData,
+            };
+            let tx = self
+                .sender
+                .clone()
+                .into_send_async(LocalAddressMessage::Message(Box::new(envelope)));
+            LocalSendFuture(LocalSendFutureInner::Sending(tx, rx))
+        } else {
+            LocalSendFuture(LocalSendFutureInner::Disconnected)
+        }
+    }
+
+    /// Waits until this address becomes disconnected.
+    pub fn join(&self) -> impl Future<Output = ()> + Send + Unpin {
+        self.ref_counter.disconnect_notice()
+    }
+}
+
+// Required because #[derive] adds an A: Clone bound
+impl<A, Rc: RefCounter> Clone for LocalAddress<A, Rc> {
+    fn clone(&self) -> Self {
+        LocalAddress {
+            sender: self.sender.clone(),
+            ref_counter: self.ref_counter.clone(),
+        }
+    }
+}
+
+#[derive(Eq, PartialEq, Copy, Clone)]
+enum RunningState {
+    Running,
+    Stopping,
+    Stopped,
+}
+
+/// `LocalContext` is the local counterpart of [`Context`](../struct.Context.html). It is used to
+/// control how the local actor is managed and to get its address from inside of a message handler.
+pub struct LocalContext<A> {
+    running: RunningState,
+    sender: Sender<LocalAddressMessage<A>>,
+    receiver: flume::Receiver<LocalAddressMessage<A>>,
+    ref_counter: Weak,
+    /// Notifications that must be stored for immediate processing, in the order they are handled.
+    self_notifications: VecDeque<Box<dyn LocalMessageEnvelope<Actor = A>>>,
+    /// How many self notifications may be handled in a row while messages are waiting in the
+    /// mailbox.
+    notification_limit: usize,
+    /// Kept alive for as long as the context to keep `LocalAddress::join` pending.
+    _shared_drop_notifier: Arc<DropNotifier>,
+}
+
+impl<A: LocalActor> LocalContext<A> {
+    /// Creates a new local actor context with a given mailbox capacity, returning an address to the
+    /// actor and the context.
+    pub fn new(message_cap: Option<usize>) -> (LocalAddress<A>, Self) {
+        let (sender, receiver) = match message_cap {
+            None => flume::unbounded(),
+            Some(cap) => flume::bounded(cap),
+        };
+
+        let shared_drop_notifier = Arc::new(DropNotifier::new());
+
+        let last_address_sender = sender.clone();
+        let strong = Strong::new(
+            AtomicBool::new(true),
+            shared_drop_notifier.subscribe(),
+            move || {
+                let _ = last_address_sender.send(LocalAddressMessage::LastAddress);
+            },
+        );
+        let weak = strong.downgrade();
+
+        let addr = LocalAddress {
+            sender: sender.clone(),
+            ref_counter: strong,
+        };
+
+        let context = LocalContext {
+            running: RunningState::Running,
+            sender,
+            receiver,
+            ref_counter: weak,
+            self_notifications: VecDeque::new(),
+            notification_limit: DEFAULT_NOTIFICATION_LIMIT,
+            _shared_drop_notifier: shared_drop_notifier,
+        };
+        (addr, context)
+    }
+
+    /// Stop the actor as soon as it has finished processing current message. This will mean that
+    /// the [`LocalActor::stopping`](trait.LocalActor.html#method.stopping) method will be called.
+    pub fn stop(&mut self) {
+        self.running = RunningState::Stopping;
+    }
+
+    /// Get an address to the current actor if there are still external addresses to the actor.
+    pub fn address(&self) -> Result<LocalAddress<A>, ActorShutdown> {
+        Ok(LocalAddress {
+            sender: self.sender.clone(),
+            ref_counter: self.ref_counter.upgrade().ok_or(ActorShutdown)?,
+        })
+    }
+
+    /// Notify this actor with a message that is handled before any other messages from the
+    /// general queue are processed (therefore, immediately).
+    ///
+    /// As with [`Context::notify`](../struct.Context.html#method.notify), a message from the
+    /// mailbox is handled after every so many notifications in a row. See
+    /// [`LocalContext::set_notification_limit`](struct.LocalContext.html#method.set_notification_limit).
+    pub fn notify<M>(&mut self, msg: M)
+    where
+        M: Message,
+        A: LocalHandler<M>,
+    {
+        let envelope = LocalEnvelope::<A, M> {
+            message: msg,
+            result_sender: None,
+            phantom: PhantomData,
+        };
+        self.self_notifications.push_back(Box::new(envelope));
+    }
+
+    /// Notify this actor with a message that is handled before any other notifications which are
+    /// queued, as well as before any messages from the mailbox.
+    pub fn notify_front<M>(&mut self, msg: M)
+    where
+        M: Message,
+        A: LocalHandler<M>,
+    {
+        let envelope = LocalEnvelope::<A, M> {
+            message: msg,
+            result_sender: None,
+            phantom: PhantomData,
+        };
+        self.self_notifications.push_front(Box::new(envelope));
+    }
+
+    /// Sets how many self notifications may be handled in a row while messages are waiting in the
+    /// mailbox. Once the limit is reached, a message from the mailbox is handled before the
+    /// remaining notifications. The default is 64.
+    pub fn set_notification_limit(&mut self, limit: usize) {
+        self.notification_limit = limit.max(1);
+    }
+
+    /// Stop accepting messages from all addresses to this actor.
+    fn stop_all(&mut self) {
+        if let Some(strong) = self.ref_counter.upgrade() {
+            strong.mark_disconnected();
+        }
+
+        self.receiver.drain();
+    }
+
+    /// Check if the context is still set to running, returning whether to continue the manage loop
+    async fn check_running(&mut self, actor: &mut A) -> bool {
+        match self.running {
+            RunningState::Running => true,
+            RunningState::Stopping => match actor.stopping(self).await {
+                KeepRunning::Yes => {
+                    self.running = RunningState::Running;
+                    true
+                }
+                KeepRunning::StopSelf | KeepRunning::StopAll => {
+                    self.stop_all();
+                    self.running = RunningState::Stopped;
+                    false
+                }
+            },
+            RunningState::Stopped => false,
+        }
+    }
+
+    /// Handle all self notifications, returning whether to continue the manage loop
+    async fn handle_self_notifications(&mut self, actor: &mut A) -> bool {
+        // Once the limit is reached, the rest wait until a message from the mailbox is handled
+        let mut handled = 0;
+        while handled < self.notification_limit || self.receiver.is_empty() {
+            let notification = match self.self_notifications.pop_front() {
+                Some(notification) => notification,
+                None => break,
+            };
+
+            notification.handle(actor, self).await;
+            if !self.check_running(actor).await {
+                return false;
+            }
+            handled += 1;
+        }
+
+        true
+    }
+
+    /// Run the given actor's main loop, handling incoming messages to its mailbox.
+    pub fn run(self, actor: A) -> impl Future<Output = A::Stop> {
+        crate::blocking::mark_actor_thread(self.manage(actor))
+    }
+
+    async fn manage(mut self, mut actor: A) -> A::Stop {
+        actor.started(&mut self).await;
+
+        if !self.check_running(&mut actor).await
+            || !self.handle_self_notifications(&mut actor).await
+        {
+            self.stop_all();
+            return actor.stopped().await;
+        }
+
+        let receiver = self.receiver.clone();
+
+        loop {
+            // Notifications which were held back by the notification limit are handled once the
+            // mailbox is empty
+            if !self.self_notifications.is_empty()
+                && receiver.is_empty()
+                && !self.handle_self_notifications(&mut actor).await
+            {
+                break;
+            }
+
+            // The context holds a sender itself, so the channel cannot become disconnected
+            let msg = match receiver.recv_async().await {
+                Ok(msg) => msg,
+                Err(_) => break,
+            };
+
+            match msg {
+                LocalAddressMessage::Message(msg) => msg.handle(&mut actor, &mut self).await,
+                LocalAddressMessage::LastAddress => {
+                    if self.ref_counter.strong_count() == 0 {
+                        self.stop_all();
+                        break;
+                    }
+                }
+            }
+
+            if !self.check_running(&mut actor).await
+                || !self.handle_self_notifications(&mut actor).await
+            {
+                break;
+            }
+        }
+
+        actor.stopped().await
+    }
+}
+
+/// A manager for the local actor, which handles incoming messages and stores the context. Its
+/// managing loop can be started with [`LocalActorManager::run`](struct.LocalActorManager.html#method.run).
+pub struct LocalActorManager<A: LocalActor> {
+    address: LocalAddress<A>,
+    actor: A,
+    ctx: LocalContext<A>,
+}
+
+impl<A: LocalActor<Stop = ()>> LocalActorManager<A> {
+    /// Spawn the actor's main loop on the given local runtime. This will allow it to handle messages.
+    pub fn spawn<S: LocalSpawner>(self, spawner: &mut S) -> LocalAddress<A> {
+        let (addr, fut) = self.run();
+        spawner.spawn_local(fut);
+        addr
+    }
+}
+
+impl<A: LocalActor> LocalActorManager<A> {
+    /// Starts the manager loop, returning the actor's address and its manage future. This will
+    /// start the actor and allow it to respond to messages once the future is polled.
+    pub fn run(self) -> (LocalAddress<A>, impl Future<Output = A::Stop>) {
+        (self.address, self.ctx.run(self.actor))
+    }
+}
@@ -0,0 +1,83 @@
+use std::future::Future;
+
+use flume::{Receiver, RecvError, Sender, TryRecvError};
+use futures_util::future::{self, Either};
+use futures_util::FutureExt;
+
+use crate::manager::AddressMessage;
+
+/// The receiving end of an actor's mailbox. Messages sent to the actor's address arrive through a
+/// channel, and messages from the actor's [durable mailboxes](../durable/index.html), once one has
+/// been opened, through a second channel, which is drained first.
+pub(crate) struct Mailbox<A> {
+    receiver: Receiver<AddressMessage<A>>,
+    durable: Option<Channel<A>>,
+}
+
+type Channel<A> = (Sender<AddressMessage<A>>, Receiver<AddressMessage<A>>);
+
+impl<A> Mailbox<A> {
+    pub(crate) fn new(receiver: Receiver<AddressMessage<A>>) -> Self {
+        Mailbox {
+            receiver,
+            durable: None,
+        }
+    }
+
+    /// Returns the sender of the channel which durable mailboxes put their messages into, creating
+    /// it if no durable mailbox has been opened yet. The channel is unbounded, as its messages are
+    /// kept on disk as well.
+    pub(crate) fn durable_sender(&mut self) -> Sender<AddressMessage<A>> {
+        self.durable.get_or_insert_with(flume::unbounded).0.clone()
+    }
+
+    /// Receives the next message. The error is never returned, as the mailbox holds a sender of
+    /// its durable channel, and its context a sender of the address's channel.
+    pub(crate) fn recv_async(
+        &self,
+    ) -> impl Future<Output = Result<AddressMessage<A>, RecvError>> + Unpin + '_ {
+        match &self.durable {
+            None => Either::Left(self.receiver.recv_async()),
+            Some((_, durable)) => Either::Right(
+                future::select(durable.recv_async(), self.receiver.recv_async())
+                    .map(|next| next.factor_first().0),
+            ),
+        }
+    }
+
+    pub(crate) fn try_recv(&self) -> Result<AddressMessage<A>, TryRecvError> {
+        match &self.durable {
+            Some((_, durable)) if !durable.is_empty() => durable.try_recv(),
+            _ => self.receiver.try_recv(),
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.receiver.len()
+            + self
+                .durable
+                .as_ref()
+                .map_or(0, |(_, durable)| durable.len())
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drops every message which is waiting to be received.
+    pub(crate) fn drain(&self) {
+        self.receiver.drain();
+        if let Some((_, durable)) = &self.durable {
+            durable.drain();
+        }
+    }
+}
+
+impl<A> Clone for Mailbox<A> {
+    fn clone(&self) -> Self {
+        Mailbox {
+            receiver: self.receiver.clone(),
+            durable: self.durable.clone(),
+        }
+    }
+}
@@ -3,20 +3,23 @@
 //! the message type rather than the actor type.
 
 use std::future::Future;
+use std::mem;
 use std::pin::Pin;
 use std::task::{Context, Poll};
 
 use catty::Receiver;
 use futures_core::future::BoxFuture;
+use futures_core::ready;
 use futures_core::stream::BoxStream;
+use futures_util::FutureExt;
 
-use crate::address::{self, Address, Disconnected, WeakAddress};
-use crate::envelope::ReturningEnvelope;
+use crate::address::{self, Address, Disconnected, SendError, WeakAddress};
+use crate::envelope::{LimitedEnvelope, ReturningEnvelope};
 use crate::manager::AddressMessage;
 use crate::private::Sealed;
 use crate::refcount::{RefCounter, Shared, Strong};
-use crate::sink::{AddressSink, MessageSink, StrongMessageSink, WeakMessageSink};
-use crate::{Handler, KeepRunning, Message};
+use crate::sink::{MessageSink, StrongMessageSink, WeakMessageSink};
+use crate::{KeepRunning, Message, NativeHandler};
 
 /// The future returned [`MessageChannel::send`](trait.MessageChannel.html#method.send).
 /// It resolves to `Result<M::Result, Disconnected>`.
@@ -25,16 +28,43 @@ pub struct SendFuture<M: Message>(SendFutureInner<M>);
 
 enum SendFutureInner<M: Message> {
     Disconnected,
+    /// Waiting for the message to be admitted by the actor's limits and sent
+    Sending(
+        BoxFuture<'static, Result<(), Disconnected>>,
+        Receiver<M::Result>,
+    ),
     Result(Receiver<M::Result>),
 }
 
+impl<M: Message> SendFuture<M> {
+    /// A future which resolves to `Err(Disconnected)` straight away.
+    pub(crate) fn disconnected() -> Self {
+        SendFuture(SendFutureInner::Disconnected)
+    }
+}
+
 impl<M: Message> Future for SendFuture<M> {
     type Output = Result<M::Result, Disconnected>;
 
     fn poll(self: Pin<&mut Self>, ctx: &mut Context) -> Poll<Self::Output> {
-        match &mut self.get_mut().0 {
+        let this = self.get_mut();
+
+        if let SendFutureInner::Sending(tx, _) = &mut this.0 {
+            if ready!(tx.poll_unpin(ctx)).is_err() {
+                this.0 = SendFutureInner::Disconnected;
+                return Poll::Ready(Err(Disconnected));
+            }
+
+            this.0 = match mem::replace(&mut this.0, SendFutureInner::Disconnected) {
+                SendFutureInner::Sending(_, rx) => SendFutureInner::Result(rx),
+                other => other,
+            };
+        }
+
+        match &mut this.0 {
             SendFutureInner::Disconnected => Poll::Ready(Err(Disconnected)),
             SendFutureInner::Result(rx) => address::poll_rx(rx, ctx),
+            SendFutureInner::Sending(..) => unreachable!("sending state was resolved above"),
         }
     }
 }
@@ -121,11 +151,12 @@ pub trait MessageChannel<M: Message>: Sealed + Unpin + Send + Sync {
     }
 
     /// Send a [`Message`](../trait.Message.html) to the actor without waiting for a response.
-    /// If this returns `Err(Disconnected)`, then the actor is stopped and not accepting messages.
-    /// If this returns `Ok(())`, the will be delivered, but may not be handled in the event that the
-    /// actor stops itself (by calling [`Context::stop`](../struct.Context.html#method.stop))
-    /// before it was handled.
-    fn do_send(&self, message: M) -> Result<(), Disconnected>;
+    /// If this returns `Err(SendError::Disconnected)`, then the actor is stopped and not accepting
+    /// messages, and if it returns `Err(SendError::RateLimited)`, the message would have exceeded
+    /// the actor's [limits](../limit/index.html) and was not sent. If this returns `Ok(())`, the
+    /// will be delivered, but may not be handled in the event that the actor stops itself (by
+    /// calling [`Context::stop`](../struct.Context.html#method.stop)) before it was handled.
+    fn do_send(&self, message: M) -> Result<(), SendError>;
 
     /// Send a [`Message`](../trait.Message.html) to the actor and asynchronously wait for a response. If this
     /// returns `Err(Disconnected)`, then the actor is stopped and not accepting messages. This,
@@ -147,6 +178,25 @@ pub trait MessageChannel<M: Message>: Sealed + Unpin + Send + Sync {
     where
         M::Result: Into<KeepRunning> + Send;
 
+    /// Attaches a stream to this channel like [`MessageChannel::attach_stream`](trait.MessageChannel.html#method.attach_stream),
+    /// but keeps up to `n` messages from the stream in flight at once. The results are still
+    /// observed in order, and forwarding stops at the first one which does not convert to
+    /// [`KeepRunning::Yes`](../enum.KeepRunning.html#variant.Yes). See
+    /// [`Address::attach_stream_buffered`](../address/struct.Address.html#method.attach_stream_buffered)
+    /// for more details.
+    fn attach_stream_buffered(self, stream: BoxStream<M>, n: usize) -> BoxFuture<()>
+    where
+        M::Result: Into<KeepRunning> + Send;
+
+    /// Attaches a stream to this channel such that all messages produced by it are sent to the
+    /// actor without waiting for them to be handled. Forwarding only stops once the stream ends or
+    /// the actor is disconnected. See
+    /// [`Address::attach_stream_do_send`](../address/struct.Address.html#method.attach_stream_do_send)
+    /// for more details.
+    fn attach_stream_do_send(self, stream: BoxStream<M>) -> BoxFuture<()>
+    where
+        M: Message<Result = ()>;
+
     /// Clones this channel as a boxed trait object.
     fn clone_channel(&self) -> Box<dyn MessageChannel<M>>;
 
@@ -216,7 +266,7 @@ pub trait WeakMessageChannel<M: Message>: MessageChannel<M> {
 
 impl<A, M: Message, Rc: RefCounter> MessageChannel<M> for Address<A, Rc>
 where
-    A: Handler<M>,
+    A: NativeHandler<M>,
 {
     fn is_connected(&self) -> bool {
         self.is_connected()
@@ -230,19 +280,34 @@ where
         self.capacity()
     }
 
-    fn do_send(&self, message: M) -> Result<(), Disconnected> {
+    fn do_send(&self, message: M) -> Result<(), SendError> {
         self.do_send(message)
     }
 
     fn send(&self, message: M) -> SendFuture<M> {
-        if self.is_connected() {
-            let (envelope, rx) = ReturningEnvelope::<A, M>::new(message);
-            let _ = self
-                .sender
-                .send(AddressMessage::Message(Box::new(envelope)));
-            SendFuture(SendFutureInner::Result(rx))
-        } else {
-            SendFuture(SendFutureInner::Disconnected)
+        if !self.is_connected() {
+            return SendFuture(SendFutureInner::Disconnected);
+        }
+
+        let message = match self.interceptors.intercept(message) {
+            Ok(message) => message,
+            Err(_) => return SendFuture(SendFutureInner::Disconnected),
+        };
+        let (envelope, rx) = ReturningEnvelope::<A, M>::new(message, &self.envelopes);
+        match self.limiter.try_acquire() {
+            Ok(permit) => {
+                let _ = self
+                    .sender
+                    .send(AddressMessage::Message(LimitedEnvelope::wrap(
+                        envelope, permit,
+                    )));
+                SendFuture(SendFutureInner::Result(rx))
+            }
+            // The future waits to be admitted, rather than blocking the thread here
+            Err(_) => {
+                let tx = self.do_send_envelope_async(envelope);
+                SendFuture(SendFutureInner::Sending(Box::pin(tx), rx))
+            }
         }
     }
 
@@ -253,15 +318,26 @@ where
         Box::pin(self.attach_stream(stream))
     }
 
+    fn attach_stream_buffered(self, stream: BoxStream<M>, n: usize) -> BoxFuture<()>
+    where
+        M::Result: Into<KeepRunning> + Send,
+    {
+        Box::pin(self.attach_stream_buffered(stream, n))
+    }
+
+    fn attach_stream_do_send(self, stream: BoxStream<M>) -> BoxFuture<()>
+    where
+        M: Message<Result = ()>,
+    {
+        Box::pin(self.attach_stream_do_send(stream))
+    }
+
     fn clone_channel(&self) -> Box<dyn MessageChannel<M>> {
         Box::new(self.clone())
     }
 
     fn sink(&self) -> Box<dyn MessageSink<M>> {
-        Box::new(AddressSink {
-            sink: self.sender.clone().into_sink(),
-            ref_counter: self.ref_counter.clone(),
-        })
+        Box::new(self.clone().into_sink())
     }
 
     fn eq(&self, other: &dyn MessageChannel<M>) -> bool {
@@ -275,7 +351,7 @@ where
 
 impl<A, M: Message> StrongMessageChannel<M> for Address<A, Strong>
 where
-    A: Handler<M>,
+    A: NativeHandler<M>,
 {
     fn downgrade(&self) -> Box<dyn WeakMessageChannel<M>> {
         Box::new(self.downgrade())
@@ -298,16 +374,13 @@ where
     }
 
     fn sink(&self) -> Box<dyn StrongMessageSink<M>> {
-        Box::new(AddressSink {
-            sink: self.sender.clone().into_sink(),
-            ref_counter: self.ref_counter.clone(),
-        })
+        Box::new(self.clone().into_sink())
     }
 }
 
 impl<A, M: Message> WeakMessageChannel<M> for WeakAddress<A>
 where
-    A: Handler<M>,
+    A: NativeHandler<M>,
 {
     /// Upcasts this weak message channel into a boxed generic
     /// [`MessageChannel`](trait.MessageChannel.html) trait object
@@ -326,9 +399,6 @@ where
     }
 
     fn sink(&self) -> Box<dyn WeakMessageSink<M>> {
-        Box::new(AddressSink {
-            sink: self.sender.clone().into_sink(),
-            ref_counter: self.ref_counter.clone(),
-        })
+        Box::new(self.clone().into_sink())
     }
 }
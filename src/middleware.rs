@@ -0,0 +1,483 @@
+//! Middleware which wraps the handling of messages, for concerns which apply to many messages or
+//! actors, such as authorisation, logging, timing or catching panics. A
+//! [`Middleware`](trait.Middleware.html) is added to a single actor with
+//! [`Context::add_middleware`](../struct.Context.html#method.add_middleware), or to every actor with
+//! [`add_global`](fn.add_global.html).
+//!
+//! Middleware wraps the handling of every kind of message: those sent with `send` and `do_send`,
+//! batches, streaming and coalesced messages, self notifications and timers. A batch passes through
+//! the chain once as a whole, which [`Invocation::is_batch`](struct.Invocation.html#method.is_batch)
+//! tells apart. Actors spawned with [`local`](../local/index.html) do not run middleware.
+
+use std::any::{Any, TypeId};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::task::Poll;
+
+use async_trait::async_trait;
+use futures_core::future::BoxFuture;
+use futures_util::future;
+
+use crate::batch::BatchHandler;
+use crate::streaming::{Emitter, StreamingHandler, StreamingMessage};
+use crate::{Actor, ActorManager, Context, Message, MessageName, NativeHandler};
+
+/// Middleware registered with `add_global`, which runs for every actor, with the id of its handle.
+/// It is replaced rather than changed in place, so that a message which is handled holds onto a
+/// snapshot of it without copying it.
+static GLOBAL: RwLock<Option<Arc<[Global]>>> = RwLock::new(None);
+/// Whether `GLOBAL` is non-empty, so that it need not be locked for every message when it is.
+static HAS_GLOBAL: AtomicBool = AtomicBool::new(false);
+/// The id of the next global middleware's handle.
+static NEXT_GLOBAL_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Wraps the handling of messages. The message is handled when the middleware calls
+/// [`Next::run`](struct.Next.html#method.run), which runs the rest of the chain and then the
+/// handler. The middleware can look at or replace the result, or not call `next` at all, in which
+/// case the message is dropped. It can also run the chain again, such as to retry a message which
+/// failed, or catch a panicking handler with
+/// [`Next::run_catching`](struct.Next.html#method.run_catching).
+///
+/// Global middleware runs first, and then the actor's own, each in the order they were added.
+///
+/// # Example
+///
+/// ```
+/// # use std::time::Instant;
+/// use xtra::middleware::{Invocation, Middleware, Next, Outcome};
+///
+/// struct Timing;
+///
+/// #[async_trait::async_trait]
+/// impl Middleware for Timing {
+///     async fn handle(&self, invocation: &Invocation, mut next: Next<'_>) -> Outcome {
+///         let start = Instant::now();
+///         let outcome = next.run().await;
+///         println!(
+///             "{} handled {} in {:?}",
+///             invocation.actor_name(),
+///             invocation.message_name(),
+///             start.elapsed()
+///         );
+///         outcome
+///     }
+/// }
+///
+/// xtra::middleware::add_global(Timing);
+/// ```
+#[async_trait]
+pub trait Middleware: Send + Sync + 'static {
+    /// Handles a message by calling `next`, or drops it by returning
+    /// [`Outcome::dropped`](struct.Outcome.html#method.dropped).
+    async fn handle(&self, invocation: &Invocation, next: Next<'_>) -> Outcome;
+}
+
+/// Adds middleware which runs for every message handled by every actor, across the process. It
+/// stays registered until it is removed through the returned handle.
+pub fn add_global(middleware: impl Middleware) -> GlobalHandle {
+    let id = NEXT_GLOBAL_ID.fetch_add(1, Ordering::Relaxed);
+    let mut global = GLOBAL.write().unwrap();
+    let mut added = global.as_deref().unwrap_or_default().to_vec();
+    added.push((id, Arc::new(middleware)));
+    *global = Some(added.into());
+    HAS_GLOBAL.store(true, Ordering::Release);
+    GlobalHandle(id)
+}
+
+/// A global middleware, with the id of its handle.
+type Global = (usize, Arc<dyn Middleware>);
+
+/// A handle to middleware added with [`add_global`](fn.add_global.html). Dropping the handle does
+/// not remove the middleware.
+#[derive(Debug)]
+pub struct GlobalHandle(usize);
+
+impl GlobalHandle {
+    /// Removes the middleware, so that it does not run for messages which are handled from now on.
+    pub fn remove(self) {
+        let mut global = GLOBAL.write().unwrap();
+        let remaining: Vec<_> = global
+            .as_deref()
+            .unwrap_or_default()
+            .iter()
+            .filter(|(id, _)| *id != self.0)
+            .cloned()
+            .collect();
+        HAS_GLOBAL.store(!remaining.is_empty(), Ordering::Release);
+        *global = match remaining.is_empty() {
+            true => None,
+            false => Some(remaining.into()),
+        };
+    }
+}
+
+/// Describes the message which is being handled.
+#[derive(Copy, Clone, Debug)]
+pub struct Invocation {
+    actor_name: &'static str,
+    message_name: &'static str,
+    message_type: TypeId,
+    batch: bool,
+}
+
+impl Invocation {
+    /// Returns the type name of the actor which handles the message.
+    pub fn actor_name(&self) -> &'static str {
+        self.actor_name
+    }
+
+    /// Returns the type name of the message.
+    pub fn message_name(&self) -> &'static str {
+        self.message_name
+    }
+
+    /// Returns whether the message is of type `M`, or is a batch of messages of type `M`.
+    pub fn is<M: 'static>(&self) -> bool {
+        self.message_type == TypeId::of::<M>()
+    }
+
+    /// Returns whether this is a batch of messages, sent with
+    /// [`Address::do_send_batch`](../address/struct.Address.html#method.do_send_batch) or merged
+    /// into one. The message is then a `Vec` of the messages, and its result is `()`.
+    pub fn is_batch(&self) -> bool {
+        self.batch
+    }
+}
+
+/// The rest of the middleware chain, ending with the message's handler.
+pub struct Next<'a> {
+    chain: &'a mut (dyn Chain + 'a),
+    index: usize,
+}
+
+impl<'a> Next<'a> {
+    /// Runs the rest of the chain and the handler, returning the result of the message.
+    ///
+    /// The handler takes the message, so running the chain again drops the message unless it is
+    /// given a new one with [`Next::run_with`](#method.run_with).
+    pub async fn run(&mut self) -> Outcome {
+        self.chain.run(self.index).await
+    }
+
+    /// Runs the rest of the chain and the handler with the given message, such as a copy of the
+    /// original taken with [`Next::message`](#method.message) to retry it. The message is dropped if
+    /// it is not of the type that is being handled.
+    ///
+    /// A streaming message can only be handled once, as its handler takes the response stream.
+    pub async fn run_with<M: Send + 'static>(&mut self, message: M) -> Outcome {
+        match self.chain.message_mut().downcast_mut::<Option<M>>() {
+            Some(slot) => *slot = Some(message),
+            None => return Outcome::dropped(),
+        }
+
+        self.run().await
+    }
+
+    /// Like [`Next::run`](#method.run), but catches a panic in the rest of the chain or in the
+    /// handler, returning its payload. The middleware can then turn it into a result, such as an
+    /// error of the message's result type, instead of stopping the actor. As the handler did not
+    /// finish, the actor's state may be inconsistent.
+    pub async fn run_catching(&mut self) -> Result<Outcome, Box<dyn Any + Send>> {
+        let mut run = self.chain.run(self.index);
+        future::poll_fn(move |cx| {
+            match panic::catch_unwind(AssertUnwindSafe(|| run.as_mut().poll(cx))) {
+                Ok(Poll::Ready(outcome)) => Poll::Ready(Ok(outcome)),
+                Ok(Poll::Pending) => Poll::Pending,
+                Err(payload) => Poll::Ready(Err(payload)),
+            }
+        })
+        .await
+    }
+
+    /// Returns the message which is yet to be handled, if it is of type `M`. It is `None` once the
+    /// handler has taken it. For a batch, `M` is the `Vec` of its messages.
+    pub fn message<M: 'static>(&self) -> Option<&M> {
+        self.chain.message().downcast_ref::<Option<M>>()?.as_ref()
+    }
+}
+
+/// The result of handling a message, as seen by middleware.
+pub struct Outcome(Option<Box<dyn Any + Send>>);
+
+impl Outcome {
+    /// An outcome in which the message is dropped. If it was sent with
+    /// [`Address::send`](../address/struct.Address.html#method.send), the sender receives
+    /// `Err(Disconnected)`.
+    pub fn dropped() -> Self {
+        Outcome(None)
+    }
+
+    /// An outcome with the given result. If the result is not of the message's result type, the
+    /// message is dropped.
+    pub fn result<T: Send + 'static>(result: T) -> Self {
+        Outcome(Some(Box::new(result)))
+    }
+
+    /// Returns whether the message was dropped.
+    pub fn is_dropped(&self) -> bool {
+        self.0.is_none()
+    }
+
+    /// Returns the result if it is of type `T`.
+    pub fn downcast_ref<T: 'static>(&self) -> Option<&T> {
+        self.0.as_ref()?.downcast_ref()
+    }
+
+    /// Returns the result mutably if it is of type `T`.
+    pub fn downcast_mut<T: 'static>(&mut self) -> Option<&mut T> {
+        self.0.as_mut()?.downcast_mut()
+    }
+}
+
+/// A type-erased middleware chain, which `Next` runs from a given middleware onwards.
+trait Chain: Send {
+    /// Runs the chain from the middleware at `index`, or the handler if there is none.
+    fn run(&mut self, index: usize) -> BoxFuture<'_, Outcome>;
+
+    /// The message which is yet to be handled, as an `Option`.
+    fn message(&self) -> &dyn Any;
+
+    /// The message which is yet to be handled, as an `Option`.
+    fn message_mut(&mut self) -> &mut dyn Any;
+}
+
+/// Handles a message at the end of a middleware chain.
+trait Terminal<A: Actor, T>: Send {
+    fn handle<'a>(
+        &'a mut self,
+        act: &'a mut A,
+        message: T,
+        ctx: &'a mut Context<A>,
+    ) -> BoxFuture<'a, Outcome>;
+}
+
+/// Handles a single message with its `Handler`. Without middleware, the handler is called directly
+/// instead, so that its result need not be boxed.
+struct Single;
+
+impl<A: NativeHandler<M>, M: Message> Terminal<A, M> for Single {
+    fn handle<'a>(
+        &'a mut self,
+        act: &'a mut A,
+        message: M,
+        ctx: &'a mut Context<A>,
+    ) -> BoxFuture<'a, Outcome> {
+        Box::pin(async move { Outcome::result(NativeHandler::handle(act, message, ctx).await) })
+    }
+}
+
+/// Handles a batch of messages with its `BatchHandler`.
+struct Batch;
+
+impl<A: BatchHandler<M>, M: Message> Terminal<A, Vec<M>> for Batch {
+    fn handle<'a>(
+        &'a mut self,
+        act: &'a mut A,
+        messages: Vec<M>,
+        ctx: &'a mut Context<A>,
+    ) -> BoxFuture<'a, Outcome> {
+        Box::pin(async move {
+            act.handle_batch(messages, ctx).await;
+            Outcome::result(())
+        })
+    }
+}
+
+/// Handles a streaming message with its `StreamingHandler`, which takes the emitter.
+struct Streaming<T>(Option<Emitter<T>>);
+
+impl<A, M> Terminal<A, M> for Streaming<M::Item>
+where
+    A: StreamingHandler<M>,
+    M: StreamingMessage,
+{
+    fn handle<'a>(
+        &'a mut self,
+        act: &'a mut A,
+        message: M,
+        ctx: &'a mut Context<A>,
+    ) -> BoxFuture<'a, Outcome> {
+        match self.0.take() {
+            Some(emitter) => Box::pin(async move {
+                StreamingHandler::handle(act, message, emitter.hand_over(), ctx).await;
+                Outcome::result(())
+            }),
+            None => Box::pin(future::ready(Outcome::dropped())),
+        }
+    }
+}
+
+/// The state of a middleware chain while a message is handled through it.
+struct Stages<'a, A: Actor, T, H> {
+    act: &'a mut A,
+    ctx: &'a mut Context<A>,
+    message: Option<T>,
+    terminal: H,
+    global: &'a [Global],
+    local: &'a [Arc<dyn Middleware>],
+    invocation: Invocation,
+}
+
+impl<'a, A, T, H> Chain for Stages<'a, A, T, H>
+where
+    A: Actor,
+    T: Send + 'static,
+    H: Terminal<A, T>,
+{
+    fn run(&mut self, index: usize) -> BoxFuture<'_, Outcome> {
+        let middleware = match self.global.get(index) {
+            Some((_, middleware)) => Some(middleware),
+            None => self.local.get(index - self.global.len()),
+        };
+
+        if let Some(middleware) = middleware {
+            let middleware = middleware.clone();
+            let invocation = self.invocation;
+            let next = Next {
+                chain: self,
+                index: index + 1,
+            };
+            return Box::pin(
+                async move { Middleware::handle(&*middleware, &invocation, next).await },
+            );
+        }
+
+        match self.message.take() {
+            Some(message) => self.terminal.handle(self.act, message, self.ctx),
+            None => Box::pin(future::ready(Outcome::dropped())),
+        }
+    }
+
+    fn message(&self) -> &dyn Any {
+        &self.message
+    }
+
+    fn message_mut(&mut self) -> &mut dyn Any {
+        &mut self.message
+    }
+}
+
+impl<A: Actor> ActorManager<A> {
+    /// Adds middleware to the actor, as with
+    /// [`Context::add_middleware`](struct.Context.html#method.add_middleware).
+    pub fn with_middleware(mut self, middleware: impl Middleware) -> Self {
+        self.ctx.add_middleware(middleware);
+        self
+    }
+}
+
+impl<A: Actor> Context<A> {
+    /// Adds middleware which runs for every message handled by this actor, after any global
+    /// middleware and the actor's middleware which was added before it. It is not shared with
+    /// other actors on the same address.
+    pub fn add_middleware(&mut self, middleware: impl Middleware) {
+        let mut added = self.middleware.to_vec();
+        added.push(Arc::new(middleware));
+        self.middleware = added.into();
+    }
+}
+
+/// Returns whether any middleware runs for the actor's messages.
+fn has_middleware<A>(ctx: &Context<A>) -> bool {
+    HAS_GLOBAL.load(Ordering::Acquire) || !ctx.middleware.is_empty()
+}
+
+/// Handles a message through the global middleware and the actor's own, returning its result, or
+/// `None` if the middleware dropped it.
+pub(crate) async fn handle<A, M>(act: &mut A, message: M, ctx: &mut Context<A>) -> Option<M::Result>
+where
+    A: NativeHandler<M>,
+    M: Message,
+{
+    if !has_middleware(ctx) {
+        return Some(NativeHandler::handle(act, message, ctx).await);
+    }
+
+    let invocation = Invocation {
+        actor_name: std::any::type_name::<A>(),
+        message_name: message.name(),
+        message_type: TypeId::of::<M>(),
+        batch: false,
+    };
+
+    let outcome = run_chain(act, message, ctx, Single, invocation).await;
+    let result = outcome.0?.downcast::<M::Result>().ok()?;
+    Some(*result)
+}
+
+/// Handles a batch of messages through the middleware.
+pub(crate) async fn handle_batch<A, M>(act: &mut A, messages: Vec<M>, ctx: &mut Context<A>)
+where
+    A: BatchHandler<M>,
+    M: Message,
+{
+    if !has_middleware(ctx) {
+        return act.handle_batch(messages, ctx).await;
+    }
+
+    let invocation = Invocation {
+        actor_name: std::any::type_name::<A>(),
+        message_name: std::any::type_name::<M>(),
+        message_type: TypeId::of::<M>(),
+        batch: true,
+    };
+
+    run_chain(act, messages, ctx, Batch, invocation).await;
+}
+
+/// Handles a streaming message through the middleware. If the middleware drops it, the response
+/// stream ends with `Err(Disconnected)`.
+pub(crate) async fn handle_streaming<A, M>(
+    act: &mut A,
+    message: M,
+    emitter: Emitter<M::Item>,
+    ctx: &mut Context<A>,
+) where
+    A: StreamingHandler<M>,
+    M: StreamingMessage,
+{
+    if !has_middleware(ctx) {
+        return StreamingHandler::handle(act, message, emitter.hand_over(), ctx).await;
+    }
+
+    let invocation = Invocation {
+        actor_name: std::any::type_name::<A>(),
+        message_name: std::any::type_name::<M>(),
+        message_type: TypeId::of::<M>(),
+        batch: false,
+    };
+
+    run_chain(act, message, ctx, Streaming(Some(emitter)), invocation).await;
+}
+
+/// Runs the global middleware, the actor's own and then `terminal` for the message.
+async fn run_chain<A, T, H>(
+    act: &mut A,
+    message: T,
+    ctx: &mut Context<A>,
+    terminal: H,
+    invocation: Invocation,
+) -> Outcome
+where
+    A: Actor,
+    T: Send + 'static,
+    H: Terminal<A, T>,
+{
+    let global = match HAS_GLOBAL.load(Ordering::Acquire) {
+        true => GLOBAL.read().unwrap().clone(),
+        false => None,
+    };
+    let local = ctx.middleware.clone();
+
+    let mut stages = Stages {
+        act,
+        ctx,
+        message: Some(message),
+        terminal,
+        global: global.as_deref().unwrap_or_default(),
+        local: &local,
+        invocation,
+    };
+    stages.run(0).await
+}
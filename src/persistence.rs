@@ -0,0 +1,497 @@
+//! Event-sourced actors. A [`PersistentActor`](trait.PersistentActor.html) changes its state only
+//! by applying events, which it writes to a [`Journal`](trait.Journal.html) before applying them.
+//! When the actor starts, it recovers its state by replaying the events in its journal. Recovery is
+//! enabled with [`ActorManager::with_persistence`](../struct.ActorManager.html#method.with_persistence)
+//! or [`Context::enable_persistence`](../struct.Context.html#method.enable_persistence).
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use futures_core::future::BoxFuture;
+
+use crate::{Actor, ActorManager, Context};
+
+/// An actor whose state is the result of applying a sequence of events, which are kept in a
+/// [`Journal`](trait.Journal.html).
+///
+/// The actor is recovered before [`Actor::started`](../trait.Actor.html#method.started) runs once
+/// persistence is enabled with
+/// [`ActorManager::with_persistence`](../struct.ActorManager.html#method.with_persistence), so that
+/// the events have been replayed before the actor handles any messages. Handlers then change the
+/// actor's state by calling [`PersistentActor::persist`](#method.persist), which writes the event to
+/// the journal and then applies it.
+///
+/// # Example
+///
+/// ```
+/// # use std::convert::TryInto;
+/// # use std::sync::Arc;
+/// # use xtra::prelude::*;
+/// # use xtra::spawn::Smol;
+/// use xtra::persistence::{Codec, DecodeError, Journal, MemoryJournal, PersistentActor};
+///
+/// struct Deposited(u64);
+///
+/// impl Codec for Deposited {
+///     fn encode(&self) -> Vec<u8> {
+///         self.0.to_le_bytes().to_vec()
+///     }
+///
+///     fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+///         let bytes = bytes.try_into().map_err(|_| DecodeError::new("expected 8 bytes"))?;
+///         Ok(Deposited(u64::from_le_bytes(bytes)))
+///     }
+/// }
+///
+/// struct Account {
+///     journal: MemoryJournal,
+///     balance: u64,
+/// }
+///
+/// #[async_trait::async_trait]
+/// impl Actor for Account {
+///     type Stop = ();
+///
+///     async fn stopped(self) {}
+/// }
+///
+/// impl PersistentActor for Account {
+///     type Event = Deposited;
+///
+///     fn persistence_id(&self) -> String {
+///         "account".to_string()
+///     }
+///
+///     fn journal(&self) -> Arc<dyn Journal> {
+///         Arc::new(self.journal.clone())
+///     }
+///
+///     fn apply(&mut self, event: &Deposited) {
+///         self.balance += event.0;
+///     }
+/// }
+///
+/// let account = Account { journal: MemoryJournal::new(), balance: 0 };
+/// let addr = account.create(None).with_persistence().spawn(&mut Smol::Global);
+/// ```
+#[async_trait]
+pub trait PersistentActor: Actor {
+    /// The events which change the actor's state.
+    type Event: Codec + Send + Sync + 'static;
+
+    /// The id of the actor's stream of events in its journal. Two actors with the same id share
+    /// their events, so the id should be unique and stay the same across restarts.
+    fn persistence_id(&self) -> String;
+
+    /// Returns the journal which the actor's events are written to.
+    fn journal(&self) -> Arc<dyn Journal>;
+
+    /// Applies an event to the actor's state. This is called both when an event has just been
+    /// persisted, and when it is replayed during recovery, so it must not have side effects.
+    fn apply(&mut self, event: &Self::Event);
+
+    /// Writes the event to the actor's journal with
+    /// [`Context::persist`](../struct.Context.html#method.persist), and then applies it. If the
+    /// event could not be written, it is not applied.
+    async fn persist(
+        &mut self,
+        event: Self::Event,
+        ctx: &mut Context<Self>,
+    ) -> Result<(), JournalError> {
+        ctx.persist(&event).await?;
+        self.apply(&event);
+        Ok(())
+    }
+}
+
+/// Recovers the actor's state by applying every event in its journal, in order. This is done before
+/// the actor starts once persistence is enabled with
+/// [`Context::enable_persistence`](../struct.Context.html#method.enable_persistence), but can
+/// instead be called by hand, such as from [`Actor::started`](../trait.Actor.html#method.started)
+/// to handle the error. It must be called before the actor can persist events. If the actor was
+/// restored from a [snapshot](../snapshot/index.html), only the events after the snapshot are
+/// applied.
+pub async fn recover<A: PersistentActor>(
+    actor: &mut A,
+    ctx: &mut Context<A>,
+) -> Result<(), JournalError> {
+    ctx.persistent = true;
+    let journal = actor.journal();
+    let persistence_id = actor.persistence_id();
+    // Events up to the restored snapshot, if any, are already part of the actor's state
+    let mut sequence = ctx
+        .snapshots
+        .as_ref()
+        .map_or(0, |snapshots| snapshots.sequence);
+
+    for (event_sequence, bytes) in journal.read(&persistence_id, sequence + 1).await? {
+        actor.apply(&A::Event::decode(&bytes)?);
+        sequence = event_sequence;
+    }
+
+    ctx.persistence = Some(Persistence {
+        journal,
+        persistence_id,
+        sequence,
+    });
+
+    Ok(())
+}
+
+fn recover_boxed<'a, A: PersistentActor>(
+    actor: &'a mut A,
+    ctx: &'a mut Context<A>,
+) -> BoxFuture<'a, Result<(), JournalError>> {
+    Box::pin(recover(actor, ctx))
+}
+
+/// Recovers a `PersistentActor`, kept in its context so that the context can recover any actor.
+pub(crate) type Recovery<A> =
+    for<'a> fn(&'a mut A, &'a mut Context<A>) -> BoxFuture<'a, Result<(), JournalError>>;
+
+impl<A: PersistentActor> ActorManager<A> {
+    /// Recovers the actor from its journal before it starts, as with
+    /// [`Context::enable_persistence`](struct.Context.html#method.enable_persistence).
+    pub fn with_persistence(mut self) -> Self {
+        self.ctx.enable_persistence();
+        self
+    }
+}
+
+impl<A: PersistentActor> Context<A> {
+    /// Enables persistence for the actor which this context runs. Before
+    /// [`Actor::started`](trait.Actor.html#method.started) runs, the actor is recovered with
+    /// [`persistence::recover`](persistence/fn.recover.html), after it has been restored from its
+    /// latest snapshot if [snapshots](struct.Context.html#method.enable_snapshots) are enabled.
+    ///
+    /// This must be called before the context is run. If recovery fails, the error is logged and
+    /// the actor is stopped without being started, as its state would be missing the events which
+    /// could not be replayed. To handle the error instead, call
+    /// [`persistence::recover`](persistence/fn.recover.html) by hand.
+    pub fn enable_persistence(&mut self) {
+        self.recovery = Some(recover_boxed::<A>);
+        self.persistent = true;
+    }
+
+    /// Writes an event to the actor's journal, returning its sequence number. This does not apply
+    /// the event - [`PersistentActor::persist`](persistence/trait.PersistentActor.html#method.persist)
+    /// does both. It fails if the actor has not been recovered with
+    /// [`persistence::recover`](persistence/fn.recover.html).
+    pub async fn persist(&mut self, event: &A::Event) -> Result<u64, JournalError> {
+        let persistence = self
+            .persistence
+            .as_mut()
+            .ok_or(JournalError::NotRecovered)?;
+
+        let sequence = persistence
+            .journal
+            .append(&persistence.persistence_id, event.encode())
+            .await?;
+        persistence.sequence = sequence;
+
+        Ok(sequence)
+    }
+}
+
+impl<A: Actor> Context<A> {
+    /// Returns whether the actor has been recovered from its journal, and so can persist events.
+    pub fn is_recovered(&self) -> bool {
+        self.persistence.is_some()
+    }
+
+    /// Recovers the actor from its journal, if persistence is enabled, returning whether it may be
+    /// started.
+    pub(crate) async fn recover_persistence(&mut self, actor: &mut A) -> bool {
+        let recovery = match self.recovery.take() {
+            Some(recovery) => recovery,
+            None => return true,
+        };
+
+        match recovery(actor, self).await {
+            Ok(()) => true,
+            Err(err) => {
+                log::error!(
+                    "Actor {} could not be recovered from its journal, and is stopped: {}",
+                    std::any::type_name::<A>(),
+                    err
+                );
+                false
+            }
+        }
+    }
+}
+
+/// The journal of a recovered `PersistentActor`, kept in its context.
+pub(crate) struct Persistence {
+    pub(crate) journal: Arc<dyn Journal>,
+    pub(crate) persistence_id: String,
+    /// The sequence number of the last event which was applied.
+    pub(crate) sequence: u64,
+}
+
+/// How events are turned into bytes to be stored in a [`Journal`](trait.Journal.html), and back.
+pub trait Codec: Sized {
+    /// Encodes the event.
+    fn encode(&self) -> Vec<u8>;
+
+    /// Decodes an event which was encoded with [`Codec::encode`](#tymethod.encode).
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError>;
+}
+
+/// The error returned when a stored event cannot be decoded.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DecodeError(String);
+
+impl DecodeError {
+    /// Creates an error with the given reason.
+    pub fn new(reason: impl Into<String>) -> Self {
+        DecodeError(reason.into())
+    }
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Could not decode event: {}", self.0)
+    }
+}
+
+impl Error for DecodeError {}
+
+/// An append-only store of events, in streams identified by a persistence id. Each event gets a
+/// sequence number, counting up from 1 within its stream.
+///
+/// The journal's futures are awaited by the actor's manage loop, so an implementation which does
+/// blocking I/O, such as [`FileJournal`](struct.FileJournal.html), blocks the executor thread while
+/// it does so.
+#[async_trait]
+pub trait Journal: Send + Sync {
+    /// Appends an event to the end of a stream, returning its sequence number.
+    async fn append(&self, persistence_id: &str, event: Vec<u8>) -> Result<u64, JournalError>;
+
+    /// Reads the events of a stream, starting from the given sequence number, in order.
+    async fn read(
+        &self,
+        persistence_id: &str,
+        from: u64,
+    ) -> Result<Vec<(u64, Vec<u8>)>, JournalError>;
+}
+
+/// An error from a [`Journal`](trait.Journal.html).
+#[derive(Debug)]
+pub enum JournalError {
+    /// Reading or writing the journal failed.
+    Io(io::Error),
+    /// A stored event could not be decoded.
+    Decode(DecodeError),
+    /// The actor tried to persist an event before it was recovered.
+    NotRecovered,
+}
+
+impl Display for JournalError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            JournalError::Io(err) => write!(f, "Journal I/O failed: {}", err),
+            JournalError::Decode(err) => Display::fmt(err, f),
+            JournalError::NotRecovered => {
+                f.write_str("Actor must be recovered before it can persist events")
+            }
+        }
+    }
+}
+
+impl Error for JournalError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            JournalError::Io(err) => Some(err),
+            JournalError::Decode(err) => Some(err),
+            JournalError::NotRecovered => None,
+        }
+    }
+}
+
+impl From<io::Error> for JournalError {
+    fn from(err: io::Error) -> Self {
+        JournalError::Io(err)
+    }
+}
+
+impl From<DecodeError> for JournalError {
+    fn from(err: DecodeError) -> Self {
+        JournalError::Decode(err)
+    }
+}
+
+/// A journal which keeps events in memory. Clones share the same events, so a clone can be given
+/// to an actor and another kept to inspect the events or to recover a new actor from them.
+#[derive(Clone, Default)]
+pub struct MemoryJournal {
+    streams: Arc<Mutex<HashMap<String, Vec<Vec<u8>>>>>,
+}
+
+impl MemoryJournal {
+    /// Creates an empty journal.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl Journal for MemoryJournal {
+    async fn append(&self, persistence_id: &str, event: Vec<u8>) -> Result<u64, JournalError> {
+        let mut streams = self.streams.lock().unwrap();
+        let stream = streams.entry(persistence_id.to_string()).or_default();
+        stream.push(event);
+        Ok(stream.len() as u64)
+    }
+
+    async fn read(
+        &self,
+        persistence_id: &str,
+        from: u64,
+    ) -> Result<Vec<(u64, Vec<u8>)>, JournalError> {
+        let streams = self.streams.lock().unwrap();
+        let events = match streams.get(persistence_id) {
+            Some(stream) => stream
+                .iter()
+                .enumerate()
+                .map(|(i, event)| (i as u64 + 1, event.clone()))
+                .filter(|(sequence, _)| *sequence >= from)
+                .collect(),
+            None => Vec::new(),
+        };
+
+        Ok(events)
+    }
+}
+
+/// A journal which appends events to files in a directory, one file per stream. Each event is
+/// written as its length followed by its bytes, and synced to disk before `append` returns.
+///
+/// An event which was only partly written, for instance because the process crashed, is cut off
+/// the end of the file when the stream is next read. If writing or syncing an event fails, the file
+/// is cut back to where it was, so that the event is not read later after being reported as failed.
+///
+/// The file operations, including waiting for `sync_data`, block the thread which polls `append` or
+/// `read` while holding the journal's lock, so appends from actors sharing a journal wait on each
+/// other. This suits actors for which an occasional short wait on the disk is acceptable. Otherwise,
+/// the journal can be wrapped in one which moves the calls onto the runtime's blocking thread pool.
+pub struct FileJournal {
+    directory: PathBuf,
+    /// The number of events in each stream which has been read or appended to so far.
+    lengths: Mutex<HashMap<String, u64>>,
+}
+
+impl FileJournal {
+    /// Opens a journal in the given directory, creating the directory if it does not exist.
+    pub fn open(directory: impl Into<PathBuf>) -> Result<Self, JournalError> {
+        let directory = directory.into();
+        fs::create_dir_all(&directory)?;
+
+        Ok(FileJournal {
+            directory,
+            lengths: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// The file of a stream.
+    fn path(&self, persistence_id: &str) -> PathBuf {
+        self.directory
+            .join(format!("{}.journal", file_stem(persistence_id)))
+    }
+
+    /// Reads every event in a stream, cutting off a partly written event at the end.
+    fn read_all(&self, persistence_id: &str) -> Result<Vec<Vec<u8>>, JournalError> {
+        let path = self.path(persistence_id);
+        let mut bytes = Vec::new();
+        match File::open(&path) {
+            Ok(mut file) => file.read_to_end(&mut bytes)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut events = Vec::new();
+        let mut offset = 0;
+
+        while let Some(header) = bytes.get(offset..offset + 4) {
+            let len = u32::from_le_bytes([header[0], header[1], header[2], header[3]]) as usize;
+            match bytes.get(offset + 4..offset + 4 + len) {
+                Some(event) => events.push(event.to_vec()),
+                None => break,
+            }
+            offset += 4 + len;
+        }
+
+        if offset < bytes.len() {
+            OpenOptions::new()
+                .write(true)
+                .open(&path)?
+                .set_len(offset as u64)?;
+        }
+
+        Ok(events)
+    }
+}
+
+#[async_trait]
+impl Journal for FileJournal {
+    async fn append(&self, persistence_id: &str, event: Vec<u8>) -> Result<u64, JournalError> {
+        let mut lengths = self.lengths.lock().unwrap();
+        let length = match lengths.get(persistence_id) {
+            Some(length) => *length,
+            None => self.read_all(persistence_id)?.len() as u64,
+        };
+
+        let len = u32::try_from(event.len()).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "an event must be shorter than 4 GiB",
+            )
+        })?;
+        let mut record = Vec::with_capacity(4 + event.len());
+        record.extend_from_slice(&len.to_le_bytes());
+        record.extend_from_slice(&event);
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.path(persistence_id))?;
+        let offset = file.seek(SeekFrom::End(0))?;
+        if let Err(err) = file.write_all(&record).and_then(|()| file.sync_data()) {
+            // Cut off what was written of the record, so that a failed event is not read later
+            let _ = file.set_len(offset);
+            return Err(err.into());
+        }
+
+        lengths.insert(persistence_id.to_string(), length + 1);
+        Ok(length + 1)
+    }
+
+    async fn read(
+        &self,
+        persistence_id: &str,
+        from: u64,
+    ) -> Result<Vec<(u64, Vec<u8>)>, JournalError> {
+        let mut lengths = self.lengths.lock().unwrap();
+        let events = self.read_all(persistence_id)?;
+        lengths.insert(persistence_id.to_string(), events.len() as u64);
+
+        Ok(events
+            .into_iter()
+            .enumerate()
+            .map(|(i, event)| (i as u64 + 1, event))
+            .filter(|(sequence, _)| *sequence >= from)
+            .collect())
+    }
+}
+
+/// Hex-encodes an id, so that any id makes a valid file name.
+pub(crate) fn file_stem(id: &str) -> String {
+    id.bytes().map(|byte| format!("{:02x}", byte)).collect()
+}
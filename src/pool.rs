@@ -0,0 +1,47 @@
+//! A pool of envelopes whose messages have been handled, so that sending a message can reuse the
+//! envelope of an earlier one of the same type rather than allocating another. It is shared
+//! between an actor's addresses, which take envelopes from it, and its context, which gives them
+//! back once it has taken their messages out.
+
+use std::any::{Any, TypeId};
+use std::sync::Mutex;
+
+/// How many envelopes of each type are kept. Envelopes given back beyond this are dropped, so that
+/// a burst of messages does not keep its memory for the lifetime of the actor.
+const KEPT_PER_TYPE: usize = 64;
+
+#[derive(Default)]
+pub(crate) struct EnvelopePool {
+    /// The envelopes kept for each type. An actor handles few types of messages, so this is
+    /// searched rather than hashed.
+    free: Mutex<Vec<(TypeId, Envelopes)>>,
+}
+
+/// Envelopes of a single type, boxed so that envelopes of every type can be kept together.
+type Envelopes = Vec<Box<dyn Any + Send>>;
+
+impl EnvelopePool {
+    /// Takes an envelope of type `E` to reuse, if one is kept.
+    pub(crate) fn take<E: Any + Send>(&self) -> Option<Box<E>> {
+        let mut free = self.free.lock().unwrap();
+        let (_, envelopes) = free.iter_mut().find(|(ty, _)| *ty == TypeId::of::<E>())?;
+        envelopes.pop()?.downcast().ok()
+    }
+
+    /// Keeps an envelope to be reused, unless enough of its type are kept already.
+    pub(crate) fn give_back<E: Any + Send>(&self, envelope: Box<E>) {
+        let mut free = self.free.lock().unwrap();
+        let index = match free.iter().position(|(ty, _)| *ty == TypeId::of::<E>()) {
+            Some(index) => index,
+            None => {
+                free.push((TypeId::of::<E>(), Vec::with_capacity(KEPT_PER_TYPE)));
+                free.len() - 1
+            }
+        };
+
+        let envelopes = &mut free[index].1;
+        if envelopes.len() < KEPT_PER_TYPE {
+            envelopes.push(envelope);
+        }
+    }
+}
@@ -22,14 +22,29 @@ pub struct Strong {
 pub struct Shared {
     connected: AtomicBool,
     drop_notice: DropNotice,
+    /// Called once the last strong reference has been dropped. This is done here rather than in the
+    /// `Drop` impl of the address, as the strong count would otherwise only be decremented after the
+    /// actor had been notified, causing it to miss that it should stop.
+    on_last_drop: Box<dyn Fn() + Send + Sync>,
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        (self.on_last_drop)();
+    }
 }
 
 impl Strong {
-    pub(crate) fn new(connected: AtomicBool, drop_notice: DropNotice) -> Self {
+    pub(crate) fn new(
+        connected: AtomicBool,
+        drop_notice: DropNotice,
+        on_last_drop: impl Fn() + Send + Sync + 'static,
+    ) -> Self {
         Self {
             shared: Arc::new(Shared {
                 connected,
                 drop_notice,
+                on_last_drop: Box::new(on_last_drop),
             }),
             lock: Arc::new(RwLock::new(())),
         }
@@ -91,13 +106,8 @@ pub trait RefCounter: Sealed + Clone + Unpin + Send + Sync + 'static {
     #[doc(hidden)]
     fn is_connected(&self) -> bool;
     #[doc(hidden)]
-    fn is_last_strong(&self) -> bool;
-    #[doc(hidden)]
     fn strong_count(&self) -> usize;
 
-    // These above two methods cannot be merged since is_last_strong is always false for Weak. If
-    // strong_count were used to implement this, a weak being dropped could think it were a strong.
-
     #[doc(hidden)]
     fn into_either(self) -> Either;
 
@@ -113,11 +123,6 @@ impl RefCounter for Strong {
         self.strong_count() > 0 && self.shared.connected.load(Ordering::Acquire)
     }
 
-    fn is_last_strong(&self) -> bool {
-        let _lock = self.lock.read().unwrap();
-        Arc::strong_count(&self.shared) == 1
-    }
-
     fn strong_count(&self) -> usize {
         let _lock = self.lock.read().unwrap();
         Arc::strong_count(&self.shared)
@@ -154,10 +159,6 @@ impl RefCounter for Weak {
         self.strong_count() > 0 && running
     }
 
-    fn is_last_strong(&self) -> bool {
-        false
-    }
-
     fn strong_count(&self) -> usize {
         let _lock = self.lock.read().unwrap();
         ArcWeak::strong_count(&self.shared)
@@ -193,13 +194,6 @@ impl RefCounter for Either {
         }
     }
 
-    fn is_last_strong(&self) -> bool {
-        match self {
-            Either::Strong(strong) => strong.is_last_strong(),
-            Either::Weak(weak) => weak.is_last_strong(),
-        }
-    }
-
     fn strong_count(&self) -> usize {
         match self {
             Either::Strong(strong) => strong.strong_count(),
@@ -0,0 +1,382 @@
+//! Calendar scheduling of actor notifications. A [`CronSchedule`](struct.CronSchedule.html) is
+//! used with [`Context::notify_cron`](../struct.Context.html#method.notify_cron), and a single
+//! point in time with [`Context::notify_at`](../struct.Context.html#method.notify_at). Unlike
+//! [`Context::notify_interval`](../struct.Context.html#method.notify_interval), these follow the
+//! wall clock, so they stay on schedule if the system clock is adjusted or the machine sleeps.
+
+use std::collections::hash_map::RandomState;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use futures_timer::Delay;
+use futures_util::future::{self, Either};
+
+use crate::drop_notice::DropNotice;
+use crate::timer::{self, Command, TimerCommands};
+
+/// The longest that a scheduled timer sleeps before checking the wall clock again, so that it
+/// notices if the clock has jumped.
+const MAX_SLEEP: Duration = Duration::from_secs(1);
+
+/// What a scheduled timer does when it finds that one or more ticks were missed, for instance
+/// because the machine was asleep or the actor's mailbox was full.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum MissedTickPolicy {
+    /// Drop the missed ticks and carry on with the next tick which is still in the future.
+    #[default]
+    Skip,
+    /// Notify the actor once for every missed tick, as quickly as possible.
+    Burst,
+    /// Notify the actor once for all of the missed ticks, and then carry on with the next tick
+    /// which is in the future.
+    Delay,
+}
+
+/// A schedule given by a cron expression, evaluated in UTC.
+///
+/// The expression has six space-separated fields: second (0-59), minute (0-59), hour (0-23), day
+/// of the month (1-31), month (1-12) and day of the week (0-6, where 0 and 7 are Sunday). A
+/// five-field expression without the seconds is also accepted, and fires at the start of the
+/// minute. Each field is either `*`, a number, a range `a-b`, or a comma-separated list of these,
+/// each optionally followed by a step `/n`. As in cron, if both the day of the month and the day
+/// of the week are restricted, a day matches if either of them does.
+///
+/// # Example
+///
+/// ```
+/// use std::time::Duration;
+/// use xtra::schedule::{CronSchedule, MissedTickPolicy};
+///
+/// // Every five minutes, up to ten seconds late to spread out the load
+/// let schedule: CronSchedule = "0 */5 * * * *".parse().unwrap();
+/// let schedule = schedule
+///     .missed_ticks(MissedTickPolicy::Delay)
+///     .jitter(Duration::from_secs(10));
+/// ```
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct CronSchedule {
+    seconds: u64,
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    days_of_week: u64,
+    any_day_of_month: bool,
+    any_day_of_week: bool,
+    missed_ticks: MissedTickPolicy,
+    jitter: Duration,
+}
+
+impl CronSchedule {
+    /// Parses a cron expression. See the type's documentation for the syntax.
+    pub fn parse(expression: &str) -> Result<Self, CronError> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let fields = match fields.len() {
+            6 => fields,
+            5 => std::iter::once("0").chain(fields).collect(),
+            n => return Err(CronError(format!("expected 5 or 6 fields, found {}", n))),
+        };
+
+        let mut days_of_week = parse_field(fields[5], 0, 7, "day of week")?;
+        if days_of_week & (1 << 7) != 0 {
+            days_of_week |= 1; // 7 is also Sunday
+        }
+
+        Ok(CronSchedule {
+            seconds: parse_field(fields[0], 0, 59, "second")?,
+            minutes: parse_field(fields[1], 0, 59, "minute")?,
+            hours: parse_field(fields[2], 0, 23, "hour")?,
+            days_of_month: parse_field(fields[3], 1, 31, "day of month")?,
+            months: parse_field(fields[4], 1, 12, "month")?,
+            days_of_week,
+            any_day_of_month: fields[3] == "*",
+            any_day_of_week: fields[5] == "*",
+            missed_ticks: MissedTickPolicy::default(),
+            jitter: Duration::from_secs(0),
+        })
+    }
+
+    /// Sets what to do when ticks were missed. By default, they are skipped.
+    pub fn missed_ticks(mut self, policy: MissedTickPolicy) -> Self {
+        self.missed_ticks = policy;
+        self
+    }
+
+    /// Delays each notification by a random duration of up to `jitter`, so that many actors on the
+    /// same schedule do not all fire at once. There is no jitter by default.
+    pub fn jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Returns the first time matching this schedule which is strictly after the given time, or
+    /// `None` if the schedule never matches (such as on the 30th of February).
+    pub fn next_after(&self, time: SystemTime) -> Option<SystemTime> {
+        let start = time
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs() as i64);
+        let mut secs = start + 1;
+        let give_up_year = civil_from_days(start.div_euclid(DAY)).0 + 400;
+
+        loop {
+            let days = secs.div_euclid(DAY);
+            let (year, month, day) = civil_from_days(days);
+            if year > give_up_year {
+                return None; // The calendar repeats every 400 years
+            }
+
+            if !matches(self.months, month) {
+                secs = next_month_start(year, month);
+                continue;
+            }
+
+            if !self.matches_day(day, weekday(days)) {
+                secs = (days + 1) * DAY;
+                continue;
+            }
+
+            let second_of_day = secs.rem_euclid(DAY);
+            let (hour, minute) = (second_of_day / 3600, second_of_day % 3600 / 60);
+            if !matches(self.hours, hour) {
+                secs = days * DAY + (hour + 1) * 3600;
+                continue;
+            }
+
+            if !matches(self.minutes, minute) {
+                secs = days * DAY + hour * 3600 + (minute + 1) * 60;
+                continue;
+            }
+
+            if !matches(self.seconds, second_of_day % 60) {
+                secs += 1;
+                continue;
+            }
+
+            return Some(UNIX_EPOCH + Duration::from_secs(secs as u64));
+        }
+    }
+
+    fn matches_day(&self, day_of_month: i64, day_of_week: i64) -> bool {
+        let dom = matches(self.days_of_month, day_of_month);
+        let dow = matches(self.days_of_week, day_of_week);
+
+        match (self.any_day_of_month, self.any_day_of_week) {
+            (false, false) => dom || dow,
+            _ => dom && dow,
+        }
+    }
+}
+
+impl FromStr for CronSchedule {
+    type Err = CronError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        CronSchedule::parse(s)
+    }
+}
+
+/// The error returned when a cron expression is invalid.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct CronError(String);
+
+impl Display for CronError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid cron expression: {}", self.0)
+    }
+}
+
+impl Error for CronError {}
+
+/// When a scheduled timer fires.
+pub(crate) enum Schedule {
+    Cron(CronSchedule),
+    At(SystemTime),
+}
+
+impl Schedule {
+    fn first(&self) -> Option<SystemTime> {
+        match self {
+            Schedule::Cron(cron) => cron.next_after(SystemTime::now()),
+            Schedule::At(time) => Some(*time),
+        }
+    }
+
+    /// Returns when to fire next, given that the timer was due at `due` and it is now `now`.
+    fn next(&self, due: SystemTime, now: SystemTime) -> Option<SystemTime> {
+        let cron = match self {
+            Schedule::Cron(cron) => cron,
+            Schedule::At(_) => return None,
+        };
+
+        let next = cron.next_after(due)?;
+        if next > now {
+            return Some(next);
+        }
+
+        match cron.missed_ticks {
+            MissedTickPolicy::Skip => cron.next_after(now),
+            MissedTickPolicy::Burst => Some(next),
+            MissedTickPolicy::Delay => Some(now),
+        }
+    }
+
+    fn jitter(&self) -> Duration {
+        match self {
+            Schedule::Cron(cron) if cron.jitter > Duration::from_secs(0) => {
+                let random = RandomState::new().build_hasher().finish();
+                Duration::from_nanos(random % cron.jitter.as_nanos().max(1) as u64)
+            }
+            _ => Duration::from_secs(0),
+        }
+    }
+}
+
+/// Runs a scheduled timer, calling `fire` whenever the schedule is due. `fire` returns a future
+/// which delivers the tick and resolves to whether the timer should carry on. The timer ends once
+/// the schedule has no more ticks, it is cancelled, a tick was not delivered or the context which
+/// created it is dropped.
+pub(crate) async fn run<F, Fut>(
+    commands: TimerCommands,
+    mut stopped: DropNotice,
+    schedule: Schedule,
+    mut fire: F,
+) where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = bool>,
+{
+    let mut due = schedule.first();
+
+    while let Some(time) = due {
+        let fire_at = time + schedule.jitter();
+
+        loop {
+            let listener = commands.listen();
+            match commands.take() {
+                Some(Command::Cancel) => return,
+                Some(Command::Reset(duration)) => {
+                    // Fire once after the duration, then carry on with the schedule from there
+                    due = Some(SystemTime::now() + duration);
+                    break;
+                }
+                None => {}
+            }
+
+            let remaining = match fire_at.duration_since(SystemTime::now()) {
+                Ok(remaining) if remaining > Duration::from_secs(0) => remaining,
+                _ => {
+                    if !timer::deliver(&commands, &mut stopped, fire()).await {
+                        return;
+                    }
+
+                    due = schedule.next(time, SystemTime::now());
+                    break;
+                }
+            };
+
+            let delay = Delay::new(remaining.min(MAX_SLEEP));
+            if let Either::Right((Either::Right(_), _)) =
+                future::select(delay, future::select(listener, &mut stopped)).await
+            {
+                return; // The context stopped
+            }
+        }
+    }
+}
+
+const DAY: i64 = 24 * 60 * 60;
+
+fn matches(set: u64, value: i64) -> bool {
+    set & (1 << value) != 0
+}
+
+/// Parses one field of a cron expression into a bit set of the values it matches.
+fn parse_field(field: &str, min: u32, max: u32, name: &str) -> Result<u64, CronError> {
+    let invalid = || CronError(format!("invalid {} field `{}`", name, field));
+    let number = |s: &str| -> Result<u32, CronError> {
+        s.parse::<u32>()
+            .ok()
+            .filter(|n| (min..=max).contains(n))
+            .ok_or_else(invalid)
+    };
+
+    let mut set = 0;
+
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().map_err(|_| invalid())?),
+            None => (part, 1),
+        };
+
+        if step == 0 {
+            return Err(invalid());
+        }
+
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (number(start)?, number(end)?),
+                // `a/n` means from a to the maximum, every n
+                None if part.contains('/') => (number(range)?, max),
+                None => (number(range)?, number(range)?),
+            },
+        };
+
+        if start > end {
+            return Err(invalid());
+        }
+
+        for value in (start..=end).step_by(step as usize) {
+            set |= 1 << value;
+        }
+    }
+
+    Ok(set)
+}
+
+/// Converts days since the Unix epoch into a (year, month, day) date in the proleptic Gregorian
+/// calendar. This is Howard Hinnant's `civil_from_days` algorithm.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    (year, month, day)
+}
+
+/// The inverse of `civil_from_days`.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+
+    era * 146_097 + doe - 719_468
+}
+
+/// The day of the week of the given number of days since the Unix epoch, where 0 is Sunday.
+fn weekday(days: i64) -> i64 {
+    (days + 4).rem_euclid(7) // 1970-01-01 was a Thursday
+}
+
+/// The second at which the month after the given one starts, in seconds since the Unix epoch.
+fn next_month_start(year: i64, month: i64) -> i64 {
+    let (year, month) = if month == 12 {
+        (year + 1, 1)
+    } else {
+        (year, month + 1)
+    };
+
+    days_from_civil(year, month, 1) * DAY
+}
@@ -2,26 +2,39 @@
 //! [`MessageChannel`](../message_channel/trait.MessageChannel.html).
 
 use std::pin::Pin;
+use std::sync::Arc;
 use std::task::{Context, Poll};
 
 use flume::r#async::SendSink;
+use futures_core::ready;
 use futures_sink::Sink;
-use futures_util::SinkExt;
 
-use crate::address::Disconnected;
-use crate::envelope::NonReturningEnvelope;
+use crate::address::SendError;
+use crate::envelope::{LimitedEnvelope, NonReturningEnvelope};
+use crate::intercept::Interceptors;
+use crate::limit::{Admission, Limiter};
 use crate::manager::AddressMessage;
 use crate::private::Sealed;
 use crate::refcount::{RefCounter, Strong, Weak};
-use crate::{Actor, Handler, Message};
+use crate::{Actor, Message, NativeHandler};
 
 /// An `AddressSink` is the [futures `Sink`](https://docs.rs/futures/0.3/futures/io/struct.Sink.html)
 /// returned by [`Address::into_sink`](../address/struct.Address.html#method.into_sink). Similarly to with
 /// addresses, the strong variety of `AddressSink` will prevent the actor from being dropped, whereas
 /// the [weak variety](struct.AddressSink.html) will not.
+///
+/// A message is sent once it is admitted by the actor's [limits](../limit/index.html), which
+/// `poll_ready` waits for. A message which is sent without waiting for `poll_ready` first is
+/// rejected with `SendError::RateLimited` if it would exceed them. The sink's errors are
+/// [`SendError`](../address/enum.SendError.html)s, so that this and a message refused by an
+/// [interceptor](../intercept/index.html) can be told apart from a stopped actor.
 pub struct AddressSink<A: 'static, Rc: RefCounter = Strong> {
     pub(crate) sink: SendSink<'static, AddressMessage<A>>,
     pub(crate) ref_counter: Rc,
+    pub(crate) limiter: Arc<Limiter>,
+    /// The admission which `poll_ready` waited for, taken by the next message
+    pub(crate) admission: Admission,
+    pub(crate) interceptors: Interceptors,
 }
 
 impl<A, Rc: RefCounter> Clone for AddressSink<A, Rc> {
@@ -29,6 +42,9 @@ impl<A, Rc: RefCounter> Clone for AddressSink<A, Rc> {
         AddressSink {
             sink: self.sink.clone(),
             ref_counter: self.ref_counter.clone(),
+            limiter: self.limiter.clone(),
+            admission: Admission::default(),
+            interceptors: self.interceptors.clone(),
         }
     }
 }
@@ -51,55 +67,59 @@ impl<A> AddressSink<A, Strong> {
         AddressSink {
             sink: self.sink.clone(),
             ref_counter: self.ref_counter.downgrade(),
-        }
-    }
-}
-
-impl<A, Rc: RefCounter> Drop for AddressSink<A, Rc> {
-    fn drop(&mut self) {
-        // We should notify the ActorManager that there are no more strong Addresses and the actor
-        // should be stopped.
-        if self.ref_counter.is_last_strong() {
-            let _ = pollster::block_on(self.sink.send(AddressMessage::LastAddress));
+            limiter: self.limiter.clone(),
+            admission: Admission::default(),
+            interceptors: self.interceptors.clone(),
         }
     }
 }
 
 impl<A, Rc: RefCounter, M: Message> Sink<M> for AddressSink<A, Rc>
 where
-    A: Handler<M>,
+    A: NativeHandler<M>,
 {
-    type Error = Disconnected;
+    type Error = SendError;
 
     fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        Pin::new(&mut self.sink)
+        let this = &mut *self;
+        ready!(this.admission.poll_admit(&this.limiter, cx));
+        Pin::new(&mut this.sink)
             .poll_ready(cx)
-            .map_err(|_| Disconnected)
+            .map_err(|_| SendError::Disconnected)
     }
 
     fn start_send(mut self: Pin<&mut Self>, item: M) -> Result<(), Self::Error> {
-        let item = AddressMessage::Message(Box::new(NonReturningEnvelope::new(item)));
+        let item = self.interceptors.intercept(item)?;
+        let permit = match self.admission.take() {
+            Some(permit) => permit,
+            None => self
+                .limiter
+                .try_acquire()
+                .map_err(|_| SendError::RateLimited)?,
+        };
+        let envelope = Box::new(NonReturningEnvelope::new(item));
+        let item = AddressMessage::Message(LimitedEnvelope::wrap(envelope, permit));
         Pin::new(&mut self.sink)
             .start_send(item)
-            .map_err(|_| Disconnected)
+            .map_err(|_| SendError::Disconnected)
     }
 
     fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
         Pin::new(&mut self.sink)
             .poll_flush(cx)
-            .map_err(|_| Disconnected)
+            .map_err(|_| SendError::Disconnected)
     }
 
     fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
         Pin::new(&mut self.sink)
             .poll_close(cx)
-            .map_err(|_| Disconnected)
+            .map_err(|_| SendError::Disconnected)
     }
 }
 
 /// A `MessageSink` is similar to a [`MessageChannel`](../message_channel/trait.MessageChannel.html),
 /// but it is a sink and operates asynchronously.
-pub trait MessageSink<M: Message>: Sealed + Sink<M, Error = Disconnected> + Unpin + Send {
+pub trait MessageSink<M: Message>: Sealed + Sink<M, Error = SendError> + Unpin + Send {
     /// Returns whether the actor referred to by this message sink is running and accepting messages.
     fn is_connected(&self) -> bool;
 
@@ -159,7 +179,7 @@ pub trait StrongMessageSink<M: Message>: MessageSink<M> {
 
 impl<A: Actor, M: Message, Rc: RefCounter> MessageSink<M> for AddressSink<A, Rc>
 where
-    A: Handler<M>,
+    A: NativeHandler<M>,
 {
     fn is_connected(&self) -> bool {
         self.ref_counter.is_connected()
@@ -180,17 +200,17 @@ where
 
 impl<A: Actor, M: Message> StrongMessageSink<M> for AddressSink<A, Strong>
 where
-    A: Handler<M>,
+    A: NativeHandler<M>,
 {
     fn downgrade(self) -> Box<dyn WeakMessageSink<M>> {
         Box::new(AddressSink::downgrade(&self))
     }
 
-    fn upcast(self) -> Box<dyn MessageSink<M, Error = Disconnected>> {
+    fn upcast(self) -> Box<dyn MessageSink<M, Error = SendError>> {
         Box::new(self)
     }
 
-    fn upcast_ref(&self) -> &dyn MessageSink<M, Error = Disconnected> {
+    fn upcast_ref(&self) -> &dyn MessageSink<M, Error = SendError> {
         self
     }
 
@@ -201,13 +221,13 @@ where
 
 impl<A: Actor, M: Message> WeakMessageSink<M> for AddressSink<A, Weak>
 where
-    A: Handler<M>,
+    A: NativeHandler<M>,
 {
-    fn upcast(self) -> Box<dyn MessageSink<M, Error = Disconnected>> {
+    fn upcast(self) -> Box<dyn MessageSink<M, Error = SendError>> {
         Box::new(self)
     }
 
-    fn upcast_ref(&self) -> &dyn MessageSink<M, Error = Disconnected> {
+    fn upcast_ref(&self) -> &dyn MessageSink<M, Error = SendError> {
         self
     }
 
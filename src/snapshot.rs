@@ -0,0 +1,341 @@
+//! Snapshots of actor state. A [`SnapshotActor`](trait.SnapshotActor.html) is restored from the
+//! latest snapshot in its [`SnapshotStore`](trait.SnapshotStore.html) before
+//! [`Actor::started`](../trait.Actor.html#method.started) runs, and snapshotted again every so
+//! many messages and once it stops. Snapshots are enabled with
+//! [`ActorManager::with_snapshots`](../struct.ActorManager.html#method.with_snapshots) or
+//! [`Context::enable_snapshots`](../struct.Context.html#method.enable_snapshots).
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+
+use crate::persistence::{file_stem, DecodeError};
+use crate::{Actor, ActorManager, Context};
+
+/// An actor whose state can be saved to and restored from a
+/// [`SnapshotStore`](trait.SnapshotStore.html).
+///
+/// If the actor is also a [`PersistentActor`](../persistence/trait.PersistentActor.html), each
+/// snapshot records the sequence number of the last event which the actor had applied, and
+/// [`persistence::recover`](../persistence/fn.recover.html) only replays the events after it. For
+/// this, the snapshot id must be the same as the persistence id.
+///
+/// # Example
+///
+/// ```
+/// # use std::convert::TryInto;
+/// # use std::sync::Arc;
+/// # use xtra::prelude::*;
+/// # use xtra::spawn::Smol;
+/// use xtra::persistence::DecodeError;
+/// use xtra::snapshot::{MemorySnapshotStore, SnapshotActor, SnapshotStore};
+///
+/// struct Counter {
+///     store: MemorySnapshotStore,
+///     count: u64,
+/// }
+///
+/// # #[async_trait::async_trait] impl Actor for Counter {type Stop = (); async fn stopped(self) -> Self::Stop {} }
+/// impl SnapshotActor for Counter {
+///     fn snapshot_id(&self) -> String {
+///         "counter".to_string()
+///     }
+///
+///     fn snapshot_store(&self) -> Arc<dyn SnapshotStore> {
+///         Arc::new(self.store.clone())
+///     }
+///
+///     // Snapshot after every hundred messages, as well as when stopping
+///     fn snapshot_every(&self) -> Option<u64> {
+///         Some(100)
+///     }
+///
+///     fn snapshot(&self) -> Vec<u8> {
+///         self.count.to_le_bytes().to_vec()
+///     }
+///
+///     fn restore(&mut self, state: &[u8]) -> Result<(), DecodeError> {
+///         let bytes = state.try_into().map_err(|_| DecodeError::new("expected 8 bytes"))?;
+///         self.count = u64::from_le_bytes(bytes);
+///         Ok(())
+///     }
+/// }
+///
+/// smol::block_on(async {
+///     let store = MemorySnapshotStore::new();
+///     let addr = Counter { store, count: 0 }
+///         .create(None)
+///         .with_snapshots()
+///         .spawn(&mut Smol::Global);
+/// })
+/// ```
+pub trait SnapshotActor: Actor {
+    /// The id under which the actor's snapshots are stored. It should be unique and stay the same
+    /// across restarts.
+    fn snapshot_id(&self) -> String;
+
+    /// Returns the store which the actor's snapshots are saved to.
+    fn snapshot_store(&self) -> Arc<dyn SnapshotStore>;
+
+    /// Returns after how many messages a snapshot is taken. Messages sent to the actor's address
+    /// and broadcasts are counted, but self notifications are not. By default, the actor is only
+    /// snapshotted when it stops.
+    fn snapshot_every(&self) -> Option<u64> {
+        None
+    }
+
+    /// Encodes the actor's state.
+    fn snapshot(&self) -> Vec<u8>;
+
+    /// Replaces the actor's state with a state encoded by
+    /// [`SnapshotActor::snapshot`](#tymethod.snapshot).
+    fn restore(&mut self, state: &[u8]) -> Result<(), DecodeError>;
+}
+
+impl<A: SnapshotActor> ActorManager<A> {
+    /// Enables snapshots for the actor, as with
+    /// [`Context::enable_snapshots`](struct.Context.html#method.enable_snapshots).
+    pub fn with_snapshots(mut self) -> Self {
+        self.ctx.enable_snapshots();
+        self
+    }
+}
+
+impl<A: SnapshotActor> Context<A> {
+    /// Enables snapshots for the actor which this context runs. Before
+    /// [`Actor::started`](trait.Actor.html#method.started) runs, the actor is restored from its
+    /// latest snapshot, if there is one. A snapshot is then taken every
+    /// [`SnapshotActor::snapshot_every`](snapshot/trait.SnapshotActor.html#method.snapshot_every)
+    /// messages, and once the actor stops, before
+    /// [`Actor::stopped`](trait.Actor.html#method.stopped) runs.
+    ///
+    /// This must be called before the context is run. If restoring fails, the actor starts from its
+    /// initial state and is not snapshotted, so that the snapshot which failed to restore is not
+    /// overwritten. A [`PersistentActor`](persistence/trait.PersistentActor.html) is not snapshotted
+    /// until it has been recovered from its journal either, as the snapshot could not record which
+    /// events it contains.
+    pub fn enable_snapshots(&mut self) {
+        self.snapshots = Some(Snapshots {
+            id: A::snapshot_id,
+            store: A::snapshot_store,
+            every: A::snapshot_every,
+            take: A::snapshot,
+            restore: A::restore,
+            since: 0,
+            sequence: 0,
+        });
+    }
+}
+
+/// The snapshot hooks of a `SnapshotActor`, kept in its context so that the context can snapshot
+/// any actor.
+pub(crate) struct Snapshots<A> {
+    id: fn(&A) -> String,
+    store: fn(&A) -> Arc<dyn SnapshotStore>,
+    every: fn(&A) -> Option<u64>,
+    take: fn(&A) -> Vec<u8>,
+    restore: fn(&mut A, &[u8]) -> Result<(), DecodeError>,
+    /// How many messages have been handled since the last snapshot.
+    since: u64,
+    /// The sequence number of the last event in the restored snapshot.
+    pub(crate) sequence: u64,
+}
+
+impl<A: Actor> Context<A> {
+    /// Restores the actor from its latest snapshot, if snapshots are enabled.
+    pub(crate) async fn restore_snapshot(&mut self, actor: &mut A) {
+        let snapshots = match self.snapshots.as_mut() {
+            Some(snapshots) => snapshots,
+            None => return,
+        };
+
+        let id = (snapshots.id)(actor);
+        let restored = match (snapshots.store)(actor).load(&id).await {
+            Ok(Some(snapshot)) => (snapshots.restore)(actor, &snapshot.state)
+                .map(|()| snapshot.sequence)
+                .map_err(|err| err.to_string()),
+            Ok(None) => Ok(0),
+            Err(err) => Err(err.to_string()),
+        };
+
+        match restored {
+            Ok(sequence) => snapshots.sequence = sequence,
+            Err(err) => {
+                log::error!(
+                    "Actor {} could not be restored from snapshot {}, and will not be snapshotted: {}",
+                    std::any::type_name::<A>(),
+                    id,
+                    err
+                );
+                self.snapshots = None;
+            }
+        }
+    }
+
+    /// Counts a handled message, taking a snapshot if enough messages have been handled since the
+    /// last one.
+    pub(crate) async fn count_snapshot_message(&mut self, actor: &mut A) {
+        let due = match self.snapshots.as_mut() {
+            Some(snapshots) => {
+                snapshots.since += 1;
+                (snapshots.every)(actor).is_some_and(|every| snapshots.since >= every)
+            }
+            None => false,
+        };
+
+        if due {
+            self.save_snapshot(actor).await;
+        }
+    }
+
+    /// Takes a snapshot of the actor, if snapshots are enabled. The actor is borrowed mutably
+    /// because `&A` is only `Send` if `A: Sync`.
+    pub(crate) async fn save_snapshot(&mut self, actor: &mut A) {
+        let snapshots = match self.snapshots.as_mut() {
+            Some(snapshots) => snapshots,
+            None => return,
+        };
+
+        snapshots.since = 0;
+        let id = (snapshots.id)(actor);
+        let sequence = match (&self.persistence, self.persistent) {
+            (Some(persistence), _) => persistence.sequence,
+            // Without the sequence number of the last applied event, every event would be replayed
+            // onto the snapshot, including those which it already contains
+            (None, true) => {
+                log::warn!(
+                    "Snapshot {} of actor {} was not saved, as the actor has not been recovered",
+                    id,
+                    std::any::type_name::<A>()
+                );
+                return;
+            }
+            (None, false) => 0,
+        };
+        let snapshot = Snapshot {
+            sequence,
+            state: (snapshots.take)(actor),
+        };
+
+        if let Err(err) = (snapshots.store)(actor).save(&id, snapshot).await {
+            log::warn!(
+                "Snapshot {} of actor {} could not be saved: {}",
+                id,
+                std::any::type_name::<A>(),
+                err
+            );
+        }
+    }
+}
+
+/// A snapshot of an actor's state.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Snapshot {
+    /// The sequence number of the last journal event which the actor had applied, or 0 if it is
+    /// not a [`PersistentActor`](../persistence/trait.PersistentActor.html).
+    pub sequence: u64,
+    /// The actor's state, as encoded by
+    /// [`SnapshotActor::snapshot`](trait.SnapshotActor.html#tymethod.snapshot).
+    pub state: Vec<u8>,
+}
+
+/// A store which keeps the latest snapshot under each id.
+#[async_trait]
+pub trait SnapshotStore: Send + Sync {
+    /// Saves a snapshot, replacing the previous snapshot with the same id.
+    async fn save(&self, id: &str, snapshot: Snapshot) -> io::Result<()>;
+
+    /// Loads the latest snapshot with the given id, if there is one.
+    async fn load(&self, id: &str) -> io::Result<Option<Snapshot>>;
+}
+
+/// A snapshot store which keeps snapshots in memory. Clones share the same snapshots.
+#[derive(Clone, Default)]
+pub struct MemorySnapshotStore {
+    snapshots: Arc<Mutex<HashMap<String, Snapshot>>>,
+}
+
+impl MemorySnapshotStore {
+    /// Creates an empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl SnapshotStore for MemorySnapshotStore {
+    async fn save(&self, id: &str, snapshot: Snapshot) -> io::Result<()> {
+        self.snapshots
+            .lock()
+            .unwrap()
+            .insert(id.to_string(), snapshot);
+        Ok(())
+    }
+
+    async fn load(&self, id: &str) -> io::Result<Option<Snapshot>> {
+        Ok(self.snapshots.lock().unwrap().get(id).cloned())
+    }
+}
+
+/// A snapshot store which keeps each snapshot in a file in a directory. A snapshot is written to
+/// a temporary file, synced to disk and then moved over the previous one, so a crash while saving
+/// leaves the previous snapshot in place. The file operations block.
+pub struct FileSnapshotStore {
+    directory: PathBuf,
+}
+
+impl FileSnapshotStore {
+    /// Opens a store in the given directory, creating the directory if it does not exist.
+    pub fn open(directory: impl Into<PathBuf>) -> io::Result<Self> {
+        let directory = directory.into();
+        fs::create_dir_all(&directory)?;
+        Ok(FileSnapshotStore { directory })
+    }
+
+    fn path(&self, id: &str, extension: &str) -> PathBuf {
+        self.directory
+            .join(format!("{}.{}", file_stem(id), extension))
+    }
+}
+
+#[async_trait]
+impl SnapshotStore for FileSnapshotStore {
+    async fn save(&self, id: &str, snapshot: Snapshot) -> io::Result<()> {
+        let temporary = self.path(id, "snapshot.tmp");
+        let mut file = File::create(&temporary)?;
+        file.write_all(&snapshot.sequence.to_le_bytes())?;
+        file.write_all(&snapshot.state)?;
+        file.sync_data()?;
+
+        fs::rename(&temporary, self.path(id, "snapshot"))
+    }
+
+    async fn load(&self, id: &str) -> io::Result<Option<Snapshot>> {
+        let mut bytes = Vec::new();
+        match File::open(self.path(id, "snapshot")) {
+            Ok(mut file) => file.read_to_end(&mut bytes)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+
+        if bytes.len() < 8 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "snapshot file is too short",
+            ));
+        }
+
+        let (sequence, state) = bytes.split_at(8);
+        let mut buf = [0; 8];
+        buf.copy_from_slice(sequence);
+
+        Ok(Some(Snapshot {
+            sequence: u64::from_le_bytes(buf),
+            state: state.to_vec(),
+        }))
+    }
+}
@@ -9,6 +9,14 @@ pub use tokio_impl::*;
 #[cfg(feature = "with-wasm_bindgen-0_2")]
 pub use wasm_bindgen_impl::*;
 
+pub use thread_impl::*;
+
+#[cfg(any(
+    feature = "with-async_std-1",
+    feature = "with-smol-1",
+    feature = "with-tokio-1",
+    feature = "with-wasm_bindgen-0_2"
+))]
 use crate::{Actor, ActorManager, Address};
 
 /// An `Spawner` represents anything that can spawn a future to be run in the background. This is
@@ -18,6 +26,13 @@ pub trait Spawner {
     fn spawn<F: Future<Output = ()> + Send + 'static>(&mut self, fut: F);
 }
 
+/// A `LocalSpawner` represents anything that can spawn a future which is not `Send` to be run in
+/// the background on the current thread. This is used to spawn [local actors](../local/index.html).
+pub trait LocalSpawner {
+    /// Spawn the given future onto the current thread.
+    fn spawn_local<F: Future<Output = ()> + 'static>(&mut self, fut: F);
+}
+
 #[cfg(feature = "with-async_std-1")]
 mod async_std_impl {
     use super::*;
@@ -32,6 +47,12 @@ mod async_std_impl {
         }
     }
 
+    impl LocalSpawner for AsyncStd {
+        fn spawn_local<F: Future<Output = ()> + 'static>(&mut self, fut: F) {
+            async_std::task::spawn_local(fut);
+        }
+    }
+
     /// An extension trait used to allow ergonomic spawning of an actor onto the global runtime.
     pub trait AsyncStdGlobalSpawnExt<A: Actor> {
         /// Spawn the actor onto the global runtime
@@ -50,20 +71,15 @@ mod smol_impl {
     use super::*;
 
     /// The smol runtime.
-    #[derive(Copy, Clone, Debug)]
+    #[derive(Copy, Clone, Debug, Default)]
     pub enum Smol<'a> {
         /// The global executor.
+        #[default]
         Global,
         /// A specific smol executor.
         Handle(&'a smol::Executor<'a>),
     }
 
-    impl<'a> Default for Smol<'a> {
-        fn default() -> Self {
-            Smol::Global
-        }
-    }
-
     impl<'a> Spawner for Smol<'a> {
         fn spawn<F: Future<Output = ()> + Send + 'static>(&mut self, fut: F) {
             let task = match self {
@@ -74,6 +90,17 @@ mod smol_impl {
         }
     }
 
+    /// A smol [`LocalExecutor`](https://docs.rs/smol/1/smol/struct.LocalExecutor.html), which
+    /// can spawn futures that are not `Send`.
+    #[derive(Copy, Clone, Debug)]
+    pub struct SmolLocal<'a>(pub &'a smol::LocalExecutor<'static>);
+
+    impl<'a> LocalSpawner for SmolLocal<'a> {
+        fn spawn_local<F: Future<Output = ()> + 'static>(&mut self, fut: F) {
+            self.0.spawn(fut).detach();
+        }
+    }
+
     /// An extension trait used to allow ergonomic spawning of an actor onto the global runtime.
     pub trait SmolGlobalSpawnExt<A: Actor> {
         /// Spawn the actor onto the global runtime
@@ -92,20 +119,15 @@ mod tokio_impl {
     use super::*;
 
     /// The Tokio runtime.
-    #[derive(Copy, Clone, Debug)]
+    #[derive(Copy, Clone, Debug, Default)]
     pub enum Tokio<'a> {
         /// The global executor.
+        #[default]
         Global,
         /// A handle to a specific executor.
         Handle(&'a tokio::runtime::Runtime),
     }
 
-    impl<'a> Default for Tokio<'a> {
-        fn default() -> Self {
-            Tokio::Global
-        }
-    }
-
     impl<'a> Spawner for Tokio<'a> {
         fn spawn<F: Future<Output = ()> + Send + 'static>(&mut self, fut: F) {
             match self {
@@ -115,6 +137,27 @@ mod tokio_impl {
         }
     }
 
+    /// A Tokio [`LocalSet`](https://docs.rs/tokio/1/tokio/task/struct.LocalSet.html), which can
+    /// spawn futures that are not `Send`.
+    #[derive(Copy, Clone, Debug, Default)]
+    pub enum TokioLocal<'a> {
+        /// The `LocalSet` which the current task is running on. Spawning onto it will panic if
+        /// the current task is not running on a `LocalSet`.
+        #[default]
+        Current,
+        /// A specific `LocalSet`.
+        Handle(&'a tokio::task::LocalSet),
+    }
+
+    impl<'a> LocalSpawner for TokioLocal<'a> {
+        fn spawn_local<F: Future<Output = ()> + 'static>(&mut self, fut: F) {
+            match self {
+                TokioLocal::Current => tokio::task::spawn_local(fut),
+                TokioLocal::Handle(local_set) => local_set.spawn_local(fut),
+            };
+        }
+    }
+
     /// An extension trait used to allow ergonomic spawning of an actor onto the global runtime.
     pub trait TokioGlobalSpawnExt<A: Actor> {
         /// Spawn the actor onto the global runtime
@@ -142,6 +185,12 @@ mod wasm_bindgen_impl {
         }
     }
 
+    impl LocalSpawner for WasmBindgen {
+        fn spawn_local<F: Future<Output = ()> + 'static>(&mut self, fut: F) {
+            wasm_bindgen_futures::spawn_local(fut)
+        }
+    }
+
     /// An extension trait used to allow ergonomic spawning of an actor onto the global runtime.
     pub trait WasmBindgenGlobalSpawnExt<A: Actor> {
         /// Spawn the actor onto the global runtime
@@ -154,3 +203,177 @@ mod wasm_bindgen_impl {
         }
     }
 }
+
+mod thread_impl {
+    use std::thread::{self, JoinHandle};
+
+    use flume::{Receiver, Sender};
+    use futures_core::future::BoxFuture;
+    use futures_util::future::{self, Either};
+    use futures_util::stream::{FuturesUnordered, StreamExt};
+
+    use super::*;
+
+    /// Spawns every future onto its own dedicated OS thread, where it is driven by a local
+    /// `block_on`. This is useful for actors which perform blocking work (such as calling into
+    /// blocking C libraries), as they would otherwise starve the other tasks of an async executor.
+    ///
+    /// The thread exits as soon as the spawned future completes, i.e. when the actor stops. Dropping
+    /// the spawner does not stop any threads; [`ThreadSpawner::join`](struct.ThreadSpawner.html#method.join)
+    /// can be used to wait for all of them to exit.
+    #[derive(Debug, Default)]
+    pub struct ThreadSpawner {
+        name: Option<String>,
+        handles: Vec<JoinHandle<()>>,
+    }
+
+    impl ThreadSpawner {
+        /// Create a new thread spawner.
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Create a new thread spawner which names the threads it spawns with the given name.
+        pub fn with_name(name: impl Into<String>) -> Self {
+            ThreadSpawner {
+                name: Some(name.into()),
+                handles: Vec::new(),
+            }
+        }
+
+        /// Block the current thread until all threads spawned by this spawner have exited, which
+        /// happens once all of the actors spawned onto them have stopped.
+        ///
+        /// If any of the actors panicked, this will resume the panic on the current thread.
+        pub fn join(self) {
+            for handle in self.handles {
+                if let Err(panic) = handle.join() {
+                    std::panic::resume_unwind(panic);
+                }
+            }
+        }
+    }
+
+    impl Spawner for ThreadSpawner {
+        fn spawn<F: Future<Output = ()> + Send + 'static>(&mut self, fut: F) {
+            // Forget about threads which have already exited so that long-lived spawners don't grow
+            self.handles.retain(|handle| !handle.is_finished());
+
+            let mut builder = thread::Builder::new();
+            if let Some(name) = &self.name {
+                builder = builder.name(name.clone());
+            }
+
+            let handle = builder
+                .spawn(move || pollster::block_on(fut))
+                .expect("failed to spawn thread");
+            self.handles.push(handle);
+        }
+    }
+
+    /// Spawns futures onto a fixed-size pool of dedicated OS threads. Each thread drives all of the
+    /// futures assigned to it with a local `block_on`, and futures are assigned to the threads in
+    /// a round-robin fashion. Unlike with [`ThreadSpawner`](struct.ThreadSpawner.html), actors
+    /// which block therefore also hold up the other actors sharing their thread.
+    ///
+    /// The threads exit once the spawner has been dropped or joined and all of the futures spawned
+    /// onto them have completed.
+    #[derive(Debug)]
+    pub struct ThreadPoolSpawner {
+        workers: Vec<Worker>,
+        next: usize,
+    }
+
+    #[derive(Debug)]
+    struct Worker {
+        sender: Sender<BoxFuture<'static, ()>>,
+        handle: JoinHandle<()>,
+    }
+
+    impl ThreadPoolSpawner {
+        /// Create a new thread pool spawner with the given number of threads.
+        ///
+        /// # Panics
+        ///
+        /// Panics if `threads` is zero or if the threads could not be spawned.
+        pub fn new(threads: usize) -> Self {
+            assert!(threads > 0, "a thread pool needs at least one thread");
+
+            let workers = (0..threads)
+                .map(|n| {
+                    let (sender, receiver) = flume::unbounded();
+                    let handle = thread::Builder::new()
+                        .name(format!("xtra-pool-{}", n))
+                        .spawn(move || pollster::block_on(run_worker(receiver)))
+                        .expect("failed to spawn thread");
+
+                    Worker { sender, handle }
+                })
+                .collect();
+
+            ThreadPoolSpawner { workers, next: 0 }
+        }
+
+        /// Stop accepting new futures and block the current thread until all of the pool's threads
+        /// have exited, which happens once all of the actors spawned onto them have stopped.
+        ///
+        /// If any of the actors panicked, this will resume the panic on the current thread.
+        pub fn join(self) {
+            let (senders, handles): (Vec<_>, Vec<_>) = self
+                .workers
+                .into_iter()
+                .map(|worker| (worker.sender, worker.handle))
+                .unzip();
+            drop(senders);
+
+            for handle in handles {
+                if let Err(panic) = handle.join() {
+                    std::panic::resume_unwind(panic);
+                }
+            }
+        }
+    }
+
+    impl Spawner for ThreadPoolSpawner {
+        fn spawn<F: Future<Output = ()> + Send + 'static>(&mut self, fut: F) {
+            let worker = &self.workers[self.next];
+            self.next = (self.next + 1) % self.workers.len();
+
+            // The worker only stops receiving once its sender has been dropped
+            let _ = worker.sender.send(Box::pin(fut));
+        }
+    }
+
+    /// Drive all futures sent to this worker until the pool stops accepting new futures and all of
+    /// the existing ones have completed.
+    async fn run_worker(receiver: Receiver<BoxFuture<'static, ()>>) {
+        let mut tasks = FuturesUnordered::new();
+        let mut accepting = true;
+
+        while accepting || !tasks.is_empty() {
+            if !accepting {
+                tasks.next().await;
+                continue;
+            }
+
+            if tasks.is_empty() {
+                match receiver.recv_async().await {
+                    Ok(task) => tasks.push(task),
+                    Err(_) => accepting = false,
+                }
+                continue;
+            }
+
+            let received = match future::select(receiver.recv_async(), tasks.next()).await {
+                Either::Left((res, _)) => Some(res),
+                Either::Right(_) => None,
+            };
+
+            match received {
+                Some(Ok(task)) => tasks.push(task),
+                Some(Err(_)) => accepting = false,
+                None => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,210 @@
+//! Streaming handlers respond to a message with a stream of items rather than a single result.
+//! The actor emits items incrementally through an [`Emitter`](struct.Emitter.html), and the sender
+//! receives them through the [`ResponseStream`](struct.ResponseStream.html) returned by
+//! [`Address::send_streaming`](../address/struct.Address.html#method.send_streaming).
+
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context as TaskContext, Poll};
+
+use flume::r#async::RecvStream;
+use flume::Sender;
+use futures_core::Stream;
+use futures_util::{FutureExt, StreamExt};
+
+use crate::address::{Disconnected, DoSendFuture};
+use crate::{Actor, Context};
+
+/// A message which is responded to with a stream of items. Only actors implementing the
+/// corresponding [`StreamingHandler<M>`](trait.StreamingHandler.html) trait can be sent a given
+/// streaming message.
+///
+/// # Example
+///
+/// ```no_run
+/// # use xtra::streaming::StreamingMessage;
+/// struct Countdown(u32);
+///
+/// impl StreamingMessage for Countdown {
+///     type Item = u32;
+/// }
+/// ```
+pub trait StreamingMessage: Send + 'static {
+    /// The type of the items emitted by the actor in response to this message.
+    type Item: Send + 'static;
+
+    /// How many items the actor may emit ahead of the receiver before
+    /// [`Emitter::emit`](struct.Emitter.html#method.emit) waits for the receiver to catch up.
+    const BUFFER: usize = 1;
+}
+
+/// A trait indicating that an [`Actor`](../trait.Actor.html) can handle a given
+/// [`StreamingMessage`](trait.StreamingMessage.html) by emitting a stream of items.
+///
+/// The response stream ends once the [`Emitter`](struct.Emitter.html) is dropped. As the emitter
+/// is passed by value, it may be moved into a spawned task in order to continue emitting after the
+/// handler has returned, thereby not blocking the actor for the lifetime of the stream.
+///
+/// This is an [`async_trait`](https://docs.rs/async-trait), so implementations should
+/// be annotated `#[async_trait]`.
+///
+/// # Example
+///
+/// ```
+/// # use xtra::prelude::*;
+/// # use xtra::spawn::Smol;
+/// # use xtra::streaming::{Emitter, StreamingHandler, StreamingMessage};
+/// # use futures_util::TryStreamExt;
+/// # struct MyActor;
+/// # #[async_trait::async_trait] impl Actor for MyActor {type Stop = (); async fn stopped(self) -> Self::Stop {} }
+/// struct Countdown(u32);
+///
+/// impl StreamingMessage for Countdown {
+///     type Item = u32;
+/// }
+///
+/// #[async_trait::async_trait]
+/// impl StreamingHandler<Countdown> for MyActor {
+///     async fn handle(&mut self, msg: Countdown, mut emitter: Emitter<u32>, _ctx: &mut Context<Self>) {
+///         for n in (0..msg.0).rev() {
+///             if emitter.emit(n).await.is_err() {
+///                 break; // The receiver is no longer interested
+///             }
+///         }
+///     }
+/// }
+///
+/// smol::block_on(async {
+///     let addr = MyActor.create(None).spawn(&mut Smol::Global);
+///     let items: Vec<u32> = addr.send_streaming(Countdown(3)).try_collect().await.unwrap();
+///     assert_eq!(items, vec![2, 1, 0]);
+/// })
+/// ```
+#[async_trait::async_trait]
+pub trait StreamingHandler<M: StreamingMessage>: Actor {
+    /// Handle a given message, emitting its response items through the given emitter.
+    ///
+    /// This is an [`async_trait`](https://docs.rs/async-trait).
+    /// See the trait documentation to see an example of how this method can be declared.
+    async fn handle(&mut self, message: M, emitter: Emitter<M::Item>, ctx: &mut Context<Self>);
+}
+
+/// The sending half of a response stream, handed to a
+/// [`StreamingHandler`](trait.StreamingHandler.html). The response stream ends once this is dropped.
+pub struct Emitter<T> {
+    sender: Sender<T>,
+    /// Set once the emitter is dropped after having been handed to the handler, telling the
+    /// response stream that it ended normally.
+    finished: Arc<AtomicBool>,
+    handed_over: bool,
+}
+
+impl<T: Send + 'static> Emitter<T> {
+    pub(crate) fn new(sender: Sender<T>, finished: Arc<AtomicBool>) -> Self {
+        Emitter {
+            sender,
+            finished,
+            handed_over: false,
+        }
+    }
+
+    /// Marks the emitter as handed to the handler, so that dropping it finishes the stream.
+    pub(crate) fn hand_over(mut self) -> Self {
+        self.handed_over = true;
+        self
+    }
+
+    /// Emit an item to the receiver of the response stream. If the receiver has not yet consumed
+    /// the buffered items, this will asynchronously wait until it does. If this returns
+    /// `Err(Disconnected)`, the response stream has been dropped and no more items should be emitted.
+    pub async fn emit(&mut self, item: T) -> Result<(), Disconnected> {
+        self.sender.send_async(item).await.map_err(|_| Disconnected)
+    }
+
+    /// Returns whether the receiver of the response stream is still listening.
+    pub fn is_connected(&self) -> bool {
+        !self.sender.is_disconnected()
+    }
+}
+
+impl<T> Drop for Emitter<T> {
+    fn drop(&mut self) {
+        // An emitter dropped with an unhandled message, or by a panicking handler, did not finish
+        if self.handed_over && !std::thread::panicking() {
+            self.finished.store(true, Ordering::Release);
+        }
+    }
+}
+
+/// The stream returned by [`Address::send_streaming`](../address/struct.Address.html#method.send_streaming).
+/// It yields the items emitted by the actor in order, and ends once the actor drops the
+/// [`Emitter`](struct.Emitter.html). If the actor is disconnected before the message is handled,
+/// or the handler panics, the stream yields `Err(Disconnected)` as its last item, so that this can
+/// be told apart from an empty response.
+#[must_use = "streams do nothing unless polled"]
+pub struct ResponseStream<A: Actor, M: StreamingMessage>(ResponseStreamInner<A, M>);
+
+pub(crate) enum ResponseStreamInner<A: Actor, M: StreamingMessage> {
+    Disconnected,
+    Sending(DoSendFuture<A>, Receiving<M::Item>),
+    Receiving(Receiving<M::Item>),
+    Done,
+}
+
+/// The receiving half of a response stream, and whether its emitter finished.
+pub(crate) struct Receiving<T: 'static> {
+    pub(crate) items: RecvStream<'static, T>,
+    pub(crate) finished: Arc<AtomicBool>,
+}
+
+impl<A: Actor, M: StreamingMessage> ResponseStream<A, M> {
+    pub(crate) fn new(inner: ResponseStreamInner<A, M>) -> Self {
+        ResponseStream(inner)
+    }
+}
+
+impl<A: Actor, M: StreamingMessage> Stream for ResponseStream<A, M> {
+    type Item = Result<M::Item, Disconnected>;
+
+    fn poll_next(self: Pin<&mut Self>, ctx: &mut TaskContext) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        if let ResponseStreamInner::Sending(tx, _) = &mut this.0 {
+            match tx.poll_unpin(ctx) {
+                Poll::Ready(Ok(())) => {}
+                Poll::Ready(Err(_)) => {
+                    this.0 = ResponseStreamInner::Done;
+                    return Poll::Ready(Some(Err(Disconnected)));
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+
+            this.0 = match std::mem::replace(&mut this.0, ResponseStreamInner::Disconnected) {
+                ResponseStreamInner::Sending(_, rx) => ResponseStreamInner::Receiving(rx),
+                other => other,
+            };
+        }
+
+        match &mut this.0 {
+            ResponseStreamInner::Disconnected => {
+                this.0 = ResponseStreamInner::Done;
+                Poll::Ready(Some(Err(Disconnected)))
+            }
+            ResponseStreamInner::Receiving(rx) => match rx.items.poll_next_unpin(ctx) {
+                Poll::Ready(Some(item)) => Poll::Ready(Some(Ok(item))),
+                Poll::Ready(None) => {
+                    let finished = rx.finished.load(Ordering::Acquire);
+                    this.0 = ResponseStreamInner::Done;
+                    match finished {
+                        true => Poll::Ready(None),
+                        false => Poll::Ready(Some(Err(Disconnected))),
+                    }
+                }
+                Poll::Pending => Poll::Pending,
+            },
+            ResponseStreamInner::Done => Poll::Ready(None),
+            ResponseStreamInner::Sending(..) => unreachable!("sending state was resolved above"),
+        }
+    }
+}
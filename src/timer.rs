@@ -0,0 +1,346 @@
+//! Timers which notify an actor after a delay or at an interval. They are created by
+//! [`Context::notify_after`](../struct.Context.html#method.notify_after) and
+//! [`Context::notify_interval`](../struct.Context.html#method.notify_interval), and can be
+//! cancelled or reset through a [`TimerHandle`](struct.TimerHandle.html). How an interval timer
+//! spaces its ticks is configured with an [`Interval`](struct.Interval.html), passed to
+//! [`Context::notify_interval_with`](../struct.Context.html#method.notify_interval_with).
+
+use std::convert::TryFrom;
+use std::fmt::{self, Debug, Formatter};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context as TaskContext, Poll};
+use std::time::{Duration, Instant};
+
+use event_listener::{Event, EventListener};
+use futures_core::future::BoxFuture;
+use futures_timer::Delay;
+use futures_util::future::{self, Either};
+use futures_util::FutureExt;
+
+use crate::drop_notice::DropNotice;
+use crate::spawn::Spawner;
+
+/// A timer which notifies an actor once or repeatedly. Like most futures, it does nothing unless
+/// it is polled, so it must be spawned onto an executor - either directly, or through
+/// [`Timer::spawn`](struct.Timer.html#method.spawn). It stops once it has fired for the last time,
+/// once it is cancelled, or once the actor which created it stops.
+#[must_use = "timers do nothing unless polled"]
+pub struct Timer {
+    fut: BoxFuture<'static, ()>,
+    shared: Arc<TimerShared>,
+}
+
+impl Timer {
+    pub(crate) fn new<F>(make_fut: F) -> Self
+    where
+        F: FnOnce(TimerCommands) -> BoxFuture<'static, ()>,
+    {
+        let shared = Arc::new(TimerShared::default());
+        Timer {
+            fut: make_fut(TimerCommands(shared.clone())),
+            shared,
+        }
+    }
+
+    /// Returns a handle through which this timer can be cancelled or reset.
+    pub fn handle(&self) -> TimerHandle {
+        TimerHandle(self.shared.clone())
+    }
+
+    /// Spawns this timer on the given spawner, returning a handle through which it can be
+    /// cancelled or reset.
+    pub fn spawn<S: Spawner>(self, spawner: &mut S) -> TimerHandle {
+        let handle = self.handle();
+        spawner.spawn(self);
+        handle
+    }
+}
+
+impl Future for Timer {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut TaskContext) -> Poll<()> {
+        self.fut.poll_unpin(cx)
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        self.shared.finished.store(true, Ordering::Release);
+    }
+}
+
+impl Debug for Timer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Timer").finish_non_exhaustive()
+    }
+}
+
+/// How an interval timer ticks. See
+/// [`Context::notify_interval_with`](../struct.Context.html#method.notify_interval_with).
+///
+/// # Example
+///
+/// ```
+/// use std::time::Duration;
+/// use xtra::timer::{Interval, QueuedTickPolicy};
+///
+/// // Tick on every second, but never have more than one tick waiting in the mailbox
+/// let interval = Interval::fixed_rate(Duration::from_secs(1)).queued_ticks(QueuedTickPolicy::Skip);
+/// ```
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Interval {
+    pub(crate) period: Duration,
+    pub(crate) mode: IntervalMode,
+    pub(crate) queued_ticks: QueuedTickPolicy,
+}
+
+impl Interval {
+    /// Ticks every `period` since the timer started, however long each tick takes to be delivered.
+    pub fn fixed_rate(period: Duration) -> Self {
+        Interval {
+            period,
+            mode: IntervalMode::FixedRate,
+            queued_ticks: QueuedTickPolicy::Queue,
+        }
+    }
+
+    /// Ticks `period` after the previous tick was delivered.
+    pub fn fixed_delay(period: Duration) -> Self {
+        Interval {
+            period,
+            mode: IntervalMode::FixedDelay,
+            queued_ticks: QueuedTickPolicy::Queue,
+        }
+    }
+
+    /// Sets what to do when a tick is due while the previous one is still waiting in the actor's
+    /// mailbox. By default, the new tick is queued behind it.
+    pub fn queued_ticks(mut self, policy: QueuedTickPolicy) -> Self {
+        self.queued_ticks = policy;
+        self
+    }
+}
+
+/// How an interval timer spaces its ticks.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum IntervalMode {
+    /// Ticks are due at whole multiples of the period after the timer started, so they do not
+    /// drift. If delivering a tick takes longer than a period, for instance because the actor's
+    /// mailbox is full, the ticks which were missed in the meantime are skipped.
+    FixedRate,
+    /// Each tick is due one period after the previous tick was delivered, so the ticks drift by
+    /// however long it takes to deliver them.
+    FixedDelay,
+}
+
+/// What an interval timer does when a tick is due while the previous tick is still waiting in the
+/// actor's mailbox.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum QueuedTickPolicy {
+    /// Send the new tick anyway, queueing it behind the previous one.
+    Queue,
+    /// Drop the new tick, and keep the message of the previous one.
+    Skip,
+    /// Drop the new tick, but replace the message of the previous one with the new message, so
+    /// that the actor handles the most recent one.
+    Coalesce,
+}
+
+/// A handle to a [`Timer`](struct.Timer.html), through which it can be cancelled or reset.
+/// Dropping the handle does not cancel the timer.
+#[derive(Clone)]
+pub struct TimerHandle(Arc<TimerShared>);
+
+impl TimerHandle {
+    /// Cancels the timer. It will not notify the actor again, even if its delay has already
+    /// elapsed but the notification has not been sent yet.
+    pub fn cancel(&self) {
+        self.0.command(Command::Cancel);
+    }
+
+    /// Restarts the timer's delay with the given duration. For an interval timer, the duration
+    /// also becomes the new interval. For a scheduled timer, the next notification is moved to
+    /// the duration from now, after which the schedule carries on as before.
+    pub fn reset(&self, duration: Duration) {
+        self.0.command(Command::Reset(duration));
+    }
+
+    /// Returns whether the timer has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        matches!(*self.0.command.lock().unwrap(), Some(Command::Cancel))
+    }
+
+    /// Returns whether the timer has been dropped, which it is once it has ended - because it has
+    /// fired for the last time, it was cancelled, or the actor stopped - or if it was never spawned.
+    pub fn is_finished(&self) -> bool {
+        self.0.finished.load(Ordering::Acquire)
+    }
+}
+
+impl Debug for TimerHandle {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TimerHandle")
+            .field("cancelled", &self.is_cancelled())
+            .field("finished", &self.is_finished())
+            .finish()
+    }
+}
+
+#[derive(Copy, Clone)]
+pub(crate) enum Command {
+    Cancel,
+    Reset(Duration),
+}
+
+#[derive(Default)]
+struct TimerShared {
+    /// The latest command which the timer has not yet acted on. A cancellation is never taken, so
+    /// that it sticks.
+    command: Mutex<Option<Command>>,
+    changed: Event,
+    /// Set once the timer has been dropped.
+    finished: AtomicBool,
+}
+
+impl TimerShared {
+    fn command(&self, command: Command) {
+        let mut current = self.command.lock().unwrap();
+        if !matches!(*current, Some(Command::Cancel)) {
+            *current = Some(command);
+            self.changed.notify(usize::MAX);
+        }
+    }
+}
+
+/// The timer's side of the commands sent through its handles.
+pub(crate) struct TimerCommands(Arc<TimerShared>);
+
+impl TimerCommands {
+    /// Returns a listener which resolves once a new command has been given. It must be created
+    /// before calling `take` in order not to miss any commands.
+    pub(crate) fn listen(&self) -> EventListener {
+        self.0.changed.listen()
+    }
+
+    /// Returns whether the timer has been cancelled.
+    pub(crate) fn is_cancelled(&self) -> bool {
+        matches!(*self.0.command.lock().unwrap(), Some(Command::Cancel))
+    }
+
+    /// Takes the latest command, if any.
+    pub(crate) fn take(&self) -> Option<Command> {
+        let mut command = self.0.command.lock().unwrap();
+        match *command {
+            Some(Command::Cancel) => Some(Command::Cancel),
+            _ => command.take(),
+        }
+    }
+}
+
+/// Runs a timer, calling `fire` once `duration` has elapsed and then, if `interval` is given,
+/// every `duration` after that. `fire` returns a future which delivers the tick and resolves to
+/// whether the timer should carry on. The timer ends once it is cancelled, a tick was not delivered
+/// or the context which created it is dropped.
+pub(crate) async fn run<F, Fut>(
+    commands: TimerCommands,
+    mut duration: Duration,
+    mut stopped: DropNotice,
+    interval: Option<IntervalMode>,
+    mut fire: F,
+) where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = bool>,
+{
+    let mut due = Instant::now() + duration;
+    let mut delay = Delay::new(duration);
+
+    loop {
+        let listener = commands.listen();
+        match commands.take() {
+            Some(Command::Cancel) => return,
+            Some(Command::Reset(new)) => {
+                duration = new;
+                due = Instant::now() + duration;
+                delay.reset(duration);
+            }
+            None => {}
+        }
+
+        match future::select(&mut delay, future::select(listener, &mut stopped)).await {
+            Either::Left(_) => {
+                // A command may have been given just as the delay elapsed
+                match commands.take() {
+                    Some(Command::Cancel) => return,
+                    Some(Command::Reset(new)) => {
+                        duration = new;
+                        due = Instant::now() + duration;
+                        delay.reset(duration);
+                        continue;
+                    }
+                    None => {}
+                }
+
+                if !deliver(&commands, &mut stopped, fire()).await {
+                    return;
+                }
+
+                let now = Instant::now();
+                due = match interval {
+                    Some(IntervalMode::FixedRate) => next_tick(due, duration, now),
+                    Some(IntervalMode::FixedDelay) => now + duration,
+                    None => return,
+                };
+                delay.reset(due.saturating_duration_since(now));
+            }
+            Either::Right((Either::Left(_), _)) => {} // A command was given
+            Either::Right((Either::Right(_), _)) => return, // The context stopped
+        }
+    }
+}
+
+/// Waits for a tick to be delivered, returning whether it was. This gives up if the timer is
+/// cancelled or the context stops in the meantime, so that a full mailbox cannot hold up a timer
+/// which should have ended.
+pub(crate) async fn deliver<F>(commands: &TimerCommands, stopped: &mut DropNotice, tick: F) -> bool
+where
+    F: Future<Output = bool>,
+{
+    futures_util::pin_mut!(tick);
+
+    loop {
+        let listener = commands.listen();
+        if commands.is_cancelled() {
+            return false;
+        }
+
+        match future::select(&mut tick, future::select(listener, &mut *stopped)).await {
+            Either::Left((delivered, _)) => return delivered,
+            Either::Right((Either::Left(_), _)) => {} // Other commands are acted on afterwards
+            Either::Right((Either::Right(_), _)) => return false,
+        }
+    }
+}
+
+/// Returns the first tick after `now` which is a whole number of periods after `due`, skipping
+/// any ticks which were missed without shifting the phase.
+fn next_tick(due: Instant, period: Duration, now: Instant) -> Instant {
+    let next = due + period;
+    if next > now {
+        return next;
+    }
+
+    if period == Duration::from_secs(0) {
+        return now;
+    }
+
+    // Computed in nanoseconds, as a period multiplied by a large number of missed ticks can
+    // overflow `u32`
+    let missed = (now - next).as_nanos() / period.as_nanos() + 1;
+    let skipped = missed.saturating_mul(period.as_nanos());
+    let skipped = Duration::from_nanos(u64::try_from(skipped).unwrap_or(u64::MAX));
+    next.checked_add(skipped).unwrap_or(now)
+}
@@ -0,0 +1,268 @@
+//! An [`AddressService`](struct.AddressService.html) wraps an address as a
+//! [`Service`](https://docs.rs/tower-service/0.3/tower_service/trait.Service.html), so that tower's
+//! layers, such as timeouts, load shedding and concurrency limits, can be put in front of an
+//! actor. Conversely, a [`ServiceActor`](struct.ServiceActor.html) runs a service as an actor.
+
+use std::marker::PhantomData;
+use std::task::{Context, Poll};
+
+use futures_core::future::BoxFuture;
+use futures_core::ready;
+use futures_util::{future, FutureExt};
+use tower_service::Service;
+
+use crate::address::{Address, Disconnected, SendFuture};
+use crate::limit::Admission;
+use crate::refcount::{RefCounter, Strong};
+use crate::{Actor, Handler, Message, NativeHandler};
+
+/// An address used as a [`Service`](https://docs.rs/tower-service/0.3/tower_service/trait.Service.html).
+/// Calling it sends the request to the actor like
+/// [`Address::send`](../address/struct.Address.html#method.send), resolving to the actor's response.
+///
+/// The service is ready once the message would be admitted by the actor's
+/// [limits](../limit/index.html) and there is room in its mailbox. While it is not, `poll_ready`
+/// returns `Poll::Pending` and wakes the task once a permit is released or the actor takes a
+/// message from its mailbox. The admission is held for the next call, so that another sender
+/// cannot take it in between. Clones of the service each wait for their own admission.
+///
+/// # Example
+///
+/// ```
+/// # use xtra::prelude::*;
+/// # use xtra::spawn::Smol;
+/// use tower_service::Service;
+/// use xtra::tower::AddressService;
+///
+/// struct Echo;
+/// # #[async_trait::async_trait] impl Actor for Echo {type Stop = (); async fn stopped(self) -> Self::Stop {} }
+///
+/// struct Request(u32);
+///
+/// impl Message for Request {
+///     type Result = u32;
+/// }
+///
+/// #[async_trait::async_trait]
+/// impl Handler<Request> for Echo {
+///     async fn handle(&mut self, request: Request, _ctx: &mut Context<Self>) -> u32 {
+///         request.0
+///     }
+/// }
+///
+/// smol::block_on(async {
+///     let addr = Echo.create(Some(16)).spawn(&mut Smol::Global);
+///     let mut service = AddressService::new(addr);
+///     futures_util::future::poll_fn(|cx| Service::<Request>::poll_ready(&mut service, cx))
+///         .await
+///         .unwrap();
+///     assert_eq!(service.call(Request(7)).await, Ok(7));
+/// })
+/// ```
+pub struct AddressService<A, Rc: RefCounter = Strong> {
+    address: Address<A, Rc>,
+    /// The admission which `poll_ready` reserved for the next call
+    admission: Admission,
+    /// Waits until the actor takes a message from its full mailbox, or stops
+    room: Option<BoxFuture<'static, ()>>,
+}
+
+impl<A, Rc: RefCounter> AddressService<A, Rc> {
+    /// Wraps the address as a service.
+    pub fn new(address: Address<A, Rc>) -> Self {
+        AddressService {
+            address,
+            admission: Admission::default(),
+            room: None,
+        }
+    }
+
+    /// Returns the address which this service sends to.
+    pub fn address(&self) -> &Address<A, Rc> {
+        &self.address
+    }
+
+    /// Takes the address out of the service.
+    pub fn into_inner(self) -> Address<A, Rc> {
+        self.address
+    }
+}
+
+impl<A, Rc: RefCounter> Clone for AddressService<A, Rc> {
+    fn clone(&self) -> Self {
+        AddressService::new(self.address.clone())
+    }
+}
+
+impl<A, M, Rc> Service<M> for AddressService<A, Rc>
+where
+    A: NativeHandler<M>,
+    M: Message,
+    Rc: RefCounter,
+{
+    type Response = M::Result;
+    type Error = Disconnected;
+    type Future = SendFuture<A, M>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Disconnected>> {
+        if !self.address.is_connected() {
+            return Poll::Ready(Err(Disconnected));
+        }
+
+        ready!(self.admission.poll_admit(&self.address.limiter, cx));
+
+        loop {
+            if let Some(room) = &mut self.room {
+                ready!(room.poll_unpin(cx));
+                self.room = None;
+            }
+
+            if !self.address.is_connected() {
+                return Poll::Ready(Err(Disconnected));
+            }
+
+            // Listen before checking, so that a message taken in between is not missed
+            let received = self.address.limiter.listen_received();
+            match self.address.capacity() {
+                Some(capacity) if self.address.len() >= capacity => {
+                    let stopped = self.address.join();
+                    self.room = Some(Box::pin(future::select(received, stopped).map(|_| ())));
+                }
+                _ => return Poll::Ready(Ok(())),
+            }
+        }
+    }
+
+    fn call(&mut self, message: M) -> Self::Future {
+        let message = match self.address.interceptors.intercept(message) {
+            Ok(message) => message,
+            Err(_) => return SendFuture::disconnected(),
+        };
+        match self.admission.take() {
+            Some(permit) => self.address.send_admitted(message, permit),
+            // Without a reserved admission, the call waits for one like any other send
+            None => self.address.send(message),
+        }
+    }
+}
+
+/// An actor which handles requests by calling a service. A request is sent to it as a
+/// [`Call`](struct.Call.html), usually with
+/// [`Address::call`](../address/struct.Address.html#method.call), and is handled by waiting for
+/// the service to be ready and calling it. Requests are handled one at a time, as with any actor.
+///
+/// # Example
+///
+/// ```
+/// # use std::task::{Context, Poll};
+/// # use futures_util::future::{self, Ready};
+/// # use xtra::prelude::*;
+/// # use xtra::spawn::Smol;
+/// use tower_service::Service;
+/// use xtra::tower::ServiceActor;
+///
+/// struct Doubler;
+///
+/// impl Service<u32> for Doubler {
+///     type Response = u32;
+///     type Error = ();
+///     type Future = Ready<Result<u32, ()>>;
+///
+///     fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), ()>> {
+///         Poll::Ready(Ok(()))
+///     }
+///
+///     fn call(&mut self, request: u32) -> Self::Future {
+///         future::ready(Ok(request * 2))
+///     }
+/// }
+///
+/// smol::block_on(async {
+///     let addr = ServiceActor::new(Doubler).create(None).spawn(&mut Smol::Global);
+///     assert_eq!(addr.call(21).await, Ok(Ok(42)));
+/// })
+/// ```
+pub struct ServiceActor<S> {
+    service: S,
+}
+
+impl<S> ServiceActor<S> {
+    /// Creates an actor which handles requests with the given service.
+    pub fn new(service: S) -> Self {
+        ServiceActor { service }
+    }
+
+    /// Takes the service out of the actor.
+    pub fn into_inner(self) -> S {
+        self.service
+    }
+}
+
+#[async_trait::async_trait]
+impl<S: Send + 'static> Actor for ServiceActor<S> {
+    type Stop = ();
+
+    async fn stopped(self) -> Self::Stop {}
+}
+
+/// The message which carries a request to a [`ServiceActor`](struct.ServiceActor.html). It
+/// resolves to the service's response.
+pub struct Call<S, R> {
+    request: R,
+    phantom: PhantomData<fn() -> S>,
+}
+
+impl<S, R> Call<S, R> {
+    /// Creates a message carrying the request.
+    pub fn new(request: R) -> Self {
+        Call {
+            request,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<S, R> Message for Call<S, R>
+where
+    S: Service<R> + 'static,
+    S::Response: Send,
+    S::Error: Send,
+    R: Send + 'static,
+{
+    type Result = Result<S::Response, S::Error>;
+}
+
+#[async_trait::async_trait]
+impl<S, R> Handler<Call<S, R>> for ServiceActor<S>
+where
+    S: Service<R> + Send + 'static,
+    S::Response: Send,
+    S::Error: Send,
+    S::Future: Send,
+    R: Send + 'static,
+{
+    async fn handle(
+        &mut self,
+        call: Call<S, R>,
+        _ctx: &mut crate::Context<Self>,
+    ) -> Result<S::Response, S::Error> {
+        future::poll_fn(|cx| self.service.poll_ready(cx)).await?;
+        self.service.call(call.request).await
+    }
+}
+
+impl<S, Rc> Address<ServiceActor<S>, Rc>
+where
+    S: Send + 'static,
+    Rc: RefCounter,
+{
+    /// Sends a request to the [`ServiceActor`](../tower/struct.ServiceActor.html), resolving to
+    /// the service's response.
+    pub fn call<R>(&self, request: R) -> SendFuture<ServiceActor<S>, Call<S, R>>
+    where
+        ServiceActor<S>: NativeHandler<Call<S, R>>,
+        Call<S, R>: Message,
+    {
+        self.send(Call::new(request))
+    }
+}
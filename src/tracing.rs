@@ -1,4 +1,4 @@
-use crate::{Handler, Message};
+use crate::{Handler, Message, NativeHandler};
 use tracing::{Instrument, Span};
 
 /// Instrument a message with `tracing`. This will attach the message handler span to the given
@@ -40,20 +40,24 @@ impl<M: Message> InstrumentedExt for M {
 }
 
 #[async_trait::async_trait]
-impl<A: Handler<M>, M: Message, const IS_CHILD: bool> Handler<Instrumented<M, IS_CHILD>> for A {
+impl<A: NativeHandler<M>, M: Message, const IS_CHILD: bool> Handler<Instrumented<M, IS_CHILD>>
+    for A
+{
     async fn handle(
         &mut self,
         message: Instrumented<M, IS_CHILD>,
         ctx: &mut crate::Context<Self>,
     ) -> M::Result {
         if IS_CHILD {
-            self.handle(message.msg, ctx)
+            NativeHandler::handle(self, message.msg, ctx)
                 .instrument(message.parent)
                 .await
         } else {
             let span = Span::current();
             span.follows_from(message.parent);
-            self.handle(message.msg, ctx).instrument(span).await
+            NativeHandler::handle(self, message.msg, ctx)
+                .instrument(span)
+                .await
         }
     }
 }
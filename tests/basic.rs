@@ -1,13 +1,36 @@
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use async_trait::async_trait;
+use futures_util::{FutureExt, SinkExt, StreamExt, TryStreamExt};
 use smol_timeout::TimeoutExt;
 
+use xtra::address::{SendError, SendTimeoutError};
+use xtra::batch::BatchHandler;
+use xtra::behaviour::{Behaviour, Rejected, Unaccepted};
+use xtra::coalesce::Coalesce;
+use xtra::durable::MailboxError;
+use xtra::fsm::{FsmActor, Transition};
+use xtra::intercept::Intercepted;
+use xtra::limit::Limits;
+use xtra::local::{LocalActor, LocalContext, LocalHandler};
+use xtra::middleware::{Invocation, Middleware, Next, Outcome};
+use xtra::persistence::{
+    Codec, DecodeError, FileJournal, Journal, JournalError, MemoryJournal, PersistentActor,
+};
 use xtra::prelude::*;
-use xtra::spawn::Smol;
-use xtra::KeepRunning;
+use xtra::schedule::CronSchedule;
+use xtra::snapshot::{
+    FileSnapshotStore, MemorySnapshotStore, Snapshot, SnapshotActor, SnapshotStore,
+};
+use xtra::spawn::{Smol, SmolLocal, ThreadPoolSpawner, ThreadSpawner};
+use xtra::streaming::{Emitter, StreamingHandler, StreamingMessage};
+use xtra::timer::{Interval, QueuedTickPolicy, TimerHandle};
+use xtra::{Disconnected, KeepRunning, NativeHandler};
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 struct Accumulator(usize);
@@ -53,6 +76,29 @@ async fn accumulate_to_ten() {
     assert_eq!(addr.send(Report).await.unwrap().0, 10);
 }
 
+#[test]
+fn test_stop_when_last_addresses_dropped_concurrently() {
+    for _ in 0..500 {
+        let (addr, fut) = Accumulator(0).create(None).run();
+        let stopped = smol::spawn(fut);
+        let barrier = Arc::new(std::sync::Barrier::new(2));
+        let dropper = {
+            let addr = addr.clone();
+            let barrier = barrier.clone();
+            std::thread::spawn(move || {
+                barrier.wait();
+                drop(addr);
+            })
+        };
+        barrier.wait();
+        drop(addr);
+        dropper.join().unwrap();
+
+        // Whichever address was dropped last, the actor must notice and stop
+        assert!(smol::block_on(stopped.timeout(Duration::from_secs(5))).is_some());
+    }
+}
+
 struct DropTester(Arc<AtomicUsize>);
 
 impl Drop for DropTester {
@@ -160,3 +206,2309 @@ async fn test_stream_cancel_join() {
     // Join should also return right away
     assert!(jh.timeout(Duration::from_secs(2)).await.is_some());
 }
+
+struct Counter;
+
+#[async_trait]
+impl Actor for Counter {
+    type Stop = ();
+
+    async fn stopped(self) -> Self::Stop {}
+}
+
+struct CountTo(usize);
+
+impl StreamingMessage for CountTo {
+    type Item = usize;
+}
+
+#[async_trait]
+impl StreamingHandler<CountTo> for Counter {
+    async fn handle(&mut self, msg: CountTo, mut emitter: Emitter<usize>, _: &mut Context<Self>) {
+        for n in 0..msg.0 {
+            if emitter.emit(n).await.is_err() {
+                break;
+            }
+        }
+    }
+}
+
+#[smol_potat::test]
+async fn test_send_streaming() {
+    let addr = Counter.create(None).spawn(&mut Smol::Global);
+
+    let items: Vec<usize> = addr.send_streaming(CountTo(5)).try_collect().await.unwrap();
+    assert_eq!(items, vec![0, 1, 2, 3, 4]);
+
+    // Dropping the stream early must not wedge the actor
+    let mut stream = addr.send_streaming(CountTo(usize::MAX));
+    assert_eq!(stream.next().await, Some(Ok(0)));
+    drop(stream);
+
+    let items: Vec<usize> = addr.send_streaming(CountTo(2)).try_collect().await.unwrap();
+    assert_eq!(items, vec![0, 1]);
+
+    // An empty response is not mistaken for a disconnected actor
+    let items: Vec<_> = addr.send_streaming(CountTo(0)).collect().await;
+    assert_eq!(items, vec![]);
+
+    let (addr, ctx) = Context::<Counter>::new(None);
+    let stream = addr.send_streaming(CountTo(2));
+    drop(ctx);
+    assert_eq!(stream.collect::<Vec<_>>().await, vec![Err(Disconnected)]);
+}
+
+#[derive(Default)]
+struct Recorder(Vec<usize>);
+
+#[async_trait]
+impl Actor for Recorder {
+    type Stop = ();
+
+    async fn stopped(self) -> Self::Stop {}
+}
+
+struct Record(usize);
+
+impl Message for Record {
+    type Result = KeepRunning;
+}
+
+struct RecordQuietly(usize);
+
+impl Message for RecordQuietly {
+    type Result = ();
+}
+
+struct GetRecords;
+
+impl Message for GetRecords {
+    type Result = Vec<usize>;
+}
+
+#[async_trait]
+impl Handler<Record> for Recorder {
+    async fn handle(&mut self, msg: Record, _: &mut Context<Self>) -> KeepRunning {
+        self.0.push(msg.0);
+        (msg.0 != 5).into()
+    }
+}
+
+#[async_trait]
+impl Handler<RecordQuietly> for Recorder {
+    async fn handle(&mut self, msg: RecordQuietly, _: &mut Context<Self>) {
+        self.0.push(msg.0);
+    }
+}
+
+#[async_trait]
+impl Handler<GetRecords> for Recorder {
+    async fn handle(&mut self, _: GetRecords, _: &mut Context<Self>) -> Vec<usize> {
+        std::mem::take(&mut self.0)
+    }
+}
+
+#[smol_potat::test]
+async fn test_attach_stream_buffered() {
+    let addr = Recorder::default().create(None).spawn(&mut Smol::Global);
+
+    let stream = futures_util::stream::iter((0..10).map(Record));
+    let forwarding = addr.clone().attach_stream_buffered(stream, 3);
+    assert!(forwarding.timeout(Duration::from_secs(2)).await.is_some());
+
+    // Messages already in flight when the stop result came back are still handled, in order
+    let records = addr.send(GetRecords).await.unwrap();
+    assert!(records.len() >= 6 && records.len() <= 8);
+    assert_eq!(records, (0..records.len()).collect::<Vec<_>>());
+
+    let stream = futures_util::stream::iter((0..10).map(RecordQuietly));
+    addr.clone().attach_stream_do_send(stream).await;
+    assert_eq!(
+        addr.send(GetRecords).await.unwrap(),
+        (0..10).collect::<Vec<_>>()
+    );
+
+    let stream = futures_util::stream::iter((0..10).map(RecordQuietly)).boxed();
+    let channel = Intercepted::new(Box::new(addr.clone()), |msg: RecordQuietly| {
+        Ok(RecordQuietly(msg.0 * 2))
+    });
+    MessageChannel::attach_stream_do_send(channel, stream).await;
+    assert_eq!(
+        addr.send(GetRecords).await.unwrap(),
+        (0..10).map(|n| n * 2).collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn test_thread_spawner() {
+    let mut spawner = ThreadSpawner::with_name("accumulator");
+    let addr = Accumulator(0).create(None).spawn(&mut spawner);
+
+    for _ in 0..10 {
+        addr.do_send(Inc).unwrap();
+    }
+    assert_eq!(smol::block_on(addr.send(Report)).unwrap().0, 10);
+
+    drop(addr);
+    spawner.join(); // The thread exits once the actor stops
+}
+
+#[test]
+fn test_thread_pool_spawner() {
+    let mut spawner = ThreadPoolSpawner::new(2);
+    let addrs: Vec<_> = (0..5)
+        .map(|_| Accumulator(0).create(None).spawn(&mut spawner))
+        .collect();
+
+    for (n, addr) in addrs.iter().enumerate() {
+        for _ in 0..n {
+            addr.do_send(Inc).unwrap();
+        }
+    }
+
+    for (n, addr) in addrs.iter().enumerate() {
+        assert_eq!(smol::block_on(addr.send(Report)).unwrap().0, n);
+    }
+
+    drop(addrs);
+    spawner.join();
+}
+
+struct LocalAccumulator(Rc<RefCell<usize>>);
+
+#[async_trait(?Send)]
+impl LocalActor for LocalAccumulator {
+    type Stop = ();
+
+    async fn stopped(self) -> Self::Stop {}
+}
+
+#[async_trait(?Send)]
+impl LocalHandler<Inc> for LocalAccumulator {
+    async fn handle(&mut self, _: Inc, _: &mut LocalContext<Self>) {
+        *self.0.borrow_mut() += 1;
+    }
+}
+
+#[async_trait(?Send)]
+impl LocalHandler<Report> for LocalAccumulator {
+    async fn handle(&mut self, _: Report, _: &mut LocalContext<Self>) -> Accumulator {
+        Accumulator(*self.0.borrow())
+    }
+}
+
+#[test]
+fn test_local_actor() {
+    let executor = smol::LocalExecutor::new();
+    let count = Rc::new(RefCell::new(0));
+
+    let addr = LocalAccumulator(count.clone())
+        .create(None)
+        .spawn(&mut SmolLocal(&executor));
+    let join = addr.join();
+
+    // The address can be used from another thread, even though the actor cannot
+    let sender = std::thread::spawn(move || {
+        for _ in 0..10 {
+            addr.do_send(Inc).unwrap();
+        }
+        addr
+    });
+
+    smol::block_on(executor.run(async {
+        let addr = sender.join().unwrap();
+        assert_eq!(addr.send(Report).await.unwrap().0, 10);
+        drop(addr);
+        join.await;
+    }));
+
+    assert_eq!(*count.borrow(), 10);
+}
+
+#[test]
+fn test_send_blocking() {
+    let addr = Accumulator(0).create(Some(1)).spawn(&mut Smol::Global);
+
+    for _ in 0..10 {
+        addr.do_send_blocking(Inc).unwrap();
+    }
+    assert_eq!(addr.send_blocking(Report).unwrap().0, 10);
+    assert_eq!(
+        addr.send_blocking_timeout(Report, Duration::from_secs(2))
+            .unwrap()
+            .0,
+        10
+    );
+
+    let addr = Recorder::default().create(None).spawn(&mut Smol::Global);
+    addr.do_send(Stall(Duration::from_secs(1))).unwrap();
+    assert_eq!(
+        addr.send_blocking_timeout(GetRecords, Duration::from_millis(50)),
+        Err(SendTimeoutError::Timeout)
+    );
+}
+
+struct Stall(Duration);
+
+impl Message for Stall {
+    type Result = ();
+}
+
+#[async_trait]
+impl Handler<Stall> for Recorder {
+    async fn handle(&mut self, msg: Stall, _: &mut Context<Self>) {
+        smol::Timer::after(msg.0).await;
+    }
+}
+
+struct BlockingReport(Address<Accumulator>);
+
+impl Message for BlockingReport {
+    type Result = ();
+}
+
+#[async_trait]
+impl Handler<BlockingReport> for Recorder {
+    async fn handle(&mut self, msg: BlockingReport, _: &mut Context<Self>) {
+        let _ = msg.0.send_blocking(Report);
+    }
+}
+
+#[cfg(debug_assertions)]
+#[smol_potat::test]
+async fn test_send_blocking_in_actor_panics() {
+    let accumulator = Accumulator(0).create(None).spawn(&mut Smol::Global);
+    let addr = Recorder::default().create(None).spawn(&mut Smol::Global);
+
+    // The handler panics, so the actor is dropped and never responds
+    assert_eq!(
+        addr.send(BlockingReport(accumulator)).await,
+        Err(Disconnected)
+    );
+}
+
+#[cfg(feature = "with-tokio-1")]
+#[test]
+fn test_send_blocking_in_tokio_spawn_blocking() {
+    let addr = Accumulator(0).create(None).spawn(&mut Smol::Global);
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+
+    // The threads which the runtime runs blocking code on may block
+    let report = runtime.block_on(async {
+        tokio::task::spawn_blocking(move || addr.send_blocking(Report))
+            .await
+            .unwrap()
+    });
+    assert_eq!(report.unwrap().0, 0);
+}
+
+struct Add(usize);
+
+impl Message for Add {
+    type Result = usize;
+}
+
+impl NativeHandler<Add> for Accumulator {
+    async fn handle(&mut self, msg: Add, _: &mut Context<Self>) -> usize {
+        self.0 += msg.0;
+        self.0
+    }
+}
+
+#[smol_potat::test]
+async fn test_native_handler() {
+    let addr = Accumulator(0).create(None).spawn(&mut Smol::Global);
+
+    addr.do_send(Add(1)).unwrap();
+    assert_eq!(addr.send(Add(2)).await, Ok(3));
+
+    let chan: &dyn MessageChannel<Add> = &addr;
+    assert_eq!(chan.send(Add(3)).await, Ok(6));
+
+    // Handlers and native handlers can be mixed on the same actor
+    addr.do_send(Inc).unwrap();
+    assert_eq!(addr.send(Report).await.unwrap().0, 7);
+}
+
+#[derive(Default)]
+struct Ingester(Vec<Vec<usize>>);
+
+#[async_trait]
+impl Actor for Ingester {
+    type Stop = ();
+
+    async fn stopped(self) -> Self::Stop {}
+}
+
+struct Event(usize);
+
+impl Message for Event {
+    type Result = ();
+}
+
+struct WaitFor(flume::Receiver<()>);
+
+impl Message for WaitFor {
+    type Result = ();
+}
+
+struct GetBatches;
+
+impl Message for GetBatches {
+    type Result = Vec<Vec<usize>>;
+}
+
+#[async_trait]
+impl BatchHandler<Event> for Ingester {
+    async fn handle_batch(&mut self, events: Vec<Event>, _: &mut Context<Self>) {
+        self.0.push(events.into_iter().map(|e| e.0).collect());
+    }
+}
+
+#[async_trait]
+impl Handler<Event> for Ingester {
+    async fn handle(&mut self, event: Event, _: &mut Context<Self>) {
+        self.0.push(vec![event.0]);
+    }
+}
+
+#[async_trait]
+impl Handler<WaitFor> for Ingester {
+    async fn handle(&mut self, msg: WaitFor, _: &mut Context<Self>) {
+        let _ = msg.0.recv_async().await;
+        self.0.push(Vec::new());
+    }
+}
+
+#[async_trait]
+impl Handler<GetBatches> for Ingester {
+    async fn handle(&mut self, _: GetBatches, _: &mut Context<Self>) -> Vec<Vec<usize>> {
+        std::mem::take(&mut self.0)
+    }
+}
+
+#[smol_potat::test]
+async fn test_do_send_batch() {
+    let addr = Ingester::default().create(None).spawn(&mut Smol::Global);
+
+    let (tx, rx) = flume::bounded(1);
+    addr.do_send(WaitFor(rx)).unwrap();
+
+    // Queue up batches while the actor is busy. The first two are merged, but not across the
+    // message in between them and the third. A single message is only merged into a batch in front
+    // of it.
+    addr.do_send(Event(100)).unwrap();
+    addr.do_send_batch((0..3).map(Event)).unwrap();
+    addr.do_send_batch((3..5).map(Event)).unwrap();
+    addr.do_send_batch(std::iter::empty::<Event>()).unwrap();
+    let (tx2, rx2) = flume::bounded(1);
+    addr.do_send(WaitFor(rx2)).unwrap();
+    addr.do_send_batch((5..7).map(Event)).unwrap();
+    addr.do_send(Event(7)).unwrap();
+    addr.do_send_async(Event(8)).await.unwrap();
+
+    tx.send(()).unwrap();
+    tx2.send(()).unwrap();
+
+    assert_eq!(
+        addr.send(GetBatches).await.unwrap(),
+        vec![
+            vec![],
+            vec![100],
+            vec![0, 1, 2, 3, 4],
+            vec![],
+            vec![5, 6, 7, 8]
+        ]
+    );
+}
+
+struct Refresh {
+    view: usize,
+    revision: usize,
+}
+
+impl Message for Refresh {
+    type Result = ();
+}
+
+impl Coalesce for Refresh {
+    type Key = usize;
+
+    fn key(&self) -> usize {
+        self.view
+    }
+}
+
+#[async_trait]
+impl Handler<Refresh> for Ingester {
+    async fn handle(&mut self, msg: Refresh, _: &mut Context<Self>) {
+        self.0.push(vec![msg.view, msg.revision]);
+    }
+}
+
+struct DropRefreshes(bool);
+
+impl Message for DropRefreshes {
+    type Result = ();
+}
+
+#[async_trait]
+impl Handler<DropRefreshes> for Ingester {
+    async fn handle(&mut self, msg: DropRefreshes, ctx: &mut Context<Self>) {
+        if msg.0 {
+            ctx.push_behaviour(
+                Behaviour::new("dropping")
+                    .accept::<DropRefreshes>()
+                    .accept::<GetBatches>()
+                    .unaccepted(Unaccepted::Drop),
+            );
+        } else {
+            ctx.pop_behaviour();
+        }
+    }
+}
+
+#[smol_potat::test]
+async fn test_do_send_coalescing() {
+    let addr = Ingester::default().create(None).spawn(&mut Smol::Global);
+
+    let (tx, rx) = flume::bounded(1);
+    addr.do_send(WaitFor(rx)).unwrap();
+
+    for (view, revision) in [(0, 1), (1, 1), (0, 2), (0, 3)] {
+        addr.do_send_coalescing(Refresh { view, revision }).unwrap();
+    }
+    tx.send(()).unwrap();
+
+    // The latest revision of view 0 takes the place of the first one in the mailbox
+    assert_eq!(
+        addr.send(GetBatches).await.unwrap(),
+        vec![vec![], vec![0, 3], vec![1, 1]]
+    );
+
+    // Once handled, a message with the same key is queued again
+    addr.do_send_coalescing(Refresh {
+        view: 0,
+        revision: 4,
+    })
+    .unwrap();
+    assert_eq!(addr.send(GetBatches).await.unwrap(), vec![vec![0, 4]]);
+
+    // A message dropped by a behaviour does not hold on to its key
+    addr.send(DropRefreshes(true)).await.unwrap();
+    addr.do_send_coalescing(Refresh {
+        view: 0,
+        revision: 5,
+    })
+    .unwrap();
+    assert_eq!(
+        addr.send(GetBatches).await.unwrap(),
+        Vec::<Vec<usize>>::new()
+    );
+    addr.send(DropRefreshes(false)).await.unwrap();
+
+    addr.do_send_coalescing(Refresh {
+        view: 0,
+        revision: 6,
+    })
+    .unwrap();
+    assert_eq!(addr.send(GetBatches).await.unwrap(), vec![vec![0, 6]]);
+}
+
+#[derive(Default)]
+struct Ticker(usize);
+
+#[async_trait]
+impl Actor for Ticker {
+    type Stop = ();
+
+    async fn stopped(self) -> Self::Stop {}
+}
+
+struct Tick;
+
+impl Message for Tick {
+    type Result = ();
+}
+
+struct StartTicking(Duration);
+
+impl Message for StartTicking {
+    type Result = TimerHandle;
+}
+
+struct TickOnce(Duration);
+
+impl Message for TickOnce {
+    type Result = TimerHandle;
+}
+
+struct TickAt(SystemTime);
+
+impl Message for TickAt {
+    type Result = TimerHandle;
+}
+
+struct StopTicking;
+
+impl Message for StopTicking {
+    type Result = bool;
+}
+
+struct GetTicks;
+
+impl Message for GetTicks {
+    type Result = usize;
+}
+
+#[async_trait]
+impl Handler<Tick> for Ticker {
+    async fn handle(&mut self, _: Tick, _: &mut Context<Self>) {
+        self.0 += 1;
+    }
+}
+
+#[async_trait]
+impl Handler<StartTicking> for Ticker {
+    async fn handle(&mut self, msg: StartTicking, ctx: &mut Context<Self>) -> TimerHandle {
+        let timer = ctx.notify_interval(msg.0, || Tick).unwrap();
+        ctx.start_timer("tick", timer, &mut Smol::Global)
+    }
+}
+
+#[async_trait]
+impl Handler<TickOnce> for Ticker {
+    async fn handle(&mut self, msg: TickOnce, ctx: &mut Context<Self>) -> TimerHandle {
+        ctx.notify_after(msg.0, Tick)
+            .unwrap()
+            .spawn(&mut Smol::Global)
+    }
+}
+
+#[async_trait]
+impl Handler<TickAt> for Ticker {
+    async fn handle(&mut self, msg: TickAt, ctx: &mut Context<Self>) -> TimerHandle {
+        ctx.notify_at(msg.0, Tick).unwrap().spawn(&mut Smol::Global)
+    }
+}
+
+#[async_trait]
+impl Handler<StopTicking> for Ticker {
+    async fn handle(&mut self, _: StopTicking, ctx: &mut Context<Self>) -> bool {
+        ctx.cancel_timer("tick")
+    }
+}
+
+#[async_trait]
+impl Handler<GetTicks> for Ticker {
+    async fn handle(&mut self, _: GetTicks, _: &mut Context<Self>) -> usize {
+        self.0
+    }
+}
+
+#[smol_potat::test]
+async fn test_timer_handles() {
+    let addr = Ticker::default().create(None).spawn(&mut Smol::Global);
+
+    // Re-arming a key cancels the timer previously started under it
+    let first = addr
+        .send(StartTicking(Duration::from_secs(60)))
+        .await
+        .unwrap();
+    let second = addr
+        .send(StartTicking(Duration::from_millis(10)))
+        .await
+        .unwrap();
+    assert!(first.is_cancelled());
+    assert!(!second.is_cancelled());
+
+    smol::Timer::after(Duration::from_millis(100)).await;
+    assert!(first.is_finished());
+    assert!(!second.is_finished());
+    assert!(addr.send(StopTicking).await.unwrap());
+    assert!(!addr.send(StopTicking).await.unwrap());
+    assert!(second.is_cancelled());
+
+    let ticks = addr.send(GetTicks).await.unwrap();
+    assert!(ticks > 0);
+    smol::Timer::after(Duration::from_millis(50)).await;
+    assert_eq!(addr.send(GetTicks).await.unwrap(), ticks);
+
+    // Resetting a one-off timer pushes it back
+    let once = addr
+        .send(TickOnce(Duration::from_millis(20)))
+        .await
+        .unwrap();
+    once.reset(Duration::from_secs(60));
+    smol::Timer::after(Duration::from_millis(100)).await;
+    assert_eq!(addr.send(GetTicks).await.unwrap(), ticks);
+
+    once.reset(Duration::from_millis(1));
+    smol::Timer::after(Duration::from_millis(100)).await;
+    assert_eq!(addr.send(GetTicks).await.unwrap(), ticks + 1);
+    assert!(once.is_finished());
+}
+
+#[test]
+fn test_cron_schedule() {
+    let at = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+
+    // 2021-01-01T00:00:00Z, a Friday
+    let new_year = 1_609_459_200;
+
+    let every_five_minutes: CronSchedule = "0 */5 * * * *".parse().unwrap();
+    assert_eq!(
+        every_five_minutes.next_after(at(new_year)),
+        Some(at(new_year + 300))
+    );
+    assert_eq!(
+        every_five_minutes.next_after(at(new_year + 299)),
+        Some(at(new_year + 300))
+    );
+
+    // Five fields means on the minute. Mondays at 09:30 is 2021-01-04T09:30:00Z.
+    let mondays: CronSchedule = "30 9 * * 1".parse().unwrap();
+    assert_eq!(
+        mondays.next_after(at(new_year)),
+        Some(at(new_year + 3 * 86400 + 9 * 3600 + 30 * 60))
+    );
+
+    // Leap days only come around every four years
+    let leap_day: CronSchedule = "0 0 0 29 2 *".parse().unwrap();
+    assert_eq!(leap_day.next_after(at(new_year)), Some(at(1_709_164_800)));
+
+    let never: CronSchedule = "0 0 0 30 2 *".parse().unwrap();
+    assert_eq!(never.next_after(at(new_year)), None);
+
+    assert!("* * * *".parse::<CronSchedule>().is_err());
+    assert!("60 * * * * *".parse::<CronSchedule>().is_err());
+    assert!("*/0 * * * * *".parse::<CronSchedule>().is_err());
+    assert!("5-1 * * * * *".parse::<CronSchedule>().is_err());
+}
+
+#[smol_potat::test]
+async fn test_notify_at() {
+    let addr = Ticker::default().create(None).spawn(&mut Smol::Global);
+
+    // A time in the past fires straight away
+    addr.send(TickAt(SystemTime::now() - Duration::from_secs(60)))
+        .await
+        .unwrap();
+    smol::Timer::after(Duration::from_millis(50)).await;
+    assert_eq!(addr.send(GetTicks).await.unwrap(), 1);
+
+    let later = addr
+        .send(TickAt(SystemTime::now() + Duration::from_secs(3600)))
+        .await
+        .unwrap();
+    addr.send(TickAt(SystemTime::now() + Duration::from_millis(20)))
+        .await
+        .unwrap();
+    smol::Timer::after(Duration::from_millis(100)).await;
+    assert_eq!(addr.send(GetTicks).await.unwrap(), 2);
+
+    later.reset(Duration::from_millis(1));
+    smol::Timer::after(Duration::from_millis(100)).await;
+    assert_eq!(addr.send(GetTicks).await.unwrap(), 3);
+}
+
+#[derive(Default)]
+struct Sampler(Vec<usize>);
+
+#[async_trait]
+impl Actor for Sampler {
+    type Stop = ();
+
+    async fn stopped(self) -> Self::Stop {}
+}
+
+struct Sample(usize);
+
+impl Message for Sample {
+    type Result = ();
+}
+
+/// Starts sampling with the given policy, and sends the number of every tick as it comes due.
+struct StartSampling(QueuedTickPolicy, flume::Sender<usize>);
+
+impl Message for StartSampling {
+    type Result = TimerHandle;
+}
+
+/// Like `StartSampling`, but sends the timer back and then blocks the actor until told to go on, so
+/// that every tick comes due while it is blocked.
+struct SampleWhileBlocked {
+    policy: QueuedTickPolicy,
+    due: flume::Sender<usize>,
+    timer: flume::Sender<TimerHandle>,
+    unblock: flume::Receiver<()>,
+}
+
+impl Message for SampleWhileBlocked {
+    type Result = ();
+}
+
+struct DropSamples(bool);
+
+impl Message for DropSamples {
+    type Result = ();
+}
+
+struct TakeSamples;
+
+impl Message for TakeSamples {
+    type Result = Vec<usize>;
+}
+
+#[async_trait]
+impl Handler<Sample> for Sampler {
+    async fn handle(&mut self, msg: Sample, _: &mut Context<Self>) {
+        self.0.push(msg.0);
+    }
+}
+
+fn start_sampling(
+    ctx: &mut Context<Sampler>,
+    policy: QueuedTickPolicy,
+    due: flume::Sender<usize>,
+) -> TimerHandle {
+    let counter = AtomicUsize::new(0);
+    let interval = Interval::fixed_rate(Duration::from_millis(10)).queued_ticks(policy);
+    ctx.notify_interval_with(interval, move || {
+        let tick = counter.fetch_add(1, Ordering::SeqCst);
+        let _ = due.send(tick);
+        Sample(tick)
+    })
+    .unwrap()
+    .spawn(&mut Smol::Global)
+}
+
+#[async_trait]
+impl Handler<StartSampling> for Sampler {
+    async fn handle(&mut self, msg: StartSampling, ctx: &mut Context<Self>) -> TimerHandle {
+        start_sampling(ctx, msg.0, msg.1)
+    }
+}
+
+#[async_trait]
+impl Handler<SampleWhileBlocked> for Sampler {
+    async fn handle(&mut self, msg: SampleWhileBlocked, ctx: &mut Context<Self>) {
+        let _ = msg.timer.send(start_sampling(ctx, msg.policy, msg.due));
+        let _ = msg.unblock.recv_async().await;
+    }
+}
+
+#[async_trait]
+impl Handler<DropSamples> for Sampler {
+    async fn handle(&mut self, msg: DropSamples, ctx: &mut Context<Self>) {
+        if msg.0 {
+            ctx.push_behaviour(
+                Behaviour::new("dropping")
+                    .accept::<DropSamples>()
+                    .accept::<StartSampling>()
+                    .accept::<TakeSamples>()
+                    .unaccepted(Unaccepted::Drop),
+            );
+        } else {
+            ctx.pop_behaviour();
+        }
+    }
+}
+
+#[async_trait]
+impl Handler<TakeSamples> for Sampler {
+    async fn handle(&mut self, _: TakeSamples, _: &mut Context<Self>) -> Vec<usize> {
+        std::mem::take(&mut self.0)
+    }
+}
+
+#[smol_potat::test]
+async fn test_queued_tick_policy() {
+    let addr = Sampler::default().create(None).spawn(&mut Smol::Global);
+
+    // Returns the samples handled from ticks which came due while the actor was blocked, and how
+    // many ticks came due
+    let blocked_ticks = |policy| {
+        let addr = addr.clone();
+        async move {
+            let (due_tx, due_rx) = flume::unbounded();
+            let (timer_tx, timer_rx) = flume::bounded(1);
+            let (unblock_tx, unblock_rx) = flume::bounded(1);
+            addr.do_send(SampleWhileBlocked {
+                policy,
+                due: due_tx,
+                timer: timer_tx,
+                unblock: unblock_rx,
+            })
+            .unwrap();
+
+            let timer = timer_rx.recv_async().await.unwrap();
+            for _ in 0..5 {
+                due_rx.recv_async().await.unwrap();
+            }
+
+            // Wait for the timer to end, so that no tick is still being delivered
+            timer.cancel();
+            while !timer.is_finished() {
+                smol::Timer::after(Duration::from_millis(1)).await;
+            }
+
+            unblock_tx.send(()).unwrap();
+            let samples = addr.send(TakeSamples).await.unwrap();
+            (samples, 5 + due_rx.len())
+        }
+    };
+
+    // Every tick is queued, except for one which was being delivered when the timer was cancelled
+    let (queued, due) = blocked_ticks(QueuedTickPolicy::Queue).await;
+    assert!(queued.len() >= due - 1);
+    assert_eq!(queued, (0..queued.len()).collect::<Vec<_>>());
+
+    // Only the first tick is kept
+    let (skipped, _) = blocked_ticks(QueuedTickPolicy::Skip).await;
+    assert_eq!(skipped, vec![0]);
+
+    // Only the latest tick is kept
+    let (coalesced, due) = blocked_ticks(QueuedTickPolicy::Coalesce).await;
+    assert_eq!(coalesced, vec![due - 1]);
+
+    // A tick dropped by a behaviour does not hold back the ticks after it
+    addr.send(DropSamples(true)).await.unwrap();
+    let (due_tx, due_rx) = flume::unbounded();
+    let timer = addr
+        .send(StartSampling(QueuedTickPolicy::Skip, due_tx))
+        .await
+        .unwrap();
+
+    // The first tick is in the mailbox once the second comes due
+    due_rx.recv_async().await.unwrap();
+    due_rx.recv_async().await.unwrap();
+    addr.send(DropSamples(false)).await.unwrap();
+
+    let sampled = async {
+        loop {
+            due_rx.recv_async().await.unwrap();
+            if !addr.send(TakeSamples).await.unwrap().is_empty() {
+                break;
+            }
+        }
+    };
+    assert!(sampled.timeout(Duration::from_secs(5)).await.is_some());
+    timer.cancel();
+}
+
+#[derive(Default)]
+struct Initialiser {
+    ready: bool,
+    handled: Vec<usize>,
+}
+
+#[async_trait]
+impl Actor for Initialiser {
+    type Stop = ();
+
+    async fn stopped(self) -> Self::Stop {}
+}
+
+struct Work(usize);
+
+impl Message for Work {
+    type Result = usize;
+}
+
+struct Ready;
+
+impl Message for Ready {
+    type Result = ();
+}
+
+struct GetHandled;
+
+impl Message for GetHandled {
+    type Result = Vec<usize>;
+}
+
+#[async_trait]
+impl Handler<Work> for Initialiser {
+    async fn handle(&mut self, work: Work, ctx: &mut Context<Self>) -> usize {
+        if !self.ready {
+            ctx.stash(work);
+            return 0;
+        }
+
+        self.handled.push(work.0);
+        work.0 * 2
+    }
+}
+
+#[async_trait]
+impl Handler<Ready> for Initialiser {
+    async fn handle(&mut self, _: Ready, ctx: &mut Context<Self>) {
+        assert_eq!(ctx.stashed(), 3);
+        self.ready = true;
+        ctx.unstash_all();
+    }
+}
+
+#[async_trait]
+impl Handler<GetHandled> for Initialiser {
+    async fn handle(&mut self, _: GetHandled, _: &mut Context<Self>) -> Vec<usize> {
+        self.handled.clone()
+    }
+}
+
+#[smol_potat::test]
+async fn test_stash() {
+    let addr = Initialiser::default().create(None).spawn(&mut Smol::Global);
+
+    addr.do_send(Work(1)).unwrap();
+    let (second, third, ()) =
+        futures_util::future::join3(addr.send(Work(2)), addr.send(Work(3)), async {
+            addr.do_send(Ready).unwrap()
+        })
+        .await;
+
+    // Replies are sent once the stashed messages have been replayed
+    assert_eq!(second.unwrap(), 4);
+    assert_eq!(third.unwrap(), 6);
+
+    addr.do_send(Work(4)).unwrap();
+    assert_eq!(addr.send(GetHandled).await.unwrap(), vec![1, 2, 3, 4]);
+}
+
+#[derive(Default)]
+struct Connection {
+    published: usize,
+}
+
+#[async_trait]
+impl Actor for Connection {
+    type Stop = ();
+
+    async fn started(&mut self, ctx: &mut Context<Self>) {
+        ctx.set_behaviour(
+            Behaviour::new("connecting")
+                .accept::<Connect>()
+                .reject::<Publish>(),
+        );
+    }
+
+    async fn stopped(self) -> Self::Stop {}
+}
+
+struct Connect;
+
+impl Message for Connect {
+    type Result = ();
+}
+
+struct Publish;
+
+impl Message for Publish {
+    type Result = Result<usize, Rejected>;
+}
+
+struct Pause;
+
+impl Message for Pause {
+    type Result = ();
+}
+
+struct Resume;
+
+impl Message for Resume {
+    type Result = ();
+}
+
+struct Published;
+
+impl Message for Published {
+    type Result = usize;
+}
+
+#[async_trait]
+impl Handler<Connect> for Connection {
+    async fn handle(&mut self, _: Connect, ctx: &mut Context<Self>) {
+        ctx.set_behaviour(
+            Behaviour::new("connected")
+                .accept::<Publish>()
+                .accept::<Pause>()
+                .accept::<Published>(),
+        );
+    }
+}
+
+#[async_trait]
+impl Handler<Publish> for Connection {
+    async fn handle(&mut self, _: Publish, _: &mut Context<Self>) -> Result<usize, Rejected> {
+        self.published += 1;
+        Ok(self.published)
+    }
+}
+
+#[async_trait]
+impl Handler<Pause> for Connection {
+    async fn handle(&mut self, _: Pause, ctx: &mut Context<Self>) {
+        ctx.push_behaviour(
+            Behaviour::new("paused")
+                .accept::<Resume>()
+                .unaccepted(Unaccepted::Drop),
+        );
+    }
+}
+
+#[async_trait]
+impl Handler<Resume> for Connection {
+    async fn handle(&mut self, _: Resume, ctx: &mut Context<Self>) {
+        assert_eq!(ctx.pop_behaviour().unwrap().name(), "paused");
+        assert_eq!(ctx.behaviour().unwrap().name(), "connected");
+    }
+}
+
+#[async_trait]
+impl Handler<Published> for Connection {
+    async fn handle(&mut self, _: Published, _: &mut Context<Self>) -> usize {
+        self.published
+    }
+}
+
+#[smol_potat::test]
+async fn test_behaviours() {
+    let addr = Connection::default().create(None).spawn(&mut Smol::Global);
+
+    let rejected = addr.send(Publish).await.unwrap().unwrap_err();
+    assert_eq!(rejected.behaviour(), "connecting");
+
+    // Stashed until connected
+    let (published, ()) = futures_util::future::join(addr.send(Published), async {
+        addr.do_send(Connect).unwrap()
+    })
+    .await;
+    assert_eq!(published.unwrap(), 0);
+    assert_eq!(addr.send(Publish).await.unwrap(), Ok(1));
+
+    // Dropped while paused
+    addr.do_send(Pause).unwrap();
+    assert!(addr.send(Publish).await.is_err());
+    addr.send(Resume).await.unwrap();
+    assert_eq!(addr.send(Publish).await.unwrap(), Ok(2));
+}
+
+#[smol_potat::test]
+async fn test_stashed_message_releases_its_permit() {
+    let addr = Connection::default()
+        .create(None)
+        .with_limits(Limits::new().concurrency(1))
+        .spawn(&mut Smol::Global);
+
+    // The stashed message must not hold the only permit, or the message which unstashes it could
+    // never be admitted
+    let sent = futures_util::future::join(addr.send(Published), addr.send(Connect))
+        .timeout(Duration::from_secs(5))
+        .await;
+    assert_eq!(sent, Some((Ok(0), Ok(()))));
+    assert_eq!(addr.send(Publish).await.unwrap(), Ok(1));
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum DoorState {
+    Closed,
+    Open,
+    Locked,
+}
+
+#[derive(Debug)]
+enum DoorEvent {
+    Open,
+    Close,
+    Lock,
+}
+
+struct Door {
+    state: DoorState,
+    log: Vec<String>,
+}
+
+#[async_trait]
+impl Actor for Door {
+    type Stop = ();
+
+    async fn started(&mut self, ctx: &mut Context<Self>) {
+        xtra::fsm::start(self, ctx).await;
+    }
+
+    async fn stopped(self) -> Self::Stop {}
+}
+
+#[async_trait]
+impl FsmActor for Door {
+    type State = DoorState;
+    type Event = DoorEvent;
+    type Spawner = Smol<'static>;
+
+    fn state_mut(&mut self) -> &mut DoorState {
+        &mut self.state
+    }
+
+    async fn on_event(&mut self, state: &DoorState, event: DoorEvent) -> Transition<DoorState> {
+        match (state, event) {
+            (DoorState::Closed, DoorEvent::Open) => Transition::Goto(DoorState::Open),
+            (DoorState::Open, DoorEvent::Close) => Transition::Goto(DoorState::Closed),
+            (DoorState::Closed, DoorEvent::Lock) => Transition::Goto(DoorState::Locked),
+            _ => Transition::Stay,
+        }
+    }
+
+    async fn on_enter(&mut self, state: &DoorState, _: &mut Context<Self>) {
+        self.log.push(format!("enter {:?}", state));
+    }
+
+    async fn on_exit(&mut self, state: &DoorState, _: &mut Context<Self>) {
+        self.log.push(format!("exit {:?}", state));
+    }
+
+    fn state_timeout(&self, state: &DoorState) -> Option<Duration> {
+        match state {
+            DoorState::Open => Some(Duration::from_millis(50)),
+            _ => None,
+        }
+    }
+
+    async fn on_timeout(&mut self, _: &DoorState) -> Transition<DoorState> {
+        Transition::Goto(DoorState::Closed)
+    }
+}
+
+struct GetLog;
+
+impl Message for GetLog {
+    type Result = Vec<String>;
+}
+
+#[async_trait]
+impl Handler<GetLog> for Door {
+    async fn handle(&mut self, _: GetLog, _: &mut Context<Self>) -> Vec<String> {
+        std::mem::take(&mut self.log)
+    }
+}
+
+#[smol_potat::test]
+async fn test_fsm_actor() {
+    let mut door = Door {
+        state: DoorState::Closed,
+        log: Vec::new(),
+    };
+
+    // Transitions can be tested without running the actor
+    assert_eq!(
+        door.on_event(&DoorState::Locked, DoorEvent::Open).await,
+        Transition::Stay
+    );
+
+    let addr = door.create(None).spawn(&mut Smol::Global);
+    assert_eq!(addr.fire(DoorEvent::Lock).await, Ok(DoorState::Locked));
+    assert_eq!(addr.fire(DoorEvent::Open).await, Ok(DoorState::Locked));
+    assert_eq!(
+        addr.send(GetLog).await.unwrap(),
+        vec!["enter Closed", "exit Closed", "enter Locked"]
+    );
+
+    // The open state times out, unless it is left first
+    let addr = Door {
+        state: DoorState::Closed,
+        log: Vec::new(),
+    }
+    .create(None)
+    .spawn(&mut Smol::Global);
+    assert_eq!(addr.fire(DoorEvent::Open).await, Ok(DoorState::Open));
+    assert_eq!(addr.fire(DoorEvent::Close).await, Ok(DoorState::Closed));
+    assert_eq!(addr.fire(DoorEvent::Open).await, Ok(DoorState::Open));
+    smol::Timer::after(Duration::from_millis(20)).await;
+    assert_eq!(addr.fire(DoorEvent::Lock).await, Ok(DoorState::Open));
+    smol::Timer::after(Duration::from_millis(80)).await;
+    assert_eq!(addr.fire(DoorEvent::Lock).await, Ok(DoorState::Locked));
+}
+
+#[derive(Default)]
+struct Notified(Vec<usize>);
+
+#[async_trait]
+impl Actor for Notified {
+    type Stop = ();
+
+    async fn started(&mut self, ctx: &mut Context<Self>) {
+        ctx.set_notification_limit(3);
+    }
+
+    async fn stopped(self) -> Self::Stop {}
+}
+
+struct Log(usize);
+
+impl Message for Log {
+    type Result = ();
+}
+
+struct NotifyInOrder;
+
+impl Message for NotifyInOrder {
+    type Result = ();
+}
+
+/// Defers a log of the number, and then tells the sender that it has been deferred.
+struct NotifyDeferred(usize, flume::Sender<()>);
+
+impl Message for NotifyDeferred {
+    type Result = ();
+}
+
+/// Notifies the actor with a chain of this many notifications, telling the sender once the chain has
+/// ended.
+struct Spin(usize, flume::Sender<()>);
+
+impl Message for Spin {
+    type Result = ();
+}
+
+struct TakeLog;
+
+impl Message for TakeLog {
+    type Result = Vec<usize>;
+}
+
+#[async_trait]
+impl Handler<Log> for Notified {
+    async fn handle(&mut self, msg: Log, _: &mut Context<Self>) {
+        self.0.push(msg.0);
+    }
+}
+
+#[async_trait]
+impl Handler<NotifyInOrder> for Notified {
+    async fn handle(&mut self, _: NotifyInOrder, ctx: &mut Context<Self>) {
+        ctx.notify(Log(1));
+        ctx.notify(Log(2));
+        ctx.notify_front(Log(0));
+    }
+}
+
+#[async_trait]
+impl Handler<NotifyDeferred> for Notified {
+    async fn handle(&mut self, msg: NotifyDeferred, ctx: &mut Context<Self>) {
+        ctx.notify_deferred(Log(msg.0));
+        let _ = msg.1.send(());
+    }
+}
+
+#[async_trait]
+impl Handler<Spin> for Notified {
+    async fn handle(&mut self, msg: Spin, ctx: &mut Context<Self>) {
+        match msg.0 {
+            0 => {
+                self.0.push(1000);
+                let _ = msg.1.send(());
+            }
+            n => ctx.notify(Spin(n - 1, msg.1)),
+        }
+    }
+}
+
+#[async_trait]
+impl Handler<WaitFor> for Notified {
+    async fn handle(&mut self, msg: WaitFor, _: &mut Context<Self>) {
+        let _ = msg.0.recv_async().await;
+    }
+}
+
+#[async_trait]
+impl Handler<TakeLog> for Notified {
+    async fn handle(&mut self, _: TakeLog, _: &mut Context<Self>) -> Vec<usize> {
+        std::mem::take(&mut self.0)
+    }
+}
+
+#[smol_potat::test]
+async fn test_self_notification_order() {
+    let addr = Notified::default().create(None).spawn(&mut Smol::Global);
+
+    addr.send(NotifyInOrder).await.unwrap();
+    assert_eq!(addr.send(TakeLog).await.unwrap(), vec![0, 1, 2]);
+
+    // Deferred behind the messages which were in the mailbox, but not those sent later. The actor is
+    // blocked until they have all been queued.
+    let (unblock_tx, unblock_rx) = flume::bounded(1);
+    let (deferred_tx, deferred_rx) = flume::bounded(1);
+    addr.do_send(WaitFor(unblock_rx)).unwrap();
+    addr.do_send(NotifyDeferred(99, deferred_tx)).unwrap();
+    addr.do_send(Log(10)).unwrap();
+    addr.do_send(Log(11)).unwrap();
+    unblock_tx.send(()).unwrap();
+    deferred_rx.recv_async().await.unwrap();
+    addr.do_send(Log(12)).unwrap();
+    assert_eq!(addr.send(TakeLog).await.unwrap(), vec![10, 11, 99, 12]);
+
+    // A chain of notifications does not hold up the mailbox
+    let (unblock_tx, unblock_rx) = flume::bounded(1);
+    let (done_tx, done_rx) = flume::bounded(1);
+    addr.do_send(WaitFor(unblock_rx)).unwrap();
+    addr.do_send(Spin(100, done_tx)).unwrap();
+    addr.do_send(Log(7)).unwrap();
+    let mut during = addr.send(TakeLog);
+    futures_util::future::poll_fn(|cx| {
+        // Polling the send once queues the message
+        assert!(during.poll_unpin(cx).is_pending());
+        std::task::Poll::Ready(())
+    })
+    .await;
+    unblock_tx.send(()).unwrap();
+    assert_eq!(during.await.unwrap(), vec![7]);
+    done_rx.recv_async().await.unwrap();
+    assert_eq!(addr.send(TakeLog).await.unwrap(), vec![1000]);
+}
+
+#[derive(Default)]
+struct LocalNotified(Vec<usize>);
+
+#[async_trait(?Send)]
+impl LocalActor for LocalNotified {
+    type Stop = ();
+
+    async fn started(&mut self, ctx: &mut LocalContext<Self>) {
+        ctx.set_notification_limit(3);
+    }
+
+    async fn stopped(self) -> Self::Stop {}
+}
+
+#[async_trait(?Send)]
+impl LocalHandler<Spin> for LocalNotified {
+    async fn handle(&mut self, msg: Spin, ctx: &mut LocalContext<Self>) {
+        match msg.0 {
+            0 => {
+                self.0.push(1000);
+                let _ = msg.1.send(());
+            }
+            n => ctx.notify(Spin(n - 1, msg.1)),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl LocalHandler<Log> for LocalNotified {
+    async fn handle(&mut self, msg: Log, _: &mut LocalContext<Self>) {
+        self.0.push(msg.0);
+    }
+}
+
+#[async_trait(?Send)]
+impl LocalHandler<WaitFor> for LocalNotified {
+    async fn handle(&mut self, msg: WaitFor, _: &mut LocalContext<Self>) {
+        let _ = msg.0.recv_async().await;
+    }
+}
+
+#[async_trait(?Send)]
+impl LocalHandler<TakeLog> for LocalNotified {
+    async fn handle(&mut self, _: TakeLog, _: &mut LocalContext<Self>) -> Vec<usize> {
+        std::mem::take(&mut self.0)
+    }
+}
+
+#[test]
+fn test_local_notification_limit() {
+    // The same chain of notifications is handled in the same order by an actor and a local actor
+    let addr = Notified::default().create(None).spawn(&mut Smol::Global);
+    let (unblock_tx, unblock_rx) = flume::bounded(1);
+    let (done_tx, done_rx) = flume::bounded(1);
+    addr.do_send(WaitFor(unblock_rx)).unwrap();
+    addr.do_send(Spin(100, done_tx)).unwrap();
+    addr.do_send(Log(7)).unwrap();
+    let mut during = addr.send(TakeLog);
+    let expected = smol::block_on(async {
+        futures_util::future::poll_fn(|cx| {
+            assert!(during.poll_unpin(cx).is_pending());
+            std::task::Poll::Ready(())
+        })
+        .await;
+        unblock_tx.send(()).unwrap();
+        let during = during.await.unwrap();
+        done_rx.recv_async().await.unwrap();
+        (during, addr.send(TakeLog).await.unwrap())
+    });
+    assert_eq!(expected, (vec![7], vec![1000]));
+
+    let executor = smol::LocalExecutor::new();
+    let addr = LocalNotified::default()
+        .create(None)
+        .spawn(&mut SmolLocal(&executor));
+    let (unblock_tx, unblock_rx) = flume::bounded(1);
+    let (done_tx, done_rx) = flume::bounded(1);
+    addr.do_send(WaitFor(unblock_rx)).unwrap();
+    addr.do_send(Spin(100, done_tx)).unwrap();
+    addr.do_send(Log(7)).unwrap();
+    let mut during = addr.send(TakeLog);
+    let local = smol::block_on(executor.run(async {
+        futures_util::future::poll_fn(|cx| {
+            assert!(during.poll_unpin(cx).is_pending());
+            std::task::Poll::Ready(())
+        })
+        .await;
+        unblock_tx.send(()).unwrap();
+        let during = during.await.unwrap();
+        done_rx.recv_async().await.unwrap();
+        (during, addr.send(TakeLog).await.unwrap())
+    }));
+    assert_eq!(local, expected);
+}
+
+struct Deposited(u64);
+
+impl Codec for Deposited {
+    fn encode(&self) -> Vec<u8> {
+        self.0.to_le_bytes().to_vec()
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut buf = [0; 8];
+        if bytes.len() != buf.len() {
+            return Err(DecodeError::new("expected 8 bytes"));
+        }
+        buf.copy_from_slice(bytes);
+        Ok(Deposited(u64::from_le_bytes(buf)))
+    }
+}
+
+struct Account {
+    journal: Arc<dyn Journal>,
+    snapshots: MemorySnapshotStore,
+    balance: u64,
+}
+
+impl Account {
+    fn new(journal: Arc<dyn Journal>) -> Self {
+        Account {
+            journal,
+            snapshots: MemorySnapshotStore::new(),
+            balance: 0,
+        }
+    }
+}
+
+#[async_trait]
+impl Actor for Account {
+    type Stop = ();
+
+    async fn started(&mut self, ctx: &mut Context<Self>) {
+        assert!(ctx.is_recovered());
+    }
+
+    async fn stopped(self) -> Self::Stop {}
+}
+
+impl PersistentActor for Account {
+    type Event = Deposited;
+
+    fn persistence_id(&self) -> String {
+        "account".to_string()
+    }
+
+    fn journal(&self) -> Arc<dyn Journal> {
+        self.journal.clone()
+    }
+
+    fn apply(&mut self, event: &Deposited) {
+        self.balance += event.0;
+    }
+}
+
+struct Deposit(u64);
+
+impl Message for Deposit {
+    type Result = Result<(), JournalError>;
+}
+
+struct Balance;
+
+impl Message for Balance {
+    type Result = u64;
+}
+
+#[async_trait]
+impl Handler<Deposit> for Account {
+    async fn handle(&mut self, msg: Deposit, ctx: &mut Context<Self>) -> Result<(), JournalError> {
+        self.persist(Deposited(msg.0), ctx).await
+    }
+}
+
+#[async_trait]
+impl Handler<Balance> for Account {
+    async fn handle(&mut self, _: Balance, _: &mut Context<Self>) -> u64 {
+        self.balance
+    }
+}
+
+async fn deposit_and_recover(journal: Arc<dyn Journal>) {
+    let addr = Account::new(journal.clone())
+        .create(None)
+        .with_persistence()
+        .spawn(&mut Smol::Global);
+    addr.send(Deposit(5)).await.unwrap().unwrap();
+    addr.send(Deposit(7)).await.unwrap().unwrap();
+    assert_eq!(addr.send(Balance).await.unwrap(), 12);
+
+    // A new actor with the same journal recovers the same state
+    let addr = Account::new(journal)
+        .create(None)
+        .with_persistence()
+        .spawn(&mut Smol::Global);
+    assert_eq!(addr.send(Balance).await.unwrap(), 12);
+}
+
+#[smol_potat::test]
+async fn test_persistent_actor() {
+    let journal = MemoryJournal::new();
+    deposit_and_recover(Arc::new(journal.clone())).await;
+    assert_eq!(journal.read("account", 2).await.unwrap().len(), 1);
+
+    let dir = std::env::temp_dir().join(format!("xtra-journal-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let journal: Arc<dyn Journal> = Arc::new(FileJournal::open(&dir).unwrap());
+    deposit_and_recover(journal).await;
+
+    // An event which was only partly written is cut off
+    let path = dir.join(format!("{}.journal", "6163636f756e74")); // "account" in hex
+    std::fs::OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .unwrap()
+        .write_all(&[8, 0])
+        .unwrap();
+    let journal: Arc<dyn Journal> = Arc::new(FileJournal::open(&dir).unwrap());
+    let addr = Account::new(journal.clone())
+        .create(None)
+        .with_persistence()
+        .spawn(&mut Smol::Global);
+    addr.send(Deposit(1)).await.unwrap().unwrap();
+    assert_eq!(journal.read("account", 1).await.unwrap().len(), 3);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    // An actor which cannot be recovered is stopped rather than started with the wrong state
+    let journal = MemoryJournal::new();
+    journal.append("account", vec![1, 2, 3]).await.unwrap();
+    let addr = Account::new(Arc::new(journal))
+        .create(None)
+        .with_persistence()
+        .spawn(&mut Smol::Global);
+    addr.join().await;
+    assert_eq!(addr.send(Balance).await, Err(Disconnected));
+}
+
+impl SnapshotActor for Account {
+    fn snapshot_id(&self) -> String {
+        self.persistence_id()
+    }
+
+    fn snapshot_store(&self) -> Arc<dyn SnapshotStore> {
+        Arc::new(self.snapshots.clone())
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        Deposited(self.balance).encode()
+    }
+
+    fn restore(&mut self, state: &[u8]) -> Result<(), DecodeError> {
+        self.balance = Deposited::decode(state)?.0;
+        Ok(())
+    }
+}
+
+struct SnapshotCounter {
+    store: Arc<dyn SnapshotStore>,
+    count: u64,
+}
+
+#[async_trait]
+impl Actor for SnapshotCounter {
+    type Stop = u64;
+
+    async fn stopped(self) -> u64 {
+        self.count
+    }
+}
+
+impl SnapshotActor for SnapshotCounter {
+    fn snapshot_id(&self) -> String {
+        "counter".to_string()
+    }
+
+    fn snapshot_store(&self) -> Arc<dyn SnapshotStore> {
+        self.store.clone()
+    }
+
+    fn snapshot_every(&self) -> Option<u64> {
+        Some(2)
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        self.count.to_le_bytes().to_vec()
+    }
+
+    fn restore(&mut self, state: &[u8]) -> Result<(), DecodeError> {
+        self.count = Deposited::decode(state)?.0;
+        Ok(())
+    }
+}
+
+struct Increment;
+
+impl Message for Increment {
+    type Result = ();
+}
+
+#[async_trait]
+impl Handler<Increment> for SnapshotCounter {
+    async fn handle(&mut self, _: Increment, _: &mut Context<Self>) {
+        self.count += 1;
+    }
+}
+
+async fn snapshotted_count(store: &Arc<dyn SnapshotStore>) -> Option<u64> {
+    let snapshot = store.load("counter").await.unwrap()?;
+    Some(Deposited::decode(&snapshot.state).unwrap().0)
+}
+
+#[smol_potat::test]
+async fn test_snapshots() {
+    let store: Arc<dyn SnapshotStore> = Arc::new(MemorySnapshotStore::new());
+    let (addr, fut) = SnapshotCounter {
+        store: store.clone(),
+        count: 0,
+    }
+    .create(None)
+    .with_snapshots()
+    .run();
+    let handle = smol::spawn(fut);
+
+    for _ in 0..3 {
+        addr.send(Increment).await.unwrap();
+    }
+    assert_eq!(snapshotted_count(&store).await, Some(2));
+
+    // A snapshot is taken when the actor stops
+    drop(addr);
+    assert_eq!(handle.await, 3);
+    assert_eq!(snapshotted_count(&store).await, Some(3));
+
+    // The state is restored before the actor starts
+    let (addr, fut) = SnapshotCounter { store, count: 0 }
+        .create(None)
+        .with_snapshots()
+        .run();
+    let handle = smol::spawn(fut);
+    addr.send(Increment).await.unwrap();
+    drop(addr);
+    assert_eq!(handle.await, 4);
+
+    // Only the events after the snapshot are replayed into a persistent actor
+    let journal = MemoryJournal::new();
+    journal
+        .append("account", Deposited(5).encode())
+        .await
+        .unwrap();
+    journal
+        .append("account", Deposited(7).encode())
+        .await
+        .unwrap();
+    let snapshots = MemorySnapshotStore::new();
+    let snapshot = Snapshot {
+        sequence: 1,
+        state: Deposited(5).encode(),
+    };
+    snapshots.save("account", snapshot).await.unwrap();
+
+    let addr = Account {
+        journal: Arc::new(journal),
+        snapshots,
+        balance: 0,
+    }
+    .create(None)
+    .with_snapshots()
+    .with_persistence()
+    .spawn(&mut Smol::Global);
+    assert_eq!(addr.send(Balance).await.unwrap(), 12);
+
+    // A persistent actor which has not been recovered is not snapshotted
+    let journal = MemoryJournal::new();
+    journal.append("account", vec![1, 2, 3]).await.unwrap();
+    let account = Account::new(Arc::new(journal));
+    let snapshots = account.snapshots.clone();
+    let addr = account
+        .create(None)
+        .with_snapshots()
+        .with_persistence()
+        .spawn(&mut Smol::Global);
+    addr.join().await;
+    assert_eq!(snapshots.load("account").await.unwrap(), None);
+}
+
+#[smol_potat::test]
+async fn test_file_snapshot_store() {
+    let dir = std::env::temp_dir().join(format!("xtra-snapshots-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let store = FileSnapshotStore::open(&dir).unwrap();
+    assert_eq!(store.load("counter").await.unwrap(), None);
+
+    for sequence in 1..3 {
+        let snapshot = Snapshot {
+            sequence,
+            state: vec![1, 2, 3],
+        };
+        store.save("counter", snapshot.clone()).await.unwrap();
+        assert_eq!(store.load("counter").await.unwrap(), Some(snapshot));
+    }
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+struct Job(u64);
+
+impl Message for Job {
+    type Result = ();
+}
+
+impl Codec for Job {
+    fn encode(&self) -> Vec<u8> {
+        Deposited(self.0).encode()
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        Ok(Job(Deposited::decode(bytes)?.0))
+    }
+}
+
+#[derive(Default)]
+struct Worker(Vec<u64>);
+
+#[async_trait]
+impl Actor for Worker {
+    type Stop = ();
+
+    async fn stopped(self) -> Self::Stop {}
+}
+
+#[async_trait]
+impl Handler<Job> for Worker {
+    async fn handle(&mut self, job: Job, _: &mut Context<Self>) {
+        self.0.push(job.0);
+    }
+}
+
+struct Done;
+
+impl Message for Done {
+    type Result = Vec<u64>;
+}
+
+#[async_trait]
+impl Handler<Done> for Worker {
+    async fn handle(&mut self, _: Done, _: &mut Context<Self>) -> Vec<u64> {
+        self.0.clone()
+    }
+}
+
+#[smol_potat::test]
+async fn test_durable_mailbox() {
+    let dir = std::env::temp_dir().join(format!("xtra-mailbox-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("jobs.log");
+
+    // The actor crashes before handling any jobs
+    let (addr, mut ctx) = Context::<Worker>::new(None);
+    let jobs = ctx.durable_mailbox(&path).unwrap();
+    jobs.do_send(Job(1)).unwrap();
+    jobs.do_send(Job(2)).unwrap();
+    assert_eq!(jobs.pending(), 2);
+
+    // The log can only be opened once at a time
+    assert!(matches!(
+        ctx.durable_mailbox::<Job>(&path),
+        Err(MailboxError::Locked)
+    ));
+    drop((addr, ctx, jobs));
+
+    // The jobs are delivered again, before any new ones
+    let mut manager = Worker::default().create(None);
+    let jobs = manager.durable_mailbox(&path).unwrap();
+    let addr = manager.spawn(&mut Smol::Global);
+    jobs.do_send(Job(3)).unwrap();
+    jobs.do_send_async(Job(4)).await.unwrap();
+    assert_eq!(addr.send(Done).await.unwrap(), vec![1, 2, 3, 4]);
+    assert_eq!(jobs.pending(), 0);
+    drop(jobs);
+
+    // Handled jobs are not delivered again, and neither are jobs which a behaviour dropped
+    let (addr, mut ctx) = Context::<Worker>::new(None);
+    let jobs = ctx.durable_mailbox(&path).unwrap();
+    ctx.set_behaviour(
+        Behaviour::new("paused")
+            .accept::<Done>()
+            .unaccepted(Unaccepted::Drop),
+    );
+    smol::spawn(ctx.run(Worker::default())).detach();
+    jobs.do_send(Job(5)).unwrap();
+    assert_eq!(addr.send(Done).await.unwrap(), Vec::<u64>::new());
+    assert_eq!(jobs.pending(), 0);
+    drop(jobs);
+
+    let mut manager = Worker::default().create(None);
+    let _jobs = manager.durable_mailbox::<Job>(&path).unwrap();
+    let addr = manager.spawn(&mut Smol::Global);
+    assert_eq!(addr.send(Done).await.unwrap(), Vec::<u64>::new());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+struct Guarded;
+
+#[async_trait]
+impl Actor for Guarded {
+    type Stop = ();
+
+    async fn stopped(self) -> Self::Stop {}
+}
+
+struct Public(usize);
+
+impl Message for Public {
+    type Result = usize;
+}
+
+struct Secret;
+
+impl Message for Secret {
+    type Result = ();
+}
+
+impl StreamingMessage for Secret {
+    type Item = ();
+}
+
+struct Answer;
+
+impl Message for Answer {
+    type Result = usize;
+}
+
+#[async_trait]
+impl Handler<Public> for Guarded {
+    async fn handle(&mut self, msg: Public, _: &mut Context<Self>) -> usize {
+        msg.0
+    }
+}
+
+#[async_trait]
+impl Handler<Secret> for Guarded {
+    async fn handle(&mut self, _: Secret, _: &mut Context<Self>) {
+        unreachable!("the secret should have been dropped");
+    }
+}
+
+#[async_trait]
+impl BatchHandler<Secret> for Guarded {
+    async fn handle_batch(&mut self, _: Vec<Secret>, _: &mut Context<Self>) {
+        unreachable!("the secrets should have been dropped");
+    }
+}
+
+#[async_trait]
+impl StreamingHandler<Secret> for Guarded {
+    async fn handle(&mut self, _: Secret, _: Emitter<()>, _: &mut Context<Self>) {
+        unreachable!("the secret should have been dropped");
+    }
+}
+
+#[async_trait]
+impl Handler<Answer> for Guarded {
+    async fn handle(&mut self, _: Answer, _: &mut Context<Self>) -> usize {
+        0
+    }
+}
+
+struct Auth;
+
+#[async_trait]
+impl Middleware for Auth {
+    async fn handle(&self, invocation: &Invocation, mut next: Next<'_>) -> Outcome {
+        match invocation.is::<Secret>() {
+            true => Outcome::dropped(),
+            false => next.run().await,
+        }
+    }
+}
+
+struct Doubling(&'static str, Arc<std::sync::Mutex<Vec<String>>>);
+
+#[async_trait]
+impl Middleware for Doubling {
+    async fn handle(&self, invocation: &Invocation, mut next: Next<'_>) -> Outcome {
+        self.1.lock().unwrap().push(format!(
+            "{} {} {}",
+            self.0,
+            invocation.actor_name(),
+            invocation.message_name()
+        ));
+
+        let mut outcome = next.run().await;
+        if let Some(result) = outcome.downcast_mut::<usize>() {
+            *result *= 2;
+        }
+        outcome
+    }
+}
+
+struct AnswerEverything;
+
+#[async_trait]
+impl Middleware for AnswerEverything {
+    async fn handle(&self, invocation: &Invocation, mut next: Next<'_>) -> Outcome {
+        match invocation.is::<Answer>() {
+            true => Outcome::result(42usize),
+            false => next.run().await,
+        }
+    }
+}
+
+#[smol_potat::test]
+async fn test_middleware() {
+    let log = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let addr = Guarded
+        .create(None)
+        .with_middleware(Auth)
+        .with_middleware(Doubling("outer", log.clone()))
+        .with_middleware(Doubling("inner", log.clone()))
+        .spawn(&mut Smol::Global);
+
+    assert_eq!(addr.send(Public(3)).await, Ok(12));
+    assert_eq!(
+        *log.lock().unwrap(),
+        vec![
+            "outer basic::Guarded basic::Public",
+            "inner basic::Guarded basic::Public"
+        ]
+    );
+
+    // Dropped messages do not reach the rest of the chain
+    assert_eq!(addr.send(Secret).await, Err(Disconnected));
+    assert_eq!(log.lock().unwrap().len(), 2);
+
+    // Batches and streaming messages pass through the middleware too
+    addr.do_send_batch(vec![Secret, Secret]).unwrap();
+    let items: Vec<_> = addr.send_streaming(Secret).collect().await;
+    assert_eq!(items, vec![Err(Disconnected)]);
+    assert_eq!(log.lock().unwrap().len(), 2);
+
+    // Global middleware runs before the actor's own, until it is removed
+    let global = xtra::middleware::add_global(AnswerEverything);
+    assert_eq!(addr.send(Answer).await, Ok(42));
+    assert_eq!(log.lock().unwrap().len(), 2);
+    global.remove();
+    assert_eq!(addr.send(Answer).await, Ok(0));
+}
+
+#[derive(Default)]
+struct Unreliable {
+    attempts: usize,
+}
+
+#[async_trait]
+impl Actor for Unreliable {
+    type Stop = ();
+
+    async fn stopped(self) -> Self::Stop {}
+}
+
+/// Fails until it has been attempted the given number of times.
+#[derive(Clone)]
+struct Flaky(usize);
+
+impl Message for Flaky {
+    type Result = Result<usize, String>;
+}
+
+struct Explode;
+
+impl Message for Explode {
+    type Result = Result<usize, String>;
+}
+
+#[async_trait]
+impl Handler<Flaky> for Unreliable {
+    async fn handle(&mut self, msg: Flaky, _: &mut Context<Self>) -> Result<usize, String> {
+        self.attempts += 1;
+        match self.attempts < msg.0 {
+            true => Err(format!("attempt {} failed", self.attempts)),
+            false => Ok(self.attempts),
+        }
+    }
+}
+
+#[async_trait]
+impl Handler<Explode> for Unreliable {
+    async fn handle(&mut self, _: Explode, _: &mut Context<Self>) -> Result<usize, String> {
+        panic!("exploded");
+    }
+}
+
+struct Retry(usize);
+
+#[async_trait]
+impl Middleware for Retry {
+    async fn handle(&self, _: &Invocation, mut next: Next<'_>) -> Outcome {
+        let message = next.message::<Flaky>().cloned();
+        let mut outcome = next.run().await;
+
+        for _ in 0..self.0 {
+            match (&message, outcome.downcast_ref::<Result<usize, String>>()) {
+                (Some(message), Some(Err(_))) => outcome = next.run_with(message.clone()).await,
+                _ => break,
+            }
+        }
+        outcome
+    }
+}
+
+struct CatchPanics;
+
+#[async_trait]
+impl Middleware for CatchPanics {
+    async fn handle(&self, _: &Invocation, mut next: Next<'_>) -> Outcome {
+        match next.run_catching().await {
+            Ok(outcome) => outcome,
+            Err(payload) => {
+                let message = payload.downcast_ref::<&str>().copied().unwrap_or_default();
+                Outcome::result(Err::<usize, String>(format!("panicked: {}", message)))
+            }
+        }
+    }
+}
+
+#[smol_potat::test]
+async fn test_middleware_retries_and_panics() {
+    let addr = Unreliable::default()
+        .create(None)
+        .with_middleware(CatchPanics)
+        .with_middleware(Retry(2))
+        .spawn(&mut Smol::Global);
+
+    assert_eq!(addr.send(Flaky(3)).await, Ok(Ok(3)));
+    assert_eq!(
+        addr.send(Flaky(7)).await,
+        Ok(Err("attempt 6 failed".to_string()))
+    );
+
+    assert_eq!(
+        addr.send(Explode).await,
+        Ok(Err("panicked: exploded".to_string()))
+    );
+    assert!(addr.is_connected());
+    assert_eq!(addr.send(Flaky(0)).await, Ok(Ok(7)));
+}
+
+#[smol_potat::test]
+async fn test_interceptors() {
+    let sent = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let record = {
+        let sent = sent.clone();
+        move |msg: Public| {
+            sent.lock().unwrap().push(msg.0);
+            Ok(msg)
+        }
+    };
+    let refuse_odd = |msg: Public| match msg.0 % 2 {
+        0 => Ok(msg),
+        _ => Err(SendError::Refused),
+    };
+
+    // The outermost interceptor runs first
+    let addr = Guarded.create(None).spawn(&mut Smol::Global);
+    let channel = Intercepted::new(Box::new(addr.clone()), refuse_odd).with_interceptor(record);
+
+    assert_eq!(channel.send_checked(Public(2)).await, Ok(2));
+    assert_eq!(
+        channel.send_checked(Public(3)).await,
+        Err(SendError::Refused)
+    );
+    assert_eq!(
+        MessageChannel::do_send(&channel, Public(5)),
+        Err(SendError::Refused)
+    );
+    assert!(channel.is_connected());
+
+    // Through a send which resolves to `Disconnected`, a refused message looks like a stopped actor
+    assert_eq!(
+        MessageChannel::send(&channel, Public(9)).await,
+        Err(Disconnected)
+    );
+    assert!(MessageChannel::eq(&channel, &addr));
+
+    let mut sink = channel.sink();
+    sink.send(Public(4)).await.unwrap();
+    assert_eq!(sink.send(Public(7)).await, Err(SendError::Refused));
+
+    assert_eq!(*sent.lock().unwrap(), vec![2, 3, 5, 9, 4, 7]);
+
+    // Only the intercepted address and its clones intercept messages
+    sent.lock().unwrap().clear();
+    let intercepted = addr.clone().with_interceptor(refuse_odd).with_interceptor({
+        let sent = sent.clone();
+        move |msg: Public| {
+            sent.lock().unwrap().push(msg.0);
+            Ok(msg)
+        }
+    });
+    assert_eq!(intercepted.send(Public(2)).await, Ok(2));
+    assert_eq!(intercepted.send(Public(3)).await, Err(Disconnected));
+    assert_eq!(intercepted.try_do_send(Public(5)), Err(SendError::Refused));
+    assert_eq!(
+        intercepted.clone().try_send(Public(7)).await,
+        Err(SendError::Refused)
+    );
+    assert_eq!(addr.send(Public(11)).await, Ok(11));
+
+    let mut sink = intercepted.clone().into_sink();
+    sink.send(Public(6)).await.unwrap();
+    assert_eq!(sink.send(Public(13)).await, Err(SendError::Refused));
+
+    assert_eq!(*sent.lock().unwrap(), vec![2, 3, 5, 7, 6, 13]);
+}
+
+#[cfg(feature = "with-tower-service-0_3")]
+#[smol_potat::test]
+async fn test_tower_service() {
+    use futures_util::future::{self, FutureExt};
+    use std::task::Poll;
+    use tower_service::Service;
+    use xtra::tower::{AddressService, ServiceActor};
+
+    fn poll_ready(service: &mut AddressService<Guarded>) -> Poll<Result<(), Disconnected>> {
+        future::poll_fn(|cx| Poll::Ready(Service::<Public>::poll_ready(service, cx)))
+            .now_or_never()
+            .unwrap()
+    }
+
+    // The service is ready while the mailbox has room, and is woken once the actor makes room
+    let (addr, fut) = Guarded.create(Some(1)).run();
+    let mut service = AddressService::new(addr.clone());
+    assert_eq!(poll_ready(&mut service), Poll::Ready(Ok(())));
+    addr.do_send(Public(1)).unwrap();
+    assert_eq!(poll_ready(&mut service), Poll::Pending);
+
+    smol::spawn(fut).detach();
+    let ready = future::poll_fn(|cx| Service::<Public>::poll_ready(&mut service, cx));
+    assert_eq!(ready.timeout(Duration::from_secs(5)).await, Some(Ok(())));
+    assert_eq!(service.call(Public(2)).await, Ok(2));
+
+    // Readiness waits for the actor's limits, holding the admission until the next call
+    let addr = Guarded
+        .create(None)
+        .with_limits(Limits::new().concurrency(1))
+        .spawn(&mut Smol::Global);
+    let mut first = AddressService::new(addr.clone());
+    let mut second = first.clone();
+    assert_eq!(poll_ready(&mut first), Poll::Ready(Ok(())));
+    assert_eq!(poll_ready(&mut second), Poll::Pending);
+    assert_eq!(first.call(Public(3)).await, Ok(3));
+    let ready = future::poll_fn(|cx| Service::<Public>::poll_ready(&mut second, cx));
+    assert_eq!(ready.timeout(Duration::from_secs(5)).await, Some(Ok(())));
+    assert_eq!(second.call(Public(4)).await, Ok(4));
+
+    // An actor's address can be wrapped again as a service actor
+    let addr = Guarded.create(None).spawn(&mut Smol::Global);
+    let service = ServiceActor::new(AddressService::new(addr))
+        .create(None)
+        .spawn(&mut Smol::Global);
+    assert_eq!(service.call(Public(5)).await, Ok(Ok(5)));
+}
+
+#[smol_potat::test]
+async fn test_limits() {
+    // Messages over the rate are rejected by the sends which cannot wait and delayed by the others
+    let limits = Limits::new().rate(1, Duration::from_millis(100));
+    let addr = Accumulator(0)
+        .create(None)
+        .with_limits(limits)
+        .spawn(&mut Smol::Global);
+    assert_eq!(addr.try_do_send(Inc), Ok(()));
+    assert_eq!(addr.try_do_send(Inc), Err(SendError::RateLimited));
+    assert_eq!(addr.try_send(Inc).await, Err(SendError::RateLimited));
+    assert_eq!(addr.do_send(Inc), Err(SendError::RateLimited));
+    assert_eq!(
+        addr.do_send_blocking_timeout(Inc, Duration::from_millis(1)),
+        Err(SendTimeoutError::Timeout)
+    );
+
+    let start = std::time::Instant::now();
+    assert_eq!(addr.send(Inc).await, Ok(()));
+    assert!(start.elapsed() >= Duration::from_millis(50));
+
+    let blocking = addr.clone();
+    let start = std::time::Instant::now();
+    let sent = std::thread::spawn(move || blocking.do_send_blocking(Inc));
+    assert_eq!(sent.join().unwrap(), Ok(()));
+    assert!(start.elapsed() >= Duration::from_millis(50));
+    assert_eq!(addr.send(Report).await.unwrap().0, 3);
+
+    // Messages count against the concurrency limit until they have been handled
+    let (addr, fut) = Accumulator(0)
+        .create(None)
+        .with_limits(Limits::new().concurrency(1))
+        .run();
+    assert_eq!(addr.try_do_send(Inc), Ok(()));
+    assert_eq!(addr.try_do_send(Inc), Err(SendError::RateLimited));
+
+    // Sinks wait until the message is admitted, while `do_send` rejects it
+    let mut sink = addr.clone().into_sink();
+    assert!(sink.send(Inc).now_or_never().is_none());
+    assert_eq!(addr.do_send(Inc), Err(SendError::RateLimited));
+
+    smol::spawn(fut).detach();
+    sink.send(Inc).await.unwrap();
+    assert_eq!(addr.send(Report).await.unwrap().0, 2);
+    assert_eq!(addr.try_send(Report).await.unwrap().0, 2);
+
+    // `try_do_send` does not wait for room in the mailbox either
+    let (addr, fut) = Accumulator(0).create(Some(1)).run();
+    assert_eq!(addr.try_do_send(Inc), Ok(()));
+    assert_eq!(addr.try_do_send(Inc), Err(SendError::MailboxFull));
+    drop(fut);
+}
+
+struct IncSelf;
+
+impl Message for IncSelf {
+    type Result = Result<(), SendError>;
+}
+
+#[async_trait]
+impl Handler<IncSelf> for Accumulator {
+    async fn handle(&mut self, _: IncSelf, ctx: &mut Context<Self>) -> Result<(), SendError> {
+        ctx.address().unwrap().do_send(Inc)
+    }
+}
+
+#[smol_potat::test]
+async fn test_limited_actor_sends_to_itself() {
+    // The handler's message holds the only permit, so waiting for another would deadlock
+    let addr = Accumulator(0)
+        .create(None)
+        .with_limits(Limits::new().concurrency(1))
+        .spawn(&mut Smol::Global);
+
+    let sent = addr.send(IncSelf).timeout(Duration::from_secs(5)).await;
+    assert_eq!(sent, Some(Ok(Err(SendError::RateLimited))));
+    assert_eq!(addr.send(Report).await.unwrap().0, 0);
+}
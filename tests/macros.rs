@@ -0,0 +1,92 @@
+use xtra::prelude::*;
+use xtra::spawn::Smol;
+
+#[derive(Message)]
+#[result = "Vec<T>"]
+struct Repeat<T: Clone + Send + 'static>(T, usize);
+
+#[derive(Default)]
+struct Counter {
+    count: usize,
+}
+
+#[async_trait::async_trait]
+impl Actor for Counter {
+    type Stop = ();
+
+    async fn stopped(self) -> Self::Stop {}
+}
+
+#[async_trait::async_trait]
+impl<T: Clone + Send + 'static> Handler<Repeat<T>> for Counter {
+    async fn handle(&mut self, msg: Repeat<T>, _: &mut Context<Self>) -> Vec<T> {
+        vec![msg.0; msg.1]
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum CounterError {
+    Stopped,
+}
+
+impl From<xtra::Disconnected> for CounterError {
+    fn from(_: xtra::Disconnected) -> Self {
+        CounterError::Stopped
+    }
+}
+
+#[xtra::handlers(client = CounterClient, error = CounterError)]
+impl Counter {
+    async fn increment(&mut self, by: usize) {
+        self.count += by;
+    }
+
+    async fn get_count(&mut self) -> usize {
+        self.count
+    }
+
+    fn reset(&mut self) -> Self {
+        std::mem::take(self)
+    }
+
+    async fn stop(&mut self, ctx: &mut Context<Self>) {
+        ctx.stop();
+    }
+}
+
+#[smol_potat::test]
+async fn test_derive_message() {
+    let addr = Counter::default().create(None).spawn(&mut Smol::Global);
+    assert_eq!(addr.send(Repeat('a', 3)).await.unwrap(), vec!['a'; 3]);
+}
+
+#[smol_potat::test]
+async fn test_handlers() {
+    let addr = Counter::default().create(None).spawn(&mut Smol::Global);
+
+    addr.send(Increment { by: 2 }).await.unwrap();
+    addr.increment(3).await.unwrap();
+    assert_eq!(addr.get_count().await, Ok(5));
+
+    assert_eq!(addr.reset().await.unwrap().count, 5);
+    assert_eq!(addr.send(GetCount).await, Ok(0));
+
+    let weak = addr.downgrade();
+    weak.stop().await.unwrap();
+    weak.join().await;
+    assert_eq!(addr.get_count().await, Err(xtra::Disconnected));
+}
+
+#[smol_potat::test]
+async fn test_client() {
+    let addr = Counter::default().create(None).spawn(&mut Smol::Global);
+    let client = CounterClient::new(addr.downgrade());
+
+    client.increment(2).await.unwrap();
+    assert_eq!(client.get_count().await, Ok(2));
+    assert_eq!(client.address().get_count().await, Ok(2));
+
+    drop(addr);
+    client.address().join().await;
+    assert_eq!(client.get_count().await, Err(CounterError::Stopped));
+}